@@ -0,0 +1,197 @@
+//! Expansion of `alias Foo := ...` declarations into read-only
+//! pseudo-types, so that they can be used wherever a type can.
+use crate::datastructures::{Link, LinkBuilder, LinkKind, Module, Property, PropertyBuilder, PropertyKind, Type, TypeBuilder};
+use crate::edgeql::{Expr, ShapeElement};
+use crate::inference::{self, InferredType, BASE_OBJECT};
+
+impl Module {
+    /// Infer the type of every alias and register it as a pseudo-type
+    /// that extends the aliased type with the computed elements of its
+    /// shape. Nested shapes with computed elements get pseudo-types of
+    /// their own, named `__<alias>__<element>`. Pseudo-types from an
+    /// earlier call are replaced.
+    pub fn resolve_aliases(&mut self) -> Result<(), String> {
+        self.types.retain(|ty| !ty.alias);
+        for alias in self.aliases.clone() {
+            if self.get_type(&alias.ident).is_some() {
+                return Err(format!("Alias `{}` has the same name as a type.", alias.ident));
+            }
+            let expr = alias.expr()?;
+            let inferred = inference::infer(self, BASE_OBJECT, &expr)
+                .map_err(|e| format!("In alias `{}`: {}", alias.ident, e))?;
+            let mut pseudo = Vec::new();
+            let mut ty = self.shape_type(&alias.ident, &inferred, shape_of(&expr), &mut pseudo)
+                .map_err(|e| format!("In alias `{}`: {}", alias.ident, e))?;
+            ty.annotations = alias.annotations.clone();
+            pseudo.push(ty);
+            self.types.extend(pseudo);
+        }
+        Ok(())
+    }
+
+    fn shape_type(
+        &self,
+        ident: &str,
+        base: &InferredType,
+        elements: &[ShapeElement],
+        pseudo: &mut Vec<Type>) -> Result<Type, String>
+    {
+        let ty = TypeBuilder::default()
+            .ident(ident)
+            .scalar(!base.object)
+            .alias(true)
+            .extends(vec![base.ty.clone()])
+            .build()?;
+        if !base.object {
+            return Ok(ty);
+        }
+        let mut properties: Vec<Property> = Vec::new();
+        let mut links: Vec<Link> = Vec::new();
+        for element in elements {
+            let nested = format!("__{}__{}", ident.trim_start_matches("__"), element.ident);
+            let inferred = match &element.expr {
+                Some(expr) => inference::infer(self, &base.ty, expr)
+                    .map_err(|e| format!("In shape element `{}`: {}", element.ident, e))?,
+                None if element.shape.iter().any(has_computed) => {
+                    let host = self.get_type(&base.ty)
+                        .ok_or_else(|| format!("`{}` is not an object type.", base.ty))?;
+                    let pointer = self.find_pointer(host, &element.ident)
+                        .ok_or_else(|| format!("`{}` has no pointer called `{}`.", base.ty, element.ident))?;
+                    inference::infer_pointer(self, host, pointer)?
+                },
+                // Plain elements only pick pointers that the base already has.
+                None => continue
+            };
+            let shape = element.expr.as_ref().map_or(&element.shape[..], shape_of);
+            let target = if inferred.object && shape.iter().any(has_computed) {
+                let nested_ty = self.shape_type(&nested, &inferred, shape, pseudo)?;
+                pseudo.push(nested_ty);
+                nested
+            } else {
+                inferred.ty.clone()
+            };
+            let required = inferred.cardinality.is_required();
+            let multi = inferred.cardinality.is_multi();
+            let overloaded = element.expr.is_none();
+            if inferred.object {
+                links.push(LinkBuilder::default()
+                    .ident(element.ident.as_str())
+                    .target(target)
+                    .kind(LinkKind::COMPUTABLE)
+                    .readonly(true)
+                    .overloaded(overloaded)
+                    .required(required)
+                    .multi(multi)
+                    .build()?);
+            } else {
+                properties.push(PropertyBuilder::default()
+                    .ident(element.ident.as_str())
+                    .target(target)
+                    .kind(PropertyKind::COMPUTABLE)
+                    .readonly(true)
+                    .overloaded(overloaded)
+                    .required(required)
+                    .multi(multi)
+                    .build()?);
+            }
+        }
+        Ok(Type {
+            properties: if properties.is_empty() { None } else { Some(Box::new(properties)) },
+            links: if links.is_empty() { None } else { Some(Box::new(links)) },
+            ..ty
+        })
+    }
+}
+
+/// The outermost shape of `expr`, looking through `SELECT`.
+fn shape_of(expr: &Expr) -> &[ShapeElement] {
+    match expr {
+        Expr::Shape { elements, .. } => elements,
+        Expr::Select { expr, .. } => shape_of(expr),
+        _ => &[]
+    }
+}
+
+fn has_computed(element: &ShapeElement) -> bool {
+    element.expr.is_some() || element.shape.iter().any(has_computed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{AliasBuilder, AliasExprBuilder, LinkBuilder, Module, Pointer, PropertyBuilder, TypeBuilder};
+    use crate::fixtures;
+
+    fn settings() -> Module {
+        let setting = TypeBuilder::default()
+            .ident("Setting")
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("name").target("str").required(true).build().unwrap(),
+                PropertyBuilder::default().ident("value").target("str").required(true).build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let group = TypeBuilder::default()
+            .ident("UserGroup")
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("name").target("str").required(true).build().unwrap()
+            ]))
+            .links(Box::new(vec![
+                LinkBuilder::default().ident("settings").target("Setting").multi(true).build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let alias = |ident: &str, sdl: &str| {
+            AliasBuilder::default()
+                .ident(ident)
+                .alias_expr(AliasExprBuilder::default().sdl(sdl).build().unwrap())
+                .build()
+                .unwrap()
+        };
+        let mut module = fixtures::module(vec![setting, group]);
+        module.aliases = vec![
+            alias("SettingAlias", "alias SettingAlias := Setting {
+                of_group := .<settings[IS UserGroup]
+            };"),
+            alias("SettingAliasAugmented", "alias SettingAliasAugmented := Setting {
+                of_group := .<settings[IS UserGroup] {
+                    name_upper := str_upper(.name)
+                }
+            };"),
+            alias("LongSettings", "alias LongSettings := (SELECT Setting FILTER len(.value) > 10)")
+        ];
+        module
+    }
+
+    #[test]
+    fn aliases_become_pseudo_types() {
+        let mut module = settings();
+        module.resolve_aliases().unwrap();
+
+        let alias = module.get_type("SettingAlias").unwrap();
+        assert!(alias.alias);
+        assert_eq!(alias.extends.as_deref(), Some(&["Setting".to_string()][..]));
+        let of_group = module.find_pointer(alias, "of_group").unwrap();
+        assert!(matches!(of_group, Pointer::Link(link) if link.readonly && link.multi));
+        assert_eq!(of_group.target(), Some("UserGroup"));
+        assert_eq!(module.find_pointer(alias, "value").unwrap().target(), Some("str"));
+
+        let augmented = module.get_type("SettingAliasAugmented").unwrap();
+        let of_group = module.find_pointer(augmented, "of_group").unwrap();
+        assert_eq!(of_group.target(), Some("__SettingAliasAugmented__of_group"));
+        let nested = module.get_type("__SettingAliasAugmented__of_group").unwrap();
+        assert_eq!(module.find_pointer(nested, "name_upper").unwrap().target(), Some("str"));
+
+        let long = module.get_type("LongSettings").unwrap();
+        assert!(long.alias && long.properties.is_none());
+
+        // Resolving again replaces the pseudo-types rather than clashing with them.
+        assert!(module.resolve_aliases().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_shapes() {
+        let mut module = settings();
+        module.aliases[0].alias_expr.sdl = Some("alias SettingAlias := Setting { of_group := .<owner }".to_string());
+        assert!(module.resolve_aliases().is_err());
+    }
+}
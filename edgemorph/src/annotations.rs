@@ -0,0 +1,249 @@
+//! Annotation declarations, and the checks that tie every
+//! `annotation foo := '...'` to one of them.
+use crate::datastructures::{list, AbstractAnnotation, Annotation, Constraint, Module};
+use crate::functions::signature;
+use crate::inheritance::merge_annotations;
+
+/// The annotations that EdgeDB declares in `std`.
+pub fn std_annotations() -> Vec<AbstractAnnotation> {
+    ["title", "description", "deprecated"].iter()
+        .map(|ident| AbstractAnnotation { ident: ident.to_string(), inheritable: false })
+        .collect()
+}
+
+impl Module {
+    /// Find the declaration of annotation `ident`, looking in
+    /// this module first and then in `std`.
+    pub fn resolve_annotation(&self, ident: &str) -> Option<AbstractAnnotation> {
+        if let Some(declared) = self.get_annotation(ident) {
+            return Some(declared.clone());
+        }
+        let ident = ident.trim_start_matches("std::");
+        std_annotations().into_iter().find(|declared| declared.ident == ident)
+    }
+
+    /// Verify that every annotation in the module refers to a declared
+    /// annotation, and that no item sets the same annotation twice.
+    pub fn validate_annotations(&self) -> Result<(), String> {
+        match self.annotation_issues().into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the annotations in the module, with the item
+    /// it is about.
+    pub(crate) fn annotation_issues(&self) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for (i, declared) in self.annotations.iter().enumerate() {
+            if self.annotations[..i].iter().any(|other| other.ident == declared.ident) {
+                let issue = format!("Annotation `{}` is declared more than once.", declared.ident);
+                issues.push((declared.ident.clone(), issue));
+            }
+        }
+        for constraint in self.constraints.iter() {
+            let owner = format!("constraint `{}`", constraint.ident);
+            self.check_constraint_annotations(&constraint.ident, &owner, constraint, &mut issues);
+        }
+        for prop in self.properties.iter() {
+            let owner = format!("property `{}`", prop.ident);
+            self.check_annotations(&prop.ident, &owner, list(&prop.annotations), &mut issues);
+        }
+        for link in self.links.iter() {
+            let owner = format!("link `{}`", link.ident);
+            self.check_annotations(&link.ident, &owner, list(&link.annotations), &mut issues);
+        }
+        for alias in self.aliases.iter() {
+            let owner = format!("alias `{}`", alias.ident);
+            self.check_annotations(&alias.ident, &owner, list(&alias.annotations), &mut issues);
+        }
+        for func in self.functions.iter() {
+            let owner = format!("function `{}`", signature(func));
+            self.check_annotations(&func.ident, &owner, list(&func.annotations), &mut issues);
+        }
+        for ty in self.types.iter() {
+            let owner = format!("`{}`", ty.ident);
+            self.check_annotations(&ty.ident, &owner, list(&ty.annotations), &mut issues);
+            for prop in list(&ty.properties) {
+                let subject = format!("{}.{}", ty.ident, prop.ident);
+                let owner = format!("`{}`", subject);
+                self.check_annotations(&subject, &owner, list(&prop.annotations), &mut issues);
+                for constraint in list(&prop.constraints) {
+                    self.check_constraint_annotations(&subject, &owner, constraint, &mut issues);
+                }
+            }
+            for link in list(&ty.links) {
+                let subject = format!("{}.{}", ty.ident, link.ident);
+                let owner = format!("`{}`", subject);
+                self.check_annotations(&subject, &owner, list(&link.annotations), &mut issues);
+                for constraint in list(&link.constraints) {
+                    self.check_constraint_annotations(&subject, &owner, constraint, &mut issues);
+                }
+                for (ident, prop) in link.link_properties() {
+                    let subject = format!("{}.{}{}", ty.ident, link.ident, ident);
+                    let owner = format!("`{}`", subject);
+                    self.check_annotations(&subject, &owner, list(&prop.annotations), &mut issues);
+                }
+            }
+            for constraint in list(&ty.constraints) {
+                self.check_constraint_annotations(&ty.ident, &owner, constraint, &mut issues);
+            }
+            for index in list(&ty.indices) {
+                let owner = format!("index on ({}) of `{}`", index.expr.ident, ty.ident);
+                self.check_annotations(&ty.ident, &owner, list(&index.annotations), &mut issues);
+            }
+        }
+        issues
+    }
+
+    /// Copy inheritable annotations from every supertype down to its
+    /// subtypes, and from a supertype's pointers down to the pointers
+    /// that override them. Annotations set on the subtype win.
+    pub fn propagate_annotations(&mut self) {
+        let module = self.clone();
+        let inheritable = |annotations: &Option<Box<Vec<Annotation>>>| -> Vec<Annotation> {
+            annotations.iter()
+                .flat_map(|annos| annos.iter())
+                .filter(|anno| matches!(module.resolve_annotation(&anno.ident), Some(d) if d.inheritable))
+                .cloned()
+                .collect()
+        };
+        for (t, ty) in module.types.iter().enumerate() {
+            for base in module.ancestors(ty) {
+                let target = &mut self.types[t];
+                let inherited = inheritable(&base.annotations);
+                if !inherited.is_empty() {
+                    merge_annotations(target.annotations.get_or_insert_with(Default::default), &inherited);
+                }
+                for base_prop in base.properties.iter().flat_map(|props| props.iter()) {
+                    let inherited = inheritable(&base_prop.annotations);
+                    let prop = target.properties.iter_mut()
+                        .flat_map(|props| props.iter_mut())
+                        .find(|prop| prop.ident == base_prop.ident);
+                    if let Some(prop) = prop.filter(|_| !inherited.is_empty()) {
+                        merge_annotations(prop.annotations.get_or_insert_with(Default::default), &inherited);
+                    }
+                }
+                for base_link in base.links.iter().flat_map(|links| links.iter()) {
+                    let inherited = inheritable(&base_link.annotations);
+                    let link = target.links.iter_mut()
+                        .flat_map(|links| links.iter_mut())
+                        .find(|link| link.ident == base_link.ident);
+                    if let Some(link) = link.filter(|_| !inherited.is_empty()) {
+                        merge_annotations(link.annotations.get_or_insert_with(Default::default), &inherited);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the annotations that `owner`, described as such in
+    /// messages, sets; issues are reported on `subject`.
+    fn check_annotations<'a>(
+        &self,
+        subject: &str,
+        owner: &str,
+        annotations: impl IntoIterator<Item = &'a Annotation>,
+        issues: &mut Vec<(String, String)>)
+    {
+        let mut seen: Vec<&str> = Vec::new();
+        for annotation in annotations {
+            let ident = annotation.ident.trim_start_matches("std::");
+            if seen.contains(&ident) {
+                let issue = format!("Annotation `{}` is set more than once on {}.", annotation.ident, owner);
+                issues.push((subject.to_string(), issue));
+            } else if self.resolve_annotation(&annotation.ident).is_none() {
+                let issue = format!("Annotation `{}` on {} is not declared.", annotation.ident, owner);
+                issues.push((subject.to_string(), issue));
+            }
+            seen.push(ident);
+        }
+    }
+
+    fn check_constraint_annotations(
+        &self,
+        subject: &str,
+        owner: &str,
+        constraint: &Constraint,
+        issues: &mut Vec<(String, String)>)
+    {
+        let annotations = list(&constraint.subcommands)
+            .iter()
+            .filter_map(|subcommand| subcommand.annotation.as_deref());
+        let owner = format!("constraint `{}` of {}", constraint.ident, owner);
+        self.check_annotations(subject, &owner, annotations, issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{AbstractAnnotationBuilder, AnnotationBuilder, Module, PropertyBuilder, TypeBuilder};
+    use crate::functions::parse_signature;
+
+    fn annotation(ident: &str, value: &str) -> crate::datastructures::Annotation {
+        AnnotationBuilder::default().ident(ident).value(value).build().unwrap()
+    }
+
+    fn library() -> Module {
+        let owner = AbstractAnnotationBuilder::default().ident("owner").inheritable(true).build().unwrap();
+        let media = TypeBuilder::default()
+            .ident("Media")
+            .abs(true)
+            .annotations(Box::new(vec![annotation("owner", "catalog team"), annotation("title", "Media")]))
+            .properties(Box::new(vec![
+                PropertyBuilder::default()
+                    .ident("name")
+                    .target("str")
+                    .annotations(Box::new(vec![annotation("owner", "search team")]))
+                    .build()
+                    .unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let book = TypeBuilder::default()
+            .ident("Book")
+            .extends(vec!["Media".to_string()])
+            .properties(Box::new(vec![PropertyBuilder::default().ident("name").target("str").build().unwrap()]))
+            .build()
+            .unwrap();
+        let mut module = Module::new("default");
+        module.annotations.push(owner);
+        module.types = vec![media, book];
+        module
+    }
+
+    #[test]
+    fn checks_annotation_declarations() {
+        let mut module = library();
+        assert!(module.validate_annotations().is_ok());
+
+        module.types[1].annotations = Some(Box::new(vec![annotation("std::description", "A book")]));
+        assert!(module.validate_annotations().is_ok());
+
+        module.types[1].annotations = Some(Box::new(vec![annotation("summary", "A book")]));
+        assert!(module.validate_annotations().is_err());
+        module.types[1].annotations = None;
+
+        let mut lower = parse_signature("lower(s: str) -> str").unwrap();
+        lower.annotations = Some(Box::new(vec![annotation("summary", "Lowercase")]));
+        module.functions.push(lower);
+        assert_eq!(
+            module.validate_annotations().unwrap_err(),
+            "Annotation `summary` on function `lower(s: str) -> str` is not declared."
+        );
+    }
+
+    #[test]
+    fn propagates_inheritable_annotations() {
+        let mut module = library();
+        module.propagate_annotations();
+
+        let book = module.get_type("Book").unwrap();
+        let annos = book.annotations.as_ref().unwrap();
+        assert_eq!(annos.len(), 1);
+        assert_eq!(annos[0].value, "catalog team");
+
+        let name = &book.properties.as_ref().unwrap()[0];
+        assert_eq!(name.annotations.as_ref().unwrap()[0].value, "search team");
+    }
+}
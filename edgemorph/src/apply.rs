@@ -0,0 +1,322 @@
+//! Replaying changes on a schema, the other way round from `diff`:
+//! `apply(old, &diff(old, new))` declares the same schema as `new`.
+//!
+//! Items are found the way `diff` matched them, by name, and
+//! constraints, indices and functions by their keys. A list that loses
+//! its last item is left out altogether, as SDL leaves out the lists
+//! of items that declare none.
+use crate::datastructures::{
+    Annotation, Constraint, Expression, Link, LinkKind, Module, Property, PropertyKind, Type
+};
+use crate::diff::{constraint_key, overload_key, Change, Location};
+use crate::fingerprint::canonical_expression;
+use crate::rename::{self, Rename};
+
+/// `module` with `changes` made to it, in order. Fails on the first
+/// change whose item is not in the module. Pseudo-types are derived
+/// again from the aliases when the module had any.
+pub fn apply(module: &Module, changes: &[Change]) -> Result<Module, String> {
+    let mut applied = module.clone();
+    for change in changes {
+        applied = match change {
+            Change::TypeRenamed { from, to, .. } => {
+                rename::apply(&applied, &[Rename::Type { from: from.clone(), to: to.clone() }])?
+            },
+            Change::PointerRenamed { at, to } => {
+                rename::apply(&applied, &[Rename::Pointer { at: at.clone(), to: to.clone() }])?
+            },
+            _ => {
+                if apply_change(&mut applied, change).is_none() {
+                    return Err(format!("Cannot {}: it is not in `{}`.", change, module.ident));
+                }
+                applied
+            }
+        };
+    }
+    if applied.types.iter().any(|ty| ty.alias) {
+        applied.resolve_aliases()?;
+    }
+    Ok(applied)
+}
+
+/// Makes one change, or `None` if its item is missing.
+fn apply_change(module: &mut Module, change: &Change) -> Option<()> {
+    match change {
+        Change::TypeAdded(ty) => module.types.push(ty.clone()),
+        Change::TypeRemoved(ty) => remove(&mut module.types, |other| other.ident == ty.ident)?,
+        Change::TypeRenamed { .. } | Change::PointerRenamed { .. } => unreachable!("renames are applied by `apply`"),
+        Change::AbstractChanged { ty, abs } => type_mut(module, ty)?.abs = *abs,
+        Change::FinalChanged { ty, is_final } => type_mut(module, ty)?.is_final = *is_final,
+        Change::BasesChanged { ty, new, .. } => {
+            type_mut(module, ty)?.extends = if new.is_empty() { None } else { Some(new.clone()) };
+        },
+        Change::PropertyAdded { at, prop } => push(properties(module, at)?, prop.clone()),
+        Change::PropertyRemoved { at, prop } => {
+            remove_listed(properties(module, at)?, |other| other.ident == prop.ident)?
+        },
+        Change::LinkAdded { at, link } => push(&mut type_mut(module, &at.ty)?.links, link.clone()),
+        Change::LinkRemoved { at, link } => {
+            remove_listed(&mut type_mut(module, &at.ty)?.links, |other| other.ident == link.ident)?
+        },
+        Change::CardinalityChanged { at, multi } => *pointer(module, at)?.multi = *multi,
+        Change::RequiredChanged { at, required } => *pointer(module, at)?.required = *required,
+        Change::ReadonlyChanged { at, readonly } => *pointer(module, at)?.readonly = *readonly,
+        Change::TargetChanged { at, new, .. } => *pointer(module, at)?.target = new.clone(),
+        Change::ExprChanged { at, new, .. } => {
+            let mut pointer = pointer(module, at)?;
+            *pointer.expr = new.clone();
+            pointer.kind.computable(new.is_some());
+        },
+        Change::DefaultChanged { at, new, .. } => *pointer(module, at)?.default = new.clone(),
+        Change::ConstraintAdded { at, constraint } => push(constraints(module, at)?, constraint.clone()),
+        Change::ConstraintRemoved { at, constraint } => {
+            let key = constraint_key(constraint);
+            remove_listed(constraints(module, at)?, |other| constraint_key(other) == key)?
+        },
+        Change::IndexAdded { ty, index } => push(&mut type_mut(module, ty)?.indices, index.clone()),
+        Change::IndexDropped { ty, index } => {
+            let key = canonical_expression(&index.expr);
+            remove_listed(&mut type_mut(module, ty)?.indices, |other| canonical_expression(&other.expr) == key)?
+        },
+        Change::AnnotationAdded { at, annotation } => push(annotations(module, at)?, annotation.clone()),
+        Change::AnnotationRemoved { at, annotation } => {
+            let ident = annotation_ident(annotation);
+            remove_listed(annotations(module, at)?, |other| annotation_ident(other) == ident)?
+        },
+        Change::AnnotationChanged { at, ident, new, .. } => {
+            let ident = ident.trim_start_matches("std::");
+            find_mut(annotations(module, at)?, |other| annotation_ident(other) == ident)?.value = new.clone();
+        },
+        Change::AbstractConstraintAdded(c) => module.constraints.push(c.clone()),
+        Change::AbstractConstraintRemoved(c) => remove(&mut module.constraints, |other| other.ident == c.ident)?,
+        Change::AbstractPropertyAdded(p) => module.properties.push(p.clone()),
+        Change::AbstractPropertyRemoved(p) => remove(&mut module.properties, |other| other.ident == p.ident)?,
+        Change::AbstractLinkAdded(l) => module.links.push(l.clone()),
+        Change::AbstractLinkRemoved(l) => remove(&mut module.links, |other| other.ident == l.ident)?,
+        Change::AnnotationDeclared(a) => module.annotations.push(a.clone()),
+        Change::AnnotationUndeclared(a) => remove(&mut module.annotations, |other| other.ident == a.ident)?,
+        Change::AliasAdded(alias) => module.aliases.push(alias.clone()),
+        Change::AliasRemoved(alias) => remove(&mut module.aliases, |other| other.ident == alias.ident)?,
+        Change::AliasChanged { new, .. } => {
+            *module.aliases.iter_mut().find(|other| other.ident == new.ident)? = new.clone();
+        },
+        Change::FunctionAdded(func) => module.functions.push(func.clone()),
+        Change::FunctionRemoved(func) => {
+            let key = overload_key(func);
+            remove(&mut module.functions, |other| overload_key(other) == key)?
+        },
+        Change::FunctionChanged { old, new } => {
+            let key = overload_key(old);
+            *module.functions.iter_mut().find(|other| overload_key(other) == key)? = new.clone();
+        }
+    }
+    Some(())
+}
+
+/// The parts of a property or link that can change in place.
+#[allow(clippy::box_collection)]
+struct Fields<'a> {
+    multi:       &'a mut bool,
+    required:    &'a mut bool,
+    readonly:    &'a mut bool,
+    target:      &'a mut Option<String>,
+    expr:        &'a mut Option<Expression>,
+    default:     &'a mut Option<Expression>,
+    constraints: &'a mut Option<Box<Vec<Constraint>>>,
+    annotations: &'a mut Option<Box<Vec<Annotation>>>,
+    kind:        Kind<'a>
+}
+
+enum Kind<'a> {
+    Property(&'a mut PropertyKind),
+    Link(&'a mut LinkKind)
+}
+
+impl<'a> Kind<'a> {
+    /// A pointer that gains or loses its expression
+    /// becomes computable or stored.
+    fn computable(&mut self, computable: bool) {
+        match self {
+            Kind::Property(kind) => **kind = if computable { PropertyKind::COMPUTABLE } else { PropertyKind::CONCRETE },
+            Kind::Link(kind) => **kind = if computable { LinkKind::COMPUTABLE } else { LinkKind::CONCRETE }
+        }
+    }
+}
+
+impl<'a> From<&'a mut Property> for Fields<'a> {
+    fn from(prop: &'a mut Property) -> Fields<'a> {
+        Fields {
+            multi: &mut prop.multi,
+            required: &mut prop.required,
+            readonly: &mut prop.readonly,
+            target: &mut prop.target,
+            expr: &mut prop.expr,
+            default: &mut prop.default,
+            constraints: &mut prop.constraints,
+            annotations: &mut prop.annotations,
+            kind: Kind::Property(&mut prop.kind)
+        }
+    }
+}
+
+impl<'a> From<&'a mut Link> for Fields<'a> {
+    fn from(link: &'a mut Link) -> Fields<'a> {
+        Fields {
+            multi: &mut link.multi,
+            required: &mut link.required,
+            readonly: &mut link.readonly,
+            target: &mut link.target,
+            expr: &mut link.expr,
+            default: &mut link.default,
+            constraints: &mut link.constraints,
+            annotations: &mut link.annotations,
+            kind: Kind::Link(&mut link.kind)
+        }
+    }
+}
+
+fn type_mut<'a>(module: &'a mut Module, ident: &str) -> Option<&'a mut Type> {
+    module.types.iter_mut().find(|ty| ty.ident == ident)
+}
+
+#[allow(clippy::box_collection)]
+fn find_mut<T>(items: &mut Option<Box<Vec<T>>>, found: impl Fn(&T) -> bool) -> Option<&mut T> {
+    items.iter_mut().flat_map(|items| items.iter_mut()).find(|item| found(item))
+}
+
+/// The pointer that `at` names.
+fn pointer<'a>(module: &'a mut Module, at: &Location) -> Option<Fields<'a>> {
+    let ty = type_mut(module, &at.ty)?;
+    let ident = at.pointer.as_deref()?;
+    if !at.is_link {
+        return find_mut(&mut ty.properties, |prop| prop.ident == ident).map(Fields::from);
+    }
+    let link = find_mut(&mut ty.links, |link| link.ident == ident)?;
+    match &at.link_property {
+        Some(prop) => find_mut(&mut link.properties, |other| &other.ident == prop).map(Fields::from),
+        None => Some(Fields::from(link))
+    }
+}
+
+/// The properties of the type, or of the link when `at` names a
+/// link property.
+#[allow(clippy::box_collection)]
+fn properties<'a>(module: &'a mut Module, at: &Location) -> Option<&'a mut Option<Box<Vec<Property>>>> {
+    let ty = type_mut(module, &at.ty)?;
+    match (&at.pointer, &at.link_property) {
+        (Some(link), Some(_)) => Some(&mut find_mut(&mut ty.links, |other| &other.ident == link)?.properties),
+        _ => Some(&mut ty.properties)
+    }
+}
+
+#[allow(clippy::box_collection)]
+fn constraints<'a>(module: &'a mut Module, at: &Location) -> Option<&'a mut Option<Box<Vec<Constraint>>>> {
+    match at.pointer {
+        Some(_) => Some(pointer(module, at)?.constraints),
+        None => Some(&mut type_mut(module, &at.ty)?.constraints)
+    }
+}
+
+#[allow(clippy::box_collection)]
+fn annotations<'a>(module: &'a mut Module, at: &Location) -> Option<&'a mut Option<Box<Vec<Annotation>>>> {
+    match at.pointer {
+        Some(_) => Some(pointer(module, at)?.annotations),
+        None => Some(&mut type_mut(module, &at.ty)?.annotations)
+    }
+}
+
+/// Annotations are matched with or without their `std::` prefix.
+fn annotation_ident(annotation: &Annotation) -> &str {
+    annotation.ident.trim_start_matches("std::")
+}
+
+#[allow(clippy::box_collection)]
+fn push<T>(items: &mut Option<Box<Vec<T>>>, item: T) {
+    items.get_or_insert_with(Default::default).push(item);
+}
+
+fn remove<T>(items: &mut Vec<T>, found: impl Fn(&T) -> bool) -> Option<()> {
+    let index = items.iter().position(found)?;
+    items.remove(index);
+    Some(())
+}
+
+/// `remove`, dropping the list when its last item goes.
+#[allow(clippy::box_collection)]
+fn remove_listed<T>(items: &mut Option<Box<Vec<T>>>, found: impl Fn(&T) -> bool) -> Option<()> {
+    remove(items.as_mut()?, found)?;
+    if items.as_ref().map(|items| items.is_empty()) == Some(true) {
+        *items = None;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{AnnotationBuilder, ConstraintBuilder, Expression, PropertyBuilder, TypeBuilder};
+    use crate::diff::{diff, Location};
+    use crate::fixtures::catalog;
+    use crate::rename::{diff_with_renames, Rename};
+    use super::apply;
+
+    #[test]
+    fn replays_a_diff() {
+        let old = catalog();
+        let mut new = catalog();
+        {
+            let item = &mut new.types[0];
+            let props = item.properties.as_mut().unwrap();
+            props.retain(|prop| prop.ident != "price");
+            props[0].multi = true;
+            props[0].constraints = Some(Box::new(vec![
+                ConstraintBuilder::default()
+                    .ident("max_len_value")
+                    .args(Box::new(vec![Expression::new("80")]))
+                    .build()
+                    .unwrap()
+            ]));
+            props.push(PropertyBuilder::default().ident("shout").computable("str_upper(.name)").build().unwrap());
+            let related = &mut item.links.as_mut().unwrap()[0];
+            related.target = Some("Product".to_string());
+            related.properties = None;
+            item.indices = None;
+            item.annotations = Some(Box::new(vec![
+                AnnotationBuilder::default().ident("title").value("An item").build().unwrap()
+            ]));
+        }
+        new.types.push(TypeBuilder::default().ident("Product").build().unwrap());
+
+        for (from, to) in &[(&old, &new), (&new, &old)] {
+            let applied = apply(from, &diff(from, to)).unwrap();
+            assert_eq!(applied.fingerprint(), to.fingerprint(), "{:?}", diff(&applied, to));
+        }
+    }
+
+    #[test]
+    fn replays_renames() {
+        let old = catalog();
+        let mut new = catalog();
+        new.types[0].ident = "Product".to_string();
+        new.types[0].links.as_mut().unwrap()[0].target = Some("Product".to_string());
+        let props = new.types[0].properties.as_mut().unwrap();
+        props.iter_mut().find(|prop| prop.ident == "price").unwrap().ident = "cost".to_string();
+        let at = Location::ty("Product").property("price");
+        let renames = vec![
+            Rename::Type { from: "Item".to_string(), to: "Product".to_string() },
+            Rename::Pointer { at, to: "cost".to_string() }
+        ];
+        let changes = diff_with_renames(&old, &new, &renames).unwrap();
+        assert_eq!(apply(&old, &changes).unwrap().fingerprint(), new.fingerprint());
+    }
+
+    #[test]
+    fn fails_on_missing_items() {
+        let mut new = catalog();
+        new.types[0].properties.as_mut().unwrap().pop();
+        let changes = diff(&catalog(), &new);
+        let mut old = catalog();
+        old.types[0].properties.as_mut().unwrap().pop();
+        assert_eq!(apply(&old, &changes).unwrap_err(),
+                   "Cannot remove property `Item.label`: it is not in `default`.");
+    }
+}
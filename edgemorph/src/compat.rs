@@ -0,0 +1,159 @@
+//! Whether client code generated from a schema keeps working once a
+//! change is installed.
+//!
+//! Generated Rust and Python clients name every type and pointer, read
+//! single pointers as plain values and multi pointers as lists,
+//! optional pointers as `Option`s, and write every writable pointer.
+//! A change is breaking when it invalidates any of that, e.g. dropping
+//! a property, making a single link multi, narrowing a scalar type or
+//! constraining the values that clients write.
+use std::fmt;
+use crate::diff::Change;
+
+/// How a change affects clients generated before it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compatibility {
+    Compatible,
+    /// Generated clients break, for the given reason.
+    Breaking(&'static str)
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compatibility::Compatible => f.write_str("compatible"),
+            Compatibility::Breaking(reason) => write!(f, "breaking: {}", reason)
+        }
+    }
+}
+
+/// Scalar types and the ones that hold every value they hold.
+const WIDENINGS: &[(&str, &[&str])] = &[
+    ("int16", &["int32", "int64", "bigint", "float32", "float64", "decimal"]),
+    ("int32", &["int64", "bigint", "float64", "decimal"]),
+    ("int64", &["bigint", "decimal"]),
+    ("bigint", &["decimal"]),
+    ("float32", &["float64"]),
+    ("cal::local_date", &["cal::local_datetime"])
+];
+
+/// Whether every value of scalar type `old` is a value of `new`.
+fn widens(old: &str, new: &str) -> bool {
+    let (old, new) = (old.trim_start_matches("std::"), new.trim_start_matches("std::"));
+    old == new || WIDENINGS.iter().any(|(from, to)| *from == old && to.contains(&new))
+}
+
+/// Classify one change for clients generated before it.
+pub fn classify(change: &Change) -> Compatibility {
+    use Compatibility::{Breaking, Compatible};
+    match change {
+        // No client code was generated for them.
+        Change::TypeAdded(ty) | Change::TypeRemoved(ty) if !ty.is_generated() => Compatible,
+        Change::TypeRemoved(_) => Breaking("clients refer to the type"),
+        Change::TypeRenamed { .. } => Breaking("clients refer to the type by its old name"),
+        Change::PointerRenamed { .. } => Breaking("clients refer to the pointer by its old name"),
+        Change::PropertyRemoved { .. } => Breaking("clients read and write the property"),
+        Change::LinkRemoved { .. } => Breaking("clients read and write the link"),
+        Change::PropertyAdded { prop, .. } if prop.required && !prop.is_computable() && prop.default.is_none() => {
+            Breaking("clients do not set the new required property")
+        },
+        Change::LinkAdded { link, .. } if link.required && !link.is_computable() && link.default.is_none() => {
+            Breaking("clients do not set the new required link")
+        },
+        Change::CardinalityChanged { multi: true, .. } => Breaking("clients read a single value, not a set"),
+        Change::CardinalityChanged { multi: false, .. } => Breaking("clients read a set, not a single value"),
+        Change::RequiredChanged { required: false, .. } => Breaking("clients expect a value to always be set"),
+        Change::RequiredChanged { required: true, .. } => Breaking("clients may leave the pointer unset"),
+        Change::ReadonlyChanged { readonly: true, .. } => Breaking("clients write the pointer"),
+        Change::TargetChanged { at, .. } if at.is_link && at.link_property.is_none() => {
+            Breaking("clients read objects of the old target type")
+        },
+        Change::TargetChanged { old: Some(old), new: Some(new), .. } if widens(old, new) => Compatible,
+        Change::TargetChanged { .. } => Breaking("the new type does not hold every value of the old one"),
+        Change::ConstraintAdded { at, .. } if at.scalar => {
+            Breaking("clients may write values of the type that the new constraint rejects")
+        },
+        Change::ConstraintAdded { .. } => Breaking("clients may write values that the new constraint rejects"),
+        Change::ExprChanged { old: None, new: Some(_), .. } => {
+            Breaking("clients write the pointer, which is computed now")
+        },
+        Change::AbstractChanged { abs: true, .. } => Breaking("clients create objects of the type"),
+        Change::BasesChanged { old, new, .. } if old.iter().any(|base| !new.contains(base)) => {
+            Breaking("clients use the type as one of the bases it dropped")
+        },
+        Change::AliasRemoved(_) => Breaking("clients query the alias"),
+        Change::FunctionRemoved(_) => Breaking("clients call the function"),
+        Change::FunctionChanged { old, new }
+            if old.return_type != new.return_type || old.return_typemod != new.return_typemod =>
+        {
+            Breaking("clients read the function's old return type")
+        },
+        _ => Compatible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{ConstraintBuilder, Expression, Module, Property, TypeBuilder};
+    use crate::diff::{diff, Change};
+    use crate::fixtures::{prop, users};
+    use super::{classify, Compatibility};
+
+    fn user(age: &str, multi: bool) -> Module {
+        let tags = Property { multi, ..prop("tags", "str") };
+        users(vec![prop("age", age), tags])
+    }
+
+    #[test]
+    fn widening_a_scalar_is_compatible() {
+        let changes = diff(&user("int32", false), &user("int64", false));
+        assert_eq!(changes.iter().map(classify).collect::<Vec<_>>(), vec![Compatibility::Compatible]);
+    }
+
+    #[test]
+    fn narrowing_and_multi_pointers_break_clients() {
+        let changes = diff(&user("int64", false), &user("int16", true));
+        let classes: Vec<String> = changes.iter().map(|change| format!("{}: {}", change, classify(change))).collect();
+        assert_eq!(classes, vec![
+            "change target of `User.age` from `int64` to `int16`: \
+             breaking: the new type does not hold every value of the old one",
+            "make `User.tags` multi: breaking: clients read a single value, not a set"
+        ]);
+    }
+
+    #[test]
+    fn new_constraints_break_clients() {
+        let old = user("int64", false);
+        let mut new = user("int64", false);
+        let max_value = |value: &str| ConstraintBuilder::default()
+            .ident("max_value")
+            .args(Box::new(vec![Expression::new(value)]))
+            .build()
+            .unwrap();
+        new.types[0].properties.as_mut().unwrap()[0].constraints = Some(Box::new(vec![max_value("150")]));
+        let rating = TypeBuilder::default().ident("Rating").scalar(true).extends(vec!["int16".to_string()]).build();
+        new.types.push(rating.unwrap());
+        let mut constrained = new.clone();
+        constrained.types[1].constraints = Some(Box::new(vec![max_value("5")]));
+
+        let changes = diff(&old, &new).into_iter().chain(diff(&new, &constrained)).collect::<Vec<_>>();
+        let classes: Vec<String> = changes.iter().map(|change| format!("{}: {}", change, classify(change))).collect();
+        assert_eq!(classes, vec![
+            "add type `Rating`: compatible",
+            "add constraint `max_value(150)` to `User.age`: \
+             breaking: clients may write values that the new constraint rejects",
+            "add constraint `max_value(5)` to `Rating`: \
+             breaking: clients may write values of the type that the new constraint rejects"
+        ]);
+        let removed = changes.iter().skip(1).filter_map(Change::inverse).map(|change| classify(&change));
+        assert!(removed.into_iter().all(|class| class == Compatibility::Compatible));
+    }
+
+    #[test]
+    fn internal_types_do_not_break_clients() {
+        let internal = TypeBuilder::default().ident("Migration").is_internal(true).build().unwrap();
+        assert_eq!(classify(&Change::TypeRemoved(internal)), Compatibility::Compatible);
+        let ty = user("int64", false).types.remove(0);
+        assert!(matches!(classify(&Change::TypeRemoved(ty)), Compatibility::Breaking(_)));
+    }
+}
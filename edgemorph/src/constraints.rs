@@ -0,0 +1,555 @@
+//! The catalog of constraints that EdgeDB ships in `std`, and the
+//! checks that tie a concrete constraint to the value it constrains.
+use crate::datastructures::{
+    list, Constraint, Expression, Module, Parameter, ParameterKind, Pointer, Type, TypeModifier
+};
+use crate::inference::{self, Cardinality, InferredType};
+
+/// What a built-in constraint can be applied to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SubjectKind {
+    /// Any value, including objects picked out with `on (...)`.
+    Any,
+    /// Scalars that can be compared with `<` and `>`.
+    Orderable,
+    /// Values with a length: `str`, `bytes` and arrays.
+    Sized,
+    Str
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstraintSignature {
+    pub ident:       String,
+    pub params:      Vec<Parameter>,
+    pub subject:     SubjectKind,
+    /// Whether the constraint is meaningless without an `on (...)` clause.
+    pub requires_on: bool,
+    /// Boolean check over `__subject__` and the parameters, for
+    /// constraints declared in a schema rather than built in.
+    pub using:       Option<Expression>,
+    pub errmessage:  String
+}
+
+fn param(ident: &str, ty: &str) -> Parameter {
+    Parameter {
+        ident: ident.to_string(),
+        ty: ty.to_string(),
+        kind: ParameterKind::Positional,
+        typemod: TypeModifier::Singleton,
+        default: None
+    }
+}
+
+fn signature(
+    ident: &str,
+    params: Vec<Parameter>,
+    subject: SubjectKind,
+    errmessage: &str) -> ConstraintSignature
+{
+    ConstraintSignature {
+        ident: ident.to_string(),
+        params,
+        subject,
+        requires_on: false,
+        using: None,
+        errmessage: errmessage.to_string()
+    }
+}
+
+/// Signatures of the constraints in EdgeDB's `std` module. `anytype`
+/// parameters take on the type of the constrained value.
+pub fn std_constraints() -> Vec<ConstraintSignature> {
+    use SubjectKind::*;
+    let mut one_of = param("vals", "anytype");
+    one_of.kind = ParameterKind::Variadic;
+    vec![
+        signature("exclusive", vec![], Any,
+                  "{__subject__} violates exclusivity constraint"),
+        ConstraintSignature {
+            requires_on: true,
+            ..signature("expression", vec![], Any, "invalid {__subject__}")
+        },
+        signature("one_of", vec![one_of], Orderable,
+                  "{__subject__} must be one of: {vals}."),
+        signature("min_value", vec![param("min", "anytype")], Orderable,
+                  "Minimum allowed value for {__subject__} is {min}."),
+        signature("min_ex_value", vec![param("min", "anytype")], Orderable,
+                  "Minimum allowed value for {__subject__} is greater than {min}."),
+        signature("max_value", vec![param("max", "anytype")], Orderable,
+                  "Maximum allowed value for {__subject__} is {max}."),
+        signature("max_ex_value", vec![param("max", "anytype")], Orderable,
+                  "Maximum allowed value for {__subject__} is less than {max}."),
+        signature("min_len_value", vec![param("min", "int64")], Sized,
+                  "{__subject__} must be no shorter than {min} characters."),
+        signature("max_len_value", vec![param("max", "int64")], Sized,
+                  "{__subject__} must be no longer than {max} characters."),
+        signature("regexp", vec![param("pattern", "str")], Str,
+                  "invalid {__subject__}"),
+    ]
+}
+
+/// Look up a `std` constraint by its short or `std::` qualified name.
+pub fn std_constraint(ident: &str) -> Option<ConstraintSignature> {
+    let ident = ident.trim_start_matches("std::");
+    std_constraints().into_iter().find(|sig| sig.ident == ident)
+}
+
+/// The signature of the constraint called `ident`: an abstract
+/// constraint declared in `module`, or else one from `std`. Declared
+/// constraints inherit whatever they leave out from the ones they extend.
+pub fn resolve(module: &Module, ident: &str) -> Result<ConstraintSignature, String> {
+    resolve_from(module, ident, &mut Vec::new())
+}
+
+fn resolve_from(module: &Module, ident: &str, seen: &mut Vec<String>) -> Result<ConstraintSignature, String> {
+    let decl = match module.get_constraint(ident) {
+        Some(decl) => decl,
+        None => return std_constraint(ident).ok_or_else(|| format!("Unknown constraint `{}`.", ident))
+    };
+    if seen.contains(&decl.ident) {
+        return Err(format!("Constraint `{}` extends itself.", decl.ident));
+    }
+    seen.push(decl.ident.clone());
+    let base = match decl.extends.as_ref().and_then(|bases| bases.first()) {
+        Some(base) => Some(resolve_from(module, base, seen)?),
+        None => None
+    };
+    Ok(ConstraintSignature {
+        ident: decl.ident.clone(),
+        params: decl.params.clone()
+            .or_else(|| base.as_ref().map(|base| base.params.clone()))
+            .unwrap_or_default(),
+        subject: base.as_ref().map_or(SubjectKind::Any, |base| base.subject),
+        requires_on: matches!(&base, Some(base) if base.requires_on),
+        using: decl.using().cloned().or_else(|| base.as_ref().and_then(|base| base.using.clone())),
+        errmessage: decl.errmessage().map(|msg| msg.to_string())
+            .or_else(|| base.as_ref().map(|base| base.errmessage.clone()))
+            .unwrap_or_else(|| "invalid {__subject__}".to_string())
+    })
+}
+
+/// Check `constraint` as applied to a value of type `subject`: the
+/// subject must suit the constraint and the arguments its parameters.
+pub fn check(module: &Module, subject: &str, constraint: &Constraint) -> Result<(), String> {
+    let sig = resolve(module, &constraint.ident)?;
+
+    let subject = match &constraint.on {
+        Some(on) => {
+            let inferred = inference::infer(module, subject, &on.parse()?)?;
+            if sig.ident == "expression" && inferred.ty.trim_start_matches("std::") != "bool" {
+                return Err(format!(
+                    "The expression of `constraint expression on (...)` must be a `bool`, not `{}`.",
+                    inferred.ty));
+            }
+            inferred
+        },
+        None if sig.requires_on => {
+            return Err(format!("Constraint `{}` requires an `on (...)` expression.", sig.ident));
+        },
+        None => InferredType {
+            ty: subject.to_string(),
+            cardinality: Cardinality::One,
+            object: module.is_object(subject)
+        }
+    };
+    check_subject(module, &sig, &subject)?;
+    check_args(module, &sig, &subject, constraint)?;
+    check_using(module, &sig, &subject)
+}
+
+/// Type the `using` expression with `__subject__` bound to the
+/// constrained value and each parameter bound to its declared type.
+fn check_using(module: &Module, sig: &ConstraintSignature, subject: &InferredType) -> Result<(), String> {
+    let using = match &sig.using {
+        Some(using) => using,
+        None => return Ok(())
+    };
+    let bindings: Vec<(String, InferredType)> = sig.params.iter()
+        .map(|param| {
+            let ty = if param.ty == "anytype" { subject.ty.as_str() } else { param.ty.as_str() };
+            let cardinality = if param.is_variadic() { Cardinality::Many } else { Cardinality::One };
+            (param.ident.clone(), InferredType { ty: ty.to_string(), cardinality, object: module.is_object(ty) })
+        })
+        .collect();
+    let inferred = inference::infer_with(module, &subject.ty, &bindings, &using.parse()?)
+        .map_err(|e| format!("In the `using` of constraint `{}` on `{}`: {}", sig.ident, subject.ty, e))?;
+    if module.scalar_base(&inferred.ty) != "bool" {
+        return Err(format!(
+            "The `using` of constraint `{}` must be a `bool`, not `{}`.",
+            sig.ident, inferred.ty));
+    }
+    Ok(())
+}
+
+/// Fill the `{param}` and `{__subject__}` placeholders of `template`.
+/// `{{` and `}}` stand for literal braces.
+pub fn interpolate(template: &str, values: &[(&str, &str)]) -> Result<String, String> {
+    let mut message = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        message.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            message.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err(format!("Unmatched `}}` in error message `{}`.", template));
+        }
+        let end = tail.find('}')
+            .ok_or_else(|| format!("Unmatched `{{` in error message `{}`.", template))?;
+        let name = &tail[1..end];
+        let value = values.iter()
+            .find(|(key, _)| *key == name)
+            .ok_or_else(|| format!("Error message `{}` refers to unknown `{{{}}}`.", template, name))?;
+        message.push_str(value.1);
+        rest = &tail[end + 1..];
+    }
+    message.push_str(rest);
+    Ok(message)
+}
+
+fn check_subject(module: &Module, sig: &ConstraintSignature, subject: &InferredType) -> Result<(), String> {
+    let base = module.scalar_base(&subject.ty);
+    let suits = match sig.subject {
+        SubjectKind::Any       => true,
+        SubjectKind::Orderable => !subject.object && base != "json",
+        SubjectKind::Sized     => matches!(base.as_str(), "str" | "bytes") || base.starts_with("array<"),
+        SubjectKind::Str       => base == "str"
+    };
+    if suits {
+        Ok(())
+    } else {
+        Err(format!("Constraint `{}` cannot be applied to `{}`.", sig.ident, subject.ty))
+    }
+}
+
+fn check_args(
+    module: &Module,
+    sig: &ConstraintSignature,
+    subject: &InferredType,
+    constraint: &Constraint) -> Result<(), String>
+{
+    let args: &[_] = constraint.args.as_ref().map_or(&[], |args| args.as_slice());
+    let variadic = matches!(sig.params.last(), Some(param) if param.is_variadic());
+    let fixed = if variadic { sig.params.len() - 1 } else { sig.params.len() };
+    let count_ok = if variadic { args.len() > fixed } else { args.len() == fixed };
+    if args.is_empty() && !sig.params.is_empty() {
+        let params: Vec<String> = sig.params.iter().map(|param| format!("{}: {}", param.ident, param.ty)).collect();
+        return Err(format!(
+            "Constraint `{}` is used without arguments, but it takes ({}).", sig.ident, params.join(", ")));
+    }
+    if !count_ok {
+        return Err(format!(
+            "Constraint `{}` takes {}{} argument(s) but {} were given.",
+            sig.ident, if variadic { "at least " } else { "" }, sig.params.len(), args.len()));
+    }
+    for (i, arg) in args.iter().enumerate() {
+        let param = &sig.params[i.min(sig.params.len() - 1)];
+        let inferred = inference::infer(module, &subject.ty, &arg.parse()?)?;
+        if inferred.cardinality.is_multi() {
+            return Err(format!(
+                "Argument `{}` of constraint `{}` must be a single value.",
+                param.ident, sig.ident));
+        }
+        let expected = if param.ty == "anytype" { subject.ty.as_str() } else { param.ty.as_str() };
+        if !assignable(module, &inferred.ty, expected) {
+            return Err(format!(
+                "Argument `{}` of constraint `{}` must be `{}`, not `{}`.",
+                param.ident, sig.ident, expected, inferred.ty));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a value of type `from` is accepted where `to` is expected,
+/// allowing for implicit casts between numeric types.
+pub(crate) fn assignable(module: &Module, from: &str, to: &str) -> bool {
+    let (from, to) = (module.scalar_base(from), module.scalar_base(to));
+    let integer = |ty: &str| matches!(ty, "int16" | "int32" | "int64" | "bigint");
+    let numeric = |ty: &str| integer(ty) || matches!(ty, "float32" | "float64" | "decimal");
+    from == to
+        || to == "anytype"
+        || (integer(&to) && integer(&from))
+        || (numeric(&to) && !integer(&to) && numeric(&from))
+}
+
+fn pointer_constraints<'m>(pointer: Pointer<'m>) -> &'m [Constraint] {
+    match pointer {
+        Pointer::Property(prop) => list(&prop.constraints),
+        Pointer::Link(link)     => list(&link.constraints)
+    }
+}
+
+pub(crate) fn same_constraint(a: &Constraint, b: &Constraint) -> bool {
+    let args = |c: &Constraint| -> Vec<String> {
+        c.args.iter().flat_map(|args| args.iter()).map(|arg| arg.ident.clone()).collect()
+    };
+    a.ident.trim_start_matches("std::") == b.ident.trim_start_matches("std::")
+        && args(a) == args(b)
+        && a.on.as_ref().map(|on| &on.ident) == b.on.as_ref().map(|on| &on.ident)
+}
+
+impl Module {
+    /// The constraints enforced on `pointer` of `ty`, including the ones
+    /// it inherits. A `delegated` constraint is not enforced on the abstract
+    /// type that declares it; each concrete subtype gets its own copy.
+    pub fn effective_constraints(&self, ty: &Type, pointer: &str) -> Vec<Constraint> {
+        let mut effective: Vec<Constraint> = Vec::new();
+        for owner in std::iter::once(ty).chain(self.ancestors(ty)) {
+            let declared = match owner.pointer(pointer) {
+                Some(declared) => pointer_constraints(declared),
+                None => continue
+            };
+            for constraint in declared.iter() {
+                if constraint.delegated && ty.abs {
+                    continue;
+                }
+                if effective.iter().any(|seen| same_constraint(seen, constraint)) {
+                    continue;
+                }
+                let mut constraint = constraint.clone();
+                constraint.delegated = false;
+                effective.push(constraint);
+            }
+        }
+        effective
+    }
+
+    /// Check the abstract constraints declared in the module: their
+    /// parameters, what they extend and the placeholders in `errmessage`.
+    /// The `using` clause is typed wherever the constraint is applied.
+    pub fn validate_abstract_constraints(&self) -> Result<(), String> {
+        match self.abstract_constraint_issues().into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the abstract constraints declared in the
+    /// module, with the constraint it is about.
+    pub(crate) fn abstract_constraint_issues(&self) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for decl in self.constraints.iter() {
+            let context = |e: String| (decl.ident.clone(), format!("In constraint `{}`: {}", decl.ident, e));
+            if !decl.abs {
+                issues.push(context("Only abstract constraints can be declared at module level.".to_string()));
+                continue;
+            }
+            let params = decl.params.as_deref().unwrap_or(&[]);
+            for (i, param) in params.iter().enumerate() {
+                if params[..i].iter().any(|other| other.ident == param.ident) {
+                    issues.push(context(format!("Parameter `{}` is declared twice.", param.ident)));
+                }
+                if param.is_variadic() && i + 1 != params.len() {
+                    issues.push(context(format!("Variadic parameter `{}` must come last.", param.ident)));
+                }
+                let known = param.ty == "anytype" || self.is_scalar(&param.ty) || self.is_object(&param.ty);
+                if !known {
+                    issues.push(context(format!("Parameter `{}` has unknown type `{}`.", param.ident, param.ty)));
+                }
+            }
+            if let Some(Err(e)) = decl.using().map(|using| using.parse()) {
+                issues.push(context(e));
+            }
+            let sig = match resolve(self, &decl.ident) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    issues.push(context(e));
+                    continue;
+                }
+            };
+            let values: Vec<(&str, &str)> = std::iter::once("__subject__")
+                .chain(sig.params.iter().map(|param| param.ident.as_str()))
+                .map(|name| (name, ""))
+                .collect();
+            if let Err(e) = interpolate(&sig.errmessage, &values) {
+                issues.push(context(e));
+            }
+        }
+        issues
+    }
+
+    /// The error message reported when `constraint` fails for `subject`,
+    /// with the constraint's arguments interpolated into its template.
+    pub fn constraint_errmessage(&self, constraint: &Constraint, subject: &str) -> Result<String, String> {
+        let sig = resolve(self, &constraint.ident)?;
+        let template = constraint.errmessage().unwrap_or(&sig.errmessage);
+        let args: Vec<&str> = constraint.args.iter()
+            .flat_map(|args| args.iter())
+            .map(|arg| arg.ident.as_str())
+            .collect();
+        let mut values = vec![("__subject__", subject.to_string())];
+        for (i, param) in sig.params.iter().enumerate() {
+            let value = if param.is_variadic() {
+                args.get(i..).unwrap_or(&[]).join(", ")
+            } else {
+                args.get(i).map_or(String::new(), |arg| arg.to_string())
+            };
+            values.push((param.ident.as_str(), value));
+        }
+        let values: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        interpolate(template, &values)
+    }
+
+    /// Check every constraint in the module against its subject.
+    pub fn validate_constraints(&self) -> Result<(), String> {
+        self.validate_abstract_constraints()?;
+        match self.constraint_issues().into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the constraints applied to types and
+    /// pointers, with the type or pointer it is about.
+    pub(crate) fn constraint_issues(&self) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for ty in self.types.iter() {
+            for constraint in ty.constraints.iter().flat_map(|cs| cs.iter()) {
+                let context = |e: String| (ty.ident.clone(), format!("In `{}`: {}", ty.ident, e));
+                let exclusive = constraint.ident.trim_start_matches("std::") == "exclusive";
+                if ty.scalar && exclusive {
+                    issues.push(context("Constraint `exclusive` cannot be used on scalar types.".to_string()));
+                } else if !ty.scalar && constraint.on.is_none() {
+                    issues.push(context(format!(
+                        "Constraint `{}` on an object type requires an `on (...)` expression.",
+                        constraint.ident)));
+                } else if let Err(e) = check(self, &ty.ident, constraint) {
+                    issues.push(context(e));
+                }
+            }
+            let pointers = ty.properties.iter().flat_map(|props| props.iter()).map(Pointer::Property)
+                .chain(ty.links.iter().flat_map(|links| links.iter()).map(Pointer::Link));
+            for pointer in pointers {
+                let subject = format!("{}.{}", ty.ident, pointer.ident());
+                let context = |e: String| (subject.clone(), format!("In `{}`: {}", subject, e));
+                let constraints = pointer_constraints(pointer);
+                if pointer.is_computable() && !constraints.is_empty() {
+                    issues.push(context("Computables cannot have constraints.".to_string()));
+                    continue;
+                }
+                let target = match pointer.target() {
+                    Some(target) => target,
+                    None => continue
+                };
+                for constraint in constraints.iter() {
+                    if let Err(e) = check(self, target, constraint) {
+                        issues.push(context(e));
+                    }
+                }
+                if let Pointer::Link(link) = pointer {
+                    for (at_ident, prop) in link.link_properties() {
+                        let target = match prop.target.as_deref() {
+                            Some(target) => target,
+                            None => continue
+                        };
+                        for constraint in prop.constraints.iter().flat_map(|cs| cs.iter()) {
+                            if let Err(e) = check(self, target, constraint) {
+                                let issue = format!("In `{}`: In `{}`: {}", subject, at_ident, e);
+                                issues.push((format!("{}{}", subject, at_ident), issue));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{ConstraintBuilder, Expression, PropertyBuilder, TypeBuilder};
+    use crate::fixtures;
+
+    fn constraint(ident: &str, args: &[&str]) -> Constraint {
+        let args: Vec<Expression> = args.iter().map(|arg| Expression::new(arg)).collect();
+        ConstraintBuilder::default().ident(ident).args(Box::new(args)).build().unwrap()
+    }
+
+    #[test]
+    fn checks_arguments_against_the_subject() {
+        let module = Module::new("default");
+        assert!(check(&module, "str", &constraint("max_len_value", &["5"])).is_ok());
+        assert!(check(&module, "int64", &constraint("max_len_value", &["5"])).is_err());
+        assert!(check(&module, "int64", &constraint("min_value", &["0"])).is_ok());
+        assert!(check(&module, "int64", &constraint("min_value", &["'zero'"])).is_err());
+        assert!(check(&module, "str", &constraint("regexp", &["'[A-Z]+'"])).is_ok());
+        assert!(check(&module, "str", &constraint("one_of", &["'a'", "'b'"])).is_ok());
+        assert!(check(&module, "str", &constraint("one_of", &[])).is_err());
+        assert!(check(&module, "str", &constraint("exclusive", &["1"])).is_err());
+    }
+
+    #[test]
+    fn user_defined_constraints() {
+        use crate::datastructures::SubcommandBuilder;
+
+        let using = SubcommandBuilder::default()
+            .using(Box::new(Expression::new("__subject__ > x")))
+            .build()
+            .unwrap();
+        let errmessage = SubcommandBuilder::default()
+            .err_message("{__subject__} must be greater than {x}")
+            .build()
+            .unwrap();
+        let decl = ConstraintBuilder::default()
+            .ident("user_int_constr")
+            .abs(true)
+            .params(vec![param("x", "int64")])
+            .subcommands(Box::new(vec![using, errmessage]))
+            .build()
+            .unwrap();
+        let mut module = Module::new("default");
+        module.constraints.push(decl);
+        assert!(module.validate_abstract_constraints().is_ok());
+
+        let concrete = constraint("user_int_constr", &["5"]);
+        assert!(check(&module, "int64", &concrete).is_ok());
+        assert!(check(&module, "bool", &concrete).is_err());
+        assert!(check(&module, "int64", &constraint("user_int_constr", &["'5'"])).is_err());
+        assert_eq!(
+            module.constraint_errmessage(&concrete, "m0").unwrap(),
+            "m0 must be greater than 5");
+
+        module.constraints[0].subcommands.as_mut().unwrap()[1].err_message = Some("{y} is wrong".to_string());
+        assert!(module.validate_abstract_constraints().is_err());
+    }
+
+    #[test]
+    fn delegated_constraints_apply_to_concrete_subtypes() {
+        let exclusive = ConstraintBuilder::default()
+            .ident("exclusive")
+            .delegated(true)
+            .build()
+            .unwrap();
+        let name = PropertyBuilder::default()
+            .ident("name")
+            .target("str")
+            .constraints(Box::new(vec![exclusive]))
+            .build()
+            .unwrap();
+        let named = TypeBuilder::default()
+            .ident("Named")
+            .abs(true)
+            .properties(Box::new(vec![name]))
+            .build()
+            .unwrap();
+        let user = TypeBuilder::default()
+            .ident("User")
+            .extends(vec!["Named".to_string()])
+            .build()
+            .unwrap();
+        let module = fixtures::module(vec![named, user]);
+        assert!(module.validate_constraints().is_ok());
+
+        let named = module.get_type("Named").unwrap();
+        assert!(module.effective_constraints(named, "name").is_empty());
+        let user = module.get_type("User").unwrap();
+        let effective = module.effective_constraints(user, "name");
+        assert_eq!(effective.len(), 1);
+        assert!(!effective[0].delegated);
+    }
+}
@@ -1,16 +1,226 @@
 use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
 use std::rc::Weak;
 use std::cell::RefCell;
 use std::boxed::Box;
-use map_vec::Set; // Contemplating an alternative.
-use std::mem::ManuallyDrop;
+use crate::edgeql::{self, Expr};
+
+/// Scalar types shipped in EdgeDB's `std` and `cal` modules.
+pub const STD_SCALARS: &[&str] = &[
+    "str", "bool", "uuid", "json", "bytes", "sequence",
+    "int16", "int32", "int64", "float32", "float64", "bigint", "decimal",
+    "datetime", "duration",
+    "cal::local_datetime", "cal::local_date", "cal::local_time",
+    "anyscalar", "anyint", "anyfloat", "anyreal",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Module {
+    pub ident: String,
+    pub types: Vec<Type>,
+    /// Abstract constraint declarations (`abstract constraint foo(...)`).
+    pub constraints: Vec<Constraint>,
+    /// Abstract property declarations (`abstract property title`).
+    pub properties: Vec<Property>,
+    /// Abstract link declarations (`abstract link owned_by`).
+    pub links: Vec<Link>,
+    /// Annotation declarations (`abstract inheritable annotation foo`).
+    pub annotations: Vec<AbstractAnnotation>,
+    /// `alias Foo := ...` declarations.
+    pub aliases: Vec<Alias>,
+    /// `function foo(...) -> ...` declarations.
+    pub functions: Vec<Function>
+}
+
+impl Module {
+    pub fn new(ident: &str) -> Module {
+        Module { ident: ident.to_string(), ..Default::default() }
+    }
 
-#[derive(Debug, Clone, Default)]
-pub struct Module {}
+    /// Look up a type declared in this module by its short
+    /// or fully-qualified (`module::Type`) name.
+    pub fn get_type(&self, ident: &str) -> Option<&Type> {
+        let ident = self.local_ident(ident);
+        self.types.iter().find(|ty| ty.ident == ident)
+    }
 
-#[derive(Builder, Debug, Clone)]
+    /// Whether `ident` names a scalar type, i.e. a `std` scalar,
+    /// a scalar type declared in this module, or a collection
+    /// (`array<...>`, `tuple<...>`) made only of scalars.
+    pub fn is_scalar(&self, ident: &str) -> bool {
+        let ident = ident.trim();
+        if let Some(inner) = collection_elements(ident) {
+            return inner.iter().all(|el| self.is_scalar(el));
+        }
+        let std_ident = ident.trim_start_matches("std::");
+        if STD_SCALARS.contains(&std_ident) {
+            return true;
+        }
+        matches!(self.get_type(ident), Some(ty) if ty.scalar)
+    }
+
+    /// The `std` scalar that a scalar type ultimately extends,
+    /// e.g. `int64` for `scalar type positive_int_t extending int64`.
+    pub fn scalar_base(&self, ident: &str) -> String {
+        let mut ident = ident.trim_start_matches("std::").to_string();
+        let mut seen = Vec::new();
+        while let Some(ty) = self.get_type(&ident) {
+            let base = match ty.extends.as_ref().and_then(|bases| bases.first()) {
+                Some(base) if ty.scalar && !seen.contains(base) => base.clone(),
+                _ => break
+            };
+            seen.push(base.clone());
+            ident = base.trim_start_matches("std::").to_string();
+        }
+        ident
+    }
+
+    /// Whether `ident` names an object type declared in this module.
+    pub fn is_object(&self, ident: &str) -> bool {
+        matches!(self.get_type(ident), Some(ty) if !ty.scalar)
+    }
+
+    /// Types that code should be generated for: everything
+    /// except builtin and internal types.
+    pub fn codegen_types(&self) -> impl Iterator<Item = &Type> {
+        self.types.iter().filter(|ty| ty.is_generated())
+    }
+
+    /// Look up an abstract constraint declared in this module.
+    pub fn get_constraint(&self, ident: &str) -> Option<&Constraint> {
+        let ident = self.local_ident(ident);
+        self.constraints.iter().find(|constraint| constraint.ident == ident)
+    }
+
+    /// Look up an abstract property declared in this module.
+    pub fn get_property(&self, ident: &str) -> Option<&Property> {
+        let ident = self.local_ident(ident);
+        self.properties.iter().find(|prop| prop.ident == ident)
+    }
+
+    /// Look up an abstract link declared in this module.
+    pub fn get_link(&self, ident: &str) -> Option<&Link> {
+        let ident = self.local_ident(ident);
+        self.links.iter().find(|link| link.ident == ident)
+    }
+
+    /// Look up an annotation declared in this module.
+    pub fn get_annotation(&self, ident: &str) -> Option<&AbstractAnnotation> {
+        let ident = self.local_ident(ident);
+        self.annotations.iter().find(|annotation| annotation.ident == ident)
+    }
+
+    /// Look up an alias declared in this module.
+    pub fn get_alias(&self, ident: &str) -> Option<&Alias> {
+        let ident = self.local_ident(ident);
+        self.aliases.iter().find(|alias| alias.ident == ident)
+    }
+
+    /// Overloads of function `ident` declared in this module.
+    pub fn get_functions<'m>(&'m self, ident: &'m str) -> impl Iterator<Item = &'m Function> {
+        let ident = self.local_ident(ident);
+        self.functions.iter().filter(move |func| func.ident == ident)
+    }
+
+    /// Supertypes of `ty`, nearest first, each listed once.
+    pub fn ancestors(&self, ty: &Type) -> Vec<&Type> {
+        let mut ancestors: Vec<&Type> = Vec::new();
+        let mut queue: Vec<&str> = ty.extends.iter()
+            .flat_map(|bases| bases.iter().map(|base| base.as_str()))
+            .collect();
+        while !queue.is_empty() {
+            let base = queue.remove(0);
+            if let Some(base) = self.get_type(base) {
+                if base.ident == ty.ident || ancestors.iter().any(|seen| seen.ident == base.ident) {
+                    continue;
+                }
+                queue.extend(base.extends.iter().flat_map(|bases| bases.iter().map(|b| b.as_str())));
+                ancestors.push(base);
+            }
+        }
+        ancestors
+    }
+
+    /// Find the property or link called `ident` on `ty` or
+    /// on the nearest supertype that declares it.
+    pub fn find_pointer<'m>(&'m self, ty: &'m Type, ident: &str) -> Option<Pointer<'m>> {
+        std::iter::once(ty)
+            .chain(self.ancestors(ty))
+            .find_map(|ty| ty.pointer(ident))
+    }
+
+    /// Verify that the properties of every link in the module
+    /// target scalar types.
+    pub fn validate_link_properties(&self) -> Result<(), String> {
+        match self.link_property_issues().into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the properties of the links in the module,
+    /// with the link property it is about.
+    pub(crate) fn link_property_issues(&self) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for ty in self.types.iter() {
+            for link in ty.links.iter().flat_map(|links| links.iter()) {
+                issues.extend(link.property_issues(self).into_iter().map(|(at_ident, e)| {
+                    (format!("{}.{}{}", ty.ident, link.ident, at_ident), format!("In `{}`: {}", ty.ident, e))
+                }));
+            }
+        }
+        issues
+    }
+
+    fn local_ident<'i>(&self, ident: &'i str) -> &'i str {
+        match ident.split("::").collect::<Vec<_>>().as_slice() {
+            [module, local] if *module == self.ident => local,
+            _ => ident
+        }
+    }
+}
+
+/// The children in one of the optional, boxed lists that schema items
+/// keep them in, such as `Type::properties`; empty for `None`.
+#[allow(clippy::box_collection)]
+pub(crate) fn list<T>(items: &Option<Box<Vec<T>>>) -> &[T] {
+    items.as_deref().map_or(&[], |items| &items[..])
+}
+
+/// Split `array<T>` or `tuple<A, B>` into its element types.
+fn collection_elements(ident: &str) -> Option<Vec<&str>> {
+    let inner = ident.strip_prefix("array<")
+        .or_else(|| ident.strip_prefix("tuple<"))?
+        .strip_suffix('>')?;
+    let mut depth = 0;
+    let mut start = 0;
+    let mut elements = Vec::new();
+    for (i, c) in inner.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                elements.push(element_type(&inner[start..i]));
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    elements.push(element_type(&inner[start..]));
+    Some(elements)
+}
+
+/// Named tuple elements are written as `name: type`.
+fn element_type(element: &str) -> &str {
+    match element.find(':') {
+        Some(i) if !element[i..].starts_with("::") => element[i + 1..].trim(),
+        _ => element.trim()
+    }
+}
+
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
 #[builder(build_fn(validate = "Self::validate"))]
-pub struct Type<'a, 'b, 'c, 'd> {
+pub struct Type {
 
     #[builder(setter(into))]
     pub ident:       String,
@@ -20,31 +230,69 @@ pub struct Type<'a, 'b, 'c, 'd> {
     
     #[builder(setter(into), default = "false")]
     pub scalar:      bool,
+
+    /// `final` types cannot be extended.
+    #[builder(setter(into), default = "false")]
+    pub is_final:    bool,
+
+    /// Shipped with EdgeDB rather than declared in a schema.
+    #[builder(setter(into), default = "false")]
+    pub builtin:     bool,
+
+    /// Used by EdgeDB itself and not meant to be referred to.
+    #[builder(setter(into), default = "false")]
+    pub is_internal: bool,
+
+    /// Read-only pseudo-type produced by an `alias` declaration.
+    #[builder(setter(into), default = "false")]
+    pub alias:       bool,
     
     #[builder(setter(into, strip_option), default)]
-    pub extends:     Option<&'a [RefCell<Weak<SuperType<'b, 'c, 'c, 'd>>>]>,
+    pub extends:     Option<Vec<String>>,
 
     #[builder(setter(into, strip_option), default)]
-    pub properties:  Option<Box<Vec<Property<'a>>>>,
+    pub properties:  Option<Box<Vec<Property>>>,
 
     #[builder(setter(into, strip_option), default)]
     pub annotations: Option<Box<Vec<Annotation>>>,
     
     #[builder(setter(into, strip_option), default)]
-    pub links:       Option<&'d [RefCell<Weak<Link<'d>>>]>,
+    pub links:       Option<Box<Vec<Link>>>,
     
     #[builder(setter(into, strip_option), default)]
     pub constraints: Option<Box<Vec<Constraint>>>,
     
     #[builder(setter(into, strip_option), default)]
-    pub indices:     Option<Box<Index>>
+    pub indices:     Option<Box<Vec<Index>>>
+
+}
+
+impl Type {
+    /// Whether clients get code for this type, i.e. it is neither
+    /// builtin nor internal.
+    pub fn is_generated(&self) -> bool {
+        !self.builtin && !self.is_internal
+    }
 
+    /// The property or link called `ident` declared directly on this type.
+    pub fn pointer(&self, ident: &str) -> Option<Pointer<'_>> {
+        let property = self.properties.iter()
+            .flat_map(|props| props.iter())
+            .find(|prop| prop.ident == ident)
+            .map(Pointer::Property);
+        property.or_else(|| {
+            self.links.iter()
+                .flat_map(|links| links.iter())
+                .find(|link| link.ident == ident)
+                .map(Pointer::Link)
+        })
+    }
 }
 
-impl<'a, 'b, 'c, 'd> TypeBuilder<'a, 'b, 'c, 'd> {
+impl TypeBuilder {
     /// Verify that `self.ident` is not an empty `String`
     fn validate(&self) -> Result<(), String> {
-        if self.ident.as_ref().unwrap().is_empty() {
+        if self.ident.as_deref() == Some("") {
             Err("`Type.ident` must not be an empty String.".to_string())
         } else {
             Ok(())
@@ -52,7 +300,7 @@ impl<'a, 'b, 'c, 'd> TypeBuilder<'a, 'b, 'c, 'd> {
     }
 }
 
-#[derive(Builder, Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Builder, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 #[builder(build_fn(validate = "Self::validate"))]
 pub struct Annotation {
     #[builder(setter(into))]
@@ -65,7 +313,7 @@ pub struct Annotation {
 impl AnnotationBuilder {
     /// Verify that `self.ident` is not an empty `String`
     fn validate(&self) -> Result<(), String> {
-        if self.ident.as_ref().unwrap().is_empty() {
+        if self.ident.as_deref() == Some("") {
             Err("`Type.ident` must not be an empty String.".to_string())
         } else {
             Ok(())
@@ -73,8 +321,32 @@ impl AnnotationBuilder {
     }
 }
 
-pub type SuperType<'a, 'b, 'c, 'd> = Type<'a, 'b, 'c, 'd>;
-pub type ArgSpec = Annotation;
+/// `abstract [inheritable] annotation <ident>`: the declaration
+/// that every `annotation <ident> := '...'` must refer to.
+#[derive(Builder, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct AbstractAnnotation {
+    #[builder(setter(into))]
+    pub ident: String,
+
+    /// Inheritable annotations are passed down to subtypes
+    /// and to the pointers that override a supertype's.
+    #[builder(setter(into), default = "false")]
+    pub inheritable: bool
+}
+
+impl AbstractAnnotationBuilder {
+    /// Verify that `self.ident` is not an empty `String`
+    fn validate(&self) -> Result<(), String> {
+        if self.ident.as_deref() == Some("") {
+            Err("`AbstractAnnotation.ident` must not be an empty String.".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub type SuperType = Type;
 
 /// As I understand it, EdgeQL uses aliases as constructs
 ///  like sub-queries to cut down on boilerplate within a 
@@ -90,7 +362,7 @@ pub type ArgSpec = Annotation;
 ///              }
 ///          }```
 ///
-#[derive(Builder, Clone, Debug)]
+#[derive(Builder, Clone, Debug, Serialize, Deserialize)]
 #[builder(build_fn(validate = "Self::validate"))]
 pub struct Alias {
     
@@ -99,12 +371,25 @@ pub struct Alias {
 
     #[builder]
     pub alias_expr: AliasExpr,
+
+    #[builder(setter(into, strip_option), default)]
+    pub annotations: Option<Box<Vec<Annotation>>>
+}
+
+impl Alias {
+    /// Parse the expression that the alias stands for.
+    pub fn expr(&self) -> Result<Expr, String> {
+        self.alias_expr.expression()
+            .ok_or_else(|| format!("Alias `{}` has no expression.", self.ident))?
+            .parse()
+            .map_err(|e| format!("In alias `{}`: {}", self.ident, e))
+    }
 }
 
 impl AliasBuilder {
     /// Verify that `self.ident` is not an empty `String`
     fn validate(&self) -> Result<(), String> {
-        if self.ident.as_ref().unwrap().is_empty() {
+        if self.ident.as_deref() == Some("") {
             Err("`AliasBuilder.ident` must not be an empty String.".to_string())
         } else {
             Ok(())
@@ -112,20 +397,38 @@ impl AliasBuilder {
     }   
 }
 
-#[derive(Builder, Debug, Clone)]
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
 pub struct AliasExpr { 
     
     #[builder(setter(into, strip_option), default)]
     pub ddl: Option<String>,
     
     #[builder(setter(into, strip_option), default)]
-    pub sdl: Option<String>
+    pub sdl: Option<String>,
+
+    /// The right-hand side of `:=`, when known on its own.
+    #[builder(setter(into, strip_option), default)]
+    pub expr: Option<Expression>
 
     // ToDo: Make traits for better building patterns + data-structural integrity
 }
 
-#[derive(Builder, Clone, Debug)]
-pub struct Property<'a> { 
+impl AliasExpr {
+    /// The aliased expression: `expr` if set, otherwise whatever
+    /// follows `:=` in the SDL or DDL source.
+    pub fn expression(&self) -> Option<Expression> {
+        if let Some(expr) = &self.expr {
+            return Some(expr.clone());
+        }
+        let source = self.sdl.as_ref().or(self.ddl.as_ref())?;
+        let start = source.find(":=")? + 2;
+        let text = source[start..].trim().trim_end_matches(';').trim();
+        Some(Expression::new(text))
+    }
+}
+
+#[derive(Builder, Clone, Debug, Serialize, Deserialize)]
+pub struct Property { 
 
     #[builder(setter(into))]
     pub ident:         String,
@@ -145,41 +448,70 @@ pub struct Property<'a> {
     #[builder(default = "false")]
     pub multi:      bool,
 
-    // Kind has the longest lifetime. It probably should be static.
-    // `'a` combines lifetime of `PropertyKind` and lifetimes of any
-    // abstract `Property` this might extend. It's a possible memory leak
-    // since any subtypes of an abstract Property might lead to references not
-    // long enough (yikes).
-    // I believe this could happen when some `Property`, p, 
-    // satisfies p.abs == false and `if let Some(P) = p.extends.unwrap()` where `P.kind`
-    // Some(P) where `P.kind` either == or != `self.kind`. If `self.kind != P.kind`, then 
-    // we're screwed.
-    pub kind:          &'a PropertyKind,
-
-    #[builder(setter(into, strip_option))]
-    pub default:       Option<&'a Expression>,
-
-    #[builder(setter(into, strip_option))]
+    #[builder(default = "PropertyKind::CONCRETE")]
+    pub kind:          PropertyKind,
+
+    /// Name of the scalar type this property points at (`-> int64`).
+    /// Filled in by `Module::resolve_computables` for computables.
+    #[builder(setter(into, strip_option), default)]
+    pub target:        Option<String>,
+
+    /// Expression of a computable property (`:= sum(.deck.cost)`).
+    #[builder(setter(into, strip_option), default)]
+    pub expr:          Option<Expression>,
+
+    // Renamed so that the setter does not shadow `Default::default`.
+    #[builder(setter(into, strip_option, name = "default_value"), default)]
+    pub default:       Option<Expression>,
+
+    #[builder(setter(into, strip_option), default)]
     pub constraints:   Option<Box<Vec<Constraint>>>,
 
-    #[builder(setter(into, strip_option))]
-    pub extends:       Option<&'a [RefCell<Weak<Property<'a>>>]>,
+    #[builder(setter(into, strip_option), default)]
+    pub annotations:   Option<Box<Vec<Annotation>>>,
+
+    /// Abstract pointers this one is derived from (`extending title`).
+    #[builder(setter(into, strip_option), default)]
+    pub extends:       Option<Vec<String>>,
 
-    #[builder(setter(into, strip_option))]
+    /// Back-reference to the owning module. It is not serialized.
+    #[builder(setter(into, strip_option), default)]
+    #[serde(skip)]
     pub module:        Option<RefCell<Weak<Module>>>
 }
 
+impl Property {
+    pub fn is_computable(&self) -> bool {
+        self.kind == PropertyKind::COMPUTABLE
+    }
+
+    pub fn is_abstract(&self) -> bool {
+        self.abs || self.kind == PropertyKind::ABSTRACT
+    }
+}
+
+impl PropertyBuilder {
+    /// Make this a computable property. Computables cannot be
+    /// written to, so the property is also marked `readonly`.
+    pub fn computable<E: Into<Expression>>(&mut self, expr: E) -> &mut Self {
+        self.expr = Some(Some(expr.into()));
+        self.kind = Some(PropertyKind::COMPUTABLE);
+        self.readonly = Some(true);
+        self
+    }
+}
+
 #[allow(dead_code)]
 #[allow(non_snake_case)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PropertyKind {
     CONCRETE,
     COMPUTABLE,
     ABSTRACT
 }
 
-#[derive(Builder, Debug, Clone)]
-pub struct Link<'a> {
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
 
     #[builder(setter(into))]
     pub ident:      String,
@@ -199,38 +531,200 @@ pub struct Link<'a> {
     #[builder(default = "false")]
     pub multi:      bool,
 
-    pub kind:          &'a LinkKind,
+    #[builder(default = "LinkKind::CONCRETE")]
+    pub kind:          LinkKind,
 
-    #[builder(setter(into, strip_option))]
-    pub default:       Option<&'a Expression>,
+    /// Name of the object type this link points at (`-> Card`).
+    /// Filled in by `Module::resolve_computables` for computables.
+    #[builder(setter(into, strip_option), default)]
+    pub target:        Option<String>,
+
+    /// Expression of a computable link (`:= .<parent[IS Tree]`).
+    #[builder(setter(into, strip_option), default)]
+    pub expr:          Option<Expression>,
 
-    #[builder(setter(into, strip_option))]
+    /// Properties that belong to the link itself rather than
+    /// to its source or target (`property count -> int64`).
+    #[builder(setter(into, strip_option), default)]
+    pub properties:    Option<Box<Vec<Property>>>,
+
+    // Renamed so that the setter does not shadow `Default::default`.
+    #[builder(setter(into, strip_option, name = "default_value"), default)]
+    pub default:       Option<Expression>,
+
+    #[builder(setter(into, strip_option), default)]
     pub constraints:   Option<Box<Vec<Constraint>>>,
 
-    #[builder(setter(into, strip_option))]
-    pub extends:       Option<&'a [RefCell<Weak<Link<'a>>>]>,
+    #[builder(setter(into, strip_option), default)]
+    pub annotations:   Option<Box<Vec<Annotation>>>,
+
+    /// Abstract pointers this one is derived from (`extending title`).
+    #[builder(setter(into, strip_option), default)]
+    pub extends:       Option<Vec<String>>,
 
-    #[builder(setter(into, strip_option))]
+    /// Back-reference to the owning module. It is not serialized.
+    #[builder(setter(into, strip_option), default)]
+    #[serde(skip)]
     pub module:        Option<RefCell<Weak<Module>>>
 }
 
+impl LinkBuilder {
+    /// Make this a computable link. Computables cannot be
+    /// written to, so the link is also marked `readonly`.
+    pub fn computable<E: Into<Expression>>(&mut self, expr: E) -> &mut Self {
+        self.expr = Some(Some(expr.into()));
+        self.kind = Some(LinkKind::COMPUTABLE);
+        self.readonly = Some(true);
+        self
+    }
+}
+
+impl Link {
+    pub fn is_computable(&self) -> bool {
+        self.kind == LinkKind::COMPUTABLE
+    }
+
+    pub fn is_abstract(&self) -> bool {
+        self.abs || self.kind == LinkKind::ABSTRACT
+    }
+
+    /// Link properties keyed the way shapes and queries address
+    /// them, e.g. `@count` for `property count -> int64`.
+    pub fn link_properties(&self) -> impl Iterator<Item = (String, &Property)> {
+        self.properties.iter()
+            .flat_map(|props| props.iter())
+            .map(|prop| (format!("@{}", prop.ident), prop))
+    }
+
+    /// Find a link property by name, with or without the leading `@`.
+    pub fn link_property(&self, ident: &str) -> Option<&Property> {
+        let ident = ident.trim_start_matches('@');
+        self.properties.iter()
+            .flat_map(|props| props.iter())
+            .find(|prop| prop.ident == ident)
+    }
+
+    /// Verify that every link property is a single scalar value.
+    /// Link properties cannot point at object types, so links
+    /// cannot be nested inside of other links.
+    pub fn validate_properties(&self, module: &Module) -> Result<(), String> {
+        match self.property_issues(module).into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the link's properties, with the `@`-prefixed
+    /// name of the property it is about.
+    pub(crate) fn property_issues(&self, module: &Module) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for (at_ident, prop) in self.link_properties() {
+            let mut push = |issue: String| issues.push((at_ident.clone(), issue));
+            if prop.multi {
+                push(format!(
+                    "Link property `{}` of link `{}` must not be `multi`.",
+                    at_ident, self.ident));
+            }
+            match prop.target.as_deref() {
+                Some(target) if module.is_object(target) => {
+                    push(format!(
+                        "Link property `{}` of link `{}` targets object type `{}`; \
+                         links cannot be nested inside of links.",
+                        at_ident, self.ident, target));
+                },
+                Some(target) if !module.is_scalar(target) => {
+                    push(format!(
+                        "Link property `{}` of link `{}` targets unknown type `{}`.",
+                        at_ident, self.ident, target));
+                },
+                Some(_) => {},
+                None if prop.is_computable() => {},
+                None => {
+                    push(format!(
+                        "Link property `{}` of link `{}` has no target type.",
+                        at_ident, self.ident));
+                }
+            }
+        }
+        issues
+    }
+}
+
 #[allow(dead_code)]
 #[allow(non_snake_case)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum LinkKind {
     CONCRETE,
     COMPUTABLE,
     ABSTRACT
 }
 
-#[derive(Builder, Debug, Clone)]
+/// A borrowed property or link, for code that treats both alike.
+#[derive(Debug, Clone, Copy)]
+pub enum Pointer<'m> {
+    Property(&'m Property),
+    Link(&'m Link)
+}
+
+impl<'m> Pointer<'m> {
+    pub fn ident(&self) -> &'m str {
+        match self {
+            Pointer::Property(prop) => &prop.ident,
+            Pointer::Link(link)     => &link.ident
+        }
+    }
+
+    pub fn target(&self) -> Option<&'m str> {
+        match self {
+            Pointer::Property(prop) => prop.target.as_deref(),
+            Pointer::Link(link)     => link.target.as_deref()
+        }
+    }
+
+    pub fn expr(&self) -> Option<&'m Expression> {
+        match self {
+            Pointer::Property(prop) => prop.expr.as_ref(),
+            Pointer::Link(link)     => link.expr.as_ref()
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        match self {
+            Pointer::Property(prop) => prop.required,
+            Pointer::Link(link)     => link.required
+        }
+    }
+
+    pub fn multi(&self) -> bool {
+        match self {
+            Pointer::Property(prop) => prop.multi,
+            Pointer::Link(link)     => link.multi
+        }
+    }
+
+    pub fn is_computable(&self) -> bool {
+        match self {
+            Pointer::Property(prop) => prop.is_computable(),
+            Pointer::Link(link)     => link.is_computable()
+        }
+    }
+
+    pub fn is_link(&self) -> bool {
+        matches!(self, Pointer::Link(_))
+    }
+}
+
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
 pub struct Constraint {
     
     #[builder(setter(into))]
     pub ident: String,
 
-    #[builder(setter(strip_option), default)]
-    pub extends: Option<RefCell<Weak<Constraint>>>,
+    #[builder(setter(into, strip_option), default)]
+    pub extends: Option<Vec<String>>,
+
+    #[builder(default = "false")]
+    pub abs: bool,
 
     #[builder(default = "false")]
     pub delegated: bool,
@@ -245,13 +739,99 @@ pub struct Constraint {
     pub on_concrete_object_types: bool,
 
     #[builder(setter(strip_option), default)]
-    pub args: Option<Box<Vec<ArgSpec>>>,
+    pub args: Option<Box<Vec<Expression>>>,
+
+    /// Subject expression of `constraint exclusive on (...)`
+    /// and of `constraint expression on (...)`.
+    #[builder(setter(into, strip_option), default)]
+    pub on: Option<Expression>,
+
+    /// Parameters of an abstract constraint, e.g. `(x: int64)`.
+    #[builder(setter(into, strip_option), default)]
+    pub params: Option<Vec<Parameter>>,
 
     #[builder(setter(strip_option), default)]
     pub subcommands: Option<Box<Vec<Subcommand>>>
 }
 
-#[derive(Builder, Debug, Clone)]
+impl Constraint {
+    /// The `using (...)` expression of an abstract constraint.
+    pub fn using(&self) -> Option<&Expression> {
+        self.subcommands.iter()
+            .flat_map(|subcommands| subcommands.iter())
+            .find_map(|subcommand| subcommand.using.as_deref())
+    }
+
+    /// The `errmessage := '...'` template of this constraint.
+    pub fn errmessage(&self) -> Option<&str> {
+        self.subcommands.iter()
+            .flat_map(|subcommands| subcommands.iter())
+            .find_map(|subcommand| subcommand.err_message.as_deref())
+    }
+}
+
+/// A parameter of a constraint or function signature,
+/// e.g. `max: int64` or `NAMED ONLY fill: OPTIONAL str = ' '`.
+#[derive(Builder, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Parameter {
+
+    #[builder(setter(into))]
+    pub ident: String,
+
+    #[builder(setter(into))]
+    pub ty: String,
+
+    #[builder(default = "ParameterKind::Positional")]
+    pub kind: ParameterKind,
+
+    #[builder(default = "TypeModifier::Singleton")]
+    pub typemod: TypeModifier,
+
+    // Renamed so that the setter does not shadow `Default::default`.
+    #[builder(setter(into, strip_option, name = "default_value"), default)]
+    pub default: Option<Expression>
+}
+
+impl Parameter {
+    pub fn is_variadic(&self) -> bool {
+        self.kind == ParameterKind::Variadic
+    }
+}
+
+/// Mirrors `schema::ParameterKind`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ParameterKind {
+    Positional,
+    NamedOnly,
+    Variadic
+}
+
+/// Mirrors `schema::TypeModifier`: whether a parameter or return
+/// value is a single value, an optional one, or a whole set.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TypeModifier {
+    Singleton,
+    Optional,
+    SetOf
+}
+
+/// Mirrors `schema::Volatility`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Volatility {
+    Immutable,
+    Stable,
+    Volatile
+}
+
+/// What a function body is written in. Functions implemented
+/// by EdgeDB itself (in SQL or in the server) are `Builtin`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Language {
+    EdgeQL,
+    Builtin
+}
+
+#[derive(Builder, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Expression {
     
     #[builder(setter(into))]
@@ -259,14 +839,31 @@ pub struct Expression {
 
 }
 
+impl Expression {
+    pub fn new(text: &str) -> Expression {
+        Expression { ident: text.to_string() }
+    }
+
+    /// Parse the EdgeQL source held in `self.ident`.
+    pub fn parse(&self) -> Result<Expr, String> {
+        edgeql::parse(&self.ident)
+    }
+}
+
+impl From<&str> for Expression {
+    fn from(text: &str) -> Expression {
+        Expression::new(text)
+    }
+}
+
 /// `UsingExpression`:
 ///    A boolean expression that returns true for valid data 
 ///    and false for invalid data. 
 ///    The expression may refer to the subject of 
 ///    the constraint as __subject__.
-type UsingExpression = Expression;
-// impl ManuallyDrop for Expression 
-#[derive(Builder, Debug, Clone)]
+pub type UsingExpression = Expression;
+
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
 pub struct Subcommand {
     
     #[builder(setter(strip_option), default)]
@@ -280,68 +877,121 @@ pub struct Subcommand {
 
 }
 
-#[derive(Builder, Debug, Clone)]
+/// `index on (<expr>)`, where `<expr>` is evaluated against
+/// each object of the host type, e.g. `__subject__.name` or
+/// `(.first_name, .last_name)`.
+#[derive(Builder, Debug, Clone, Serialize, Deserialize)]
 pub struct Index {
 
     #[builder(setter(into))]
-    pub ident: String,
+    pub expr: Expression,
 
-    #[builder(setter(strip_option), default)]
-    pub alias: Option<Annotation>
+    #[builder(setter(into, strip_option), default)]
+    pub annotations: Option<Box<Vec<Annotation>>>
 
 }
 
-#[derive(Builder, Debug, Clone, PartialEq)]
-pub struct Function<'poly, R, S> 
-    where R: FuncRet<'poly>,
-          S: FuncScope + FuncRet<'poly>
-{
+/// A function signature, as declared with `function name(...) -> ...`
+/// and as introspected from `schema::Function`.
+#[derive(Builder, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct Function {
     #[builder(setter(into))]
     pub ident: String,
 
-    #[builder(setter(strip_option))]
-    pub args:  Option<Box<Vec<ArgSpec>>>,
+    #[builder(default)]
+    pub params: Vec<Parameter>,
 
-    #[builder(setter(into, strip_option))]
-    pub ret_type: Option<Box<&'poly R>>,
+    #[builder(setter(into))]
+    pub return_type: String,
 
-    #[builder(setter(strip_option))]
-    pub scope: Option<Box<&'poly S>>
-}
+    #[builder(default = "TypeModifier::Singleton")]
+    pub return_typemod: TypeModifier,
 
-/// `edgemorph::FuncRet<'c>`
-/// 
-/// `FuncRet<'poly>` elides a second-order lifetime bound, such that
-/// `'poly` must outlive both `'a + 'b + 'c`: the lifetime of both a `Type`'s or 
-/// `self.extends` supertype, in addition to `'d`
-/// `Link`'s references to 
-pub trait FuncRet<'poly> {
-    fn from<'a: 'poly, 'b: 'poly, 'c: 'poly, 'd: 'poly>(ty: &'c Type<'a, 'b, 'c, 'd>) -> Set<Type<'a, 'b, 'c, 'd>>;
+    #[builder(default = "Volatility::Volatile")]
+    pub volatility: Volatility,
+
+    #[builder(default = "Language::EdgeQL")]
+    pub language: Language,
+
+    /// The `using (...)` body of an EdgeQL function.
+    #[builder(setter(into, strip_option), default)]
+    pub body: Option<Expression>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub annotations: Option<Box<Vec<Annotation>>>
 }
 
+impl Function {
+    pub fn positional(&self) -> impl Iterator<Item = &Parameter> {
+        self.params.iter().filter(|param| param.kind == ParameterKind::Positional)
+    }
+
+    pub fn variadic(&self) -> Option<&Parameter> {
+        self.params.iter().find(|param| param.is_variadic())
+    }
 
-#[repr(C)]
-pub union Statement {
-    pub(crate) uxpr: ManuallyDrop<UsingExpression>,
-    pub(crate) axpr: ManuallyDrop<AliasExpr>,
-    pub(crate) expr: ManuallyDrop<Expression>,
-    pub(crate) subc: ManuallyDrop<Subcommand>,
-    pub(crate) cons: ManuallyDrop<Constraint>
+    pub fn named_only(&self) -> impl Iterator<Item = &Parameter> {
+        self.params.iter().filter(|param| param.kind == ParameterKind::NamedOnly)
+    }
 }
 
-impl Drop for Statement {
-    fn drop(&mut self) {
-        unsafe {
-            ManuallyDrop::drop(&mut self.cons);
-            ManuallyDrop::drop(&mut self.expr);
-            ManuallyDrop::drop(&mut self.subc);
-            ManuallyDrop::drop(&mut self.axpr);
-            ManuallyDrop::drop(&mut self.uxpr);
+impl FunctionBuilder {
+    /// Verify that the function has a name and a well-formed parameter
+    /// list: positional parameters first, then at most one variadic one,
+    /// then named-only ones, with no required positional parameter after
+    /// one with a default, and no name used twice.
+    fn validate(&self) -> Result<(), String> {
+        let ident = self.ident.as_deref().unwrap_or_default();
+        if self.ident.as_deref() == Some("") {
+            return Err("`Function.ident` must not be an empty String.".to_string());
+        }
+        let params = self.params.as_deref().unwrap_or(&[]);
+        let rank = |kind: ParameterKind| match kind {
+            ParameterKind::Positional => 0,
+            ParameterKind::Variadic   => 1,
+            ParameterKind::NamedOnly  => 2
+        };
+        for (i, param) in params.iter().enumerate() {
+            let earlier = &params[..i];
+            if earlier.iter().any(|other| other.ident == param.ident) {
+                return Err(format!("`{}` has two parameters called `{}`.", ident, param.ident));
+            }
+            if earlier.iter().any(|other| rank(other.kind) > rank(param.kind)) {
+                return Err(format!(
+                    "Parameter `{}` of `{}` is out of order: positional parameters come first, \
+                     then the variadic one, then named-only ones.", param.ident, ident));
+            }
+            if param.is_variadic() && earlier.iter().any(|other| other.is_variadic()) {
+                return Err(format!("`{}` has more than one variadic parameter.", ident));
+            }
+            if param.is_variadic() && param.default.is_some() {
+                return Err(format!("Variadic parameter `{}` of `{}` cannot have a default.", param.ident, ident));
+            }
+            let follows_default = earlier.iter()
+                .any(|other| other.kind == ParameterKind::Positional && other.default.is_some());
+            if param.kind == ParameterKind::Positional && param.default.is_none() && follows_default {
+                return Err(format!(
+                    "Required parameter `{}` of `{}` follows one with a default.", param.ident, ident));
+            }
         }
+        if matches!(self.language, None | Some(Language::EdgeQL)) && matches!(self.body, None | Some(None)) {
+            return Err(format!("EdgeQL function `{}` has no body.", ident));
+        }
+        Ok(())
     }
 }
 
-pub trait FuncScope {
-    fn from(stmts: &[Statement]) -> Vec<Box<Self>>;
+/// A statement of a schema or of a migration script, for code that
+/// handles the different kinds of EdgeQL together. See `crate::visit`
+/// for walking them.
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Using(UsingExpression),
+    Alias(AliasExpr),
+    Expression(Expression),
+    Subcommand(Subcommand),
+    Constraint(Constraint),
+    /// A query, e.g. `SELECT User { name } FILTER .active`.
+    Query(Expr)
 }
-
@@ -0,0 +1,656 @@
+//! DDL that migrates a database from one schema to another.
+//!
+//! Steps are ordered so that everything a statement refers to exists
+//! when it runs: declarations and types are created before the
+//! pointers that use them, and pointers are dropped before the types
+//! and declarations they use.
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::datastructures::{
+    list, AbstractAnnotation, Alias, Annotation, Constraint, Expression, Function, Link, Module, Parameter,
+    ParameterKind, Property, Type, TypeModifier, Volatility
+};
+use crate::diff::{diff, Change, Location};
+use crate::functions::signature;
+
+/// What running a step may do to the data already in the database.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum Risk {
+    /// Cannot fail or lose data.
+    Safe,
+    /// Existing data has to be filled in or converted first, e.g. when
+    /// a pointer becomes required or a new constraint is added.
+    NeedsBackfill,
+    /// Drops data.
+    Destructive
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Risk::Safe          => "safe",
+            Risk::NeedsBackfill => "needs backfill",
+            Risk::Destructive   => "destructive"
+        })
+    }
+}
+
+/// One DDL statement of a migration, with the change it makes.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub ddl:    String,
+    pub risk:   Risk,
+    pub reason: String,
+    /// DDL that undoes the step, or `None` when it cannot be undone
+    /// without the data it drops.
+    #[serde(default)]
+    pub down:   Option<String>
+}
+
+impl Step {
+    fn new(ddl: String, risk: Risk, reason: &Change) -> Step {
+        Step { ddl, risk, reason: reason.to_string(), down: down(reason) }
+    }
+
+    /// A step that makes part of `reason`, undone by `undo`.
+    fn part(ddl: String, risk: Risk, reason: &Change, undo: &Change) -> Step {
+        Step { down: down(undo), ..Step::new(ddl, risk, reason) }
+    }
+}
+
+/// The steps that migrate a database with schema `old` to `new`.
+pub fn migration(old: &Module, new: &Module) -> Vec<Step> {
+    generate(&diff(old, new))
+}
+
+/// Turn a change list into ordered DDL steps.
+pub fn generate(changes: &[Change]) -> Vec<Step> {
+    let mut phases: Vec<Vec<Step>> = vec![Vec::new(); Phase::Count as usize];
+    let mut push = |phase: Phase, step: Step| phases[phase as usize].push(step);
+    let added_types: Vec<&Type> = changes.iter()
+        .filter_map(|change| match change { Change::TypeAdded(ty) => Some(ty), _ => None })
+        .collect();
+    let removed_types: Vec<&Type> = changes.iter()
+        .filter_map(|change| match change { Change::TypeRemoved(ty) => Some(ty), _ => None })
+        .collect();
+    for ty in by_bases(&added_types) {
+        let change = Change::TypeAdded(ty.clone());
+        push(Phase::CreateTypes, Step::new(create_type(ty), Risk::Safe, &change));
+        let at = Location::of(ty);
+        // The pointers, constraints and indices of the new type are
+        // undone one by one, before the type is dropped.
+        for prop in list(&ty.properties) {
+            let phase = if prop.is_computable() { Phase::CreateComputables } else { Phase::CreatePointers };
+            let undo = Change::PropertyAdded { at: at.property(&prop.ident), prop: prop.clone() };
+            push(phase, Step::part(alter(&at, &create_property(prop)), Risk::Safe, &change, &undo));
+        }
+        for link in list(&ty.links) {
+            let phase = if link.is_computable() { Phase::CreateComputables } else { Phase::CreatePointers };
+            let undo = Change::LinkAdded { at: at.link(&link.ident), link: link.clone() };
+            push(phase, Step::part(alter(&at, &create_link(link)), Risk::Safe, &change, &undo));
+        }
+        // A scalar's constraints are part of its declaration.
+        for constraint in list(&ty.constraints).iter().filter(|_| !ty.scalar) {
+            let undo = Change::ConstraintAdded { at: at.clone(), constraint: constraint.clone() };
+            let ddl = alter(&at, &create_constraint(constraint));
+            push(Phase::Constrain, Step::part(ddl, Risk::Safe, &change, &undo));
+        }
+        for index in list(&ty.indices) {
+            let undo = Change::IndexAdded { ty: ty.ident.clone(), index: index.clone() };
+            let ddl = alter(&at, &format!("CREATE INDEX ON ({})", index.expr.ident));
+            push(Phase::Constrain, Step::part(ddl, Risk::Safe, &change, &undo));
+        }
+    }
+    // Pointers between removed types are dropped before any of the
+    // types, which can then go in any order, even when they link to
+    // each other in a cycle.
+    for ty in &removed_types {
+        let change = Change::TypeRemoved((*ty).clone());
+        let at = Location::of(ty);
+        let removed = |target: &Option<String>| {
+            removed_types.iter().any(|other| other.ident != ty.ident && target.as_ref() == Some(&other.ident))
+        };
+        let props = list(&ty.properties).iter()
+            .filter(|prop| removed(&prop.target))
+            .map(|prop| Change::PropertyRemoved { at: at.property(&prop.ident), prop: prop.clone() });
+        let links = list(&ty.links).iter()
+            .filter(|link| removed(&link.target))
+            .map(|link| Change::LinkRemoved { at: at.link(&link.ident), link: link.clone() });
+        for undo in props.chain(links).collect::<Vec<_>>() {
+            if let Some((phase, ddl, risk)) = step(&undo) {
+                push(phase, Step::part(ddl, risk, &change, &undo));
+            }
+        }
+    }
+    for ty in by_bases(&removed_types).into_iter().rev() {
+        let change = Change::TypeRemoved(ty.clone());
+        let risk = if ty.scalar { Risk::Safe } else { Risk::Destructive };
+        push(Phase::DropTypes, Step::new(drop_type(ty), risk, &change));
+    }
+    for change in changes {
+        if let Some((phase, ddl, risk)) = step(change) {
+            push(phase, Step::new(ddl, risk, change));
+        }
+    }
+    phases.into_iter().flatten().collect()
+}
+
+/// The order in which steps run.
+#[derive(Clone, Copy)]
+enum Phase {
+    Declare,
+    Rename,
+    DropDependents,
+    DropConstraints,
+    CreateTypes,
+    CreatePointers,
+    CreateComputables,
+    Alter,
+    Constrain,
+    CreateDependents,
+    DropPointers,
+    DropTypes,
+    Undeclare,
+    Count
+}
+
+/// The step for every change except added and removed types,
+/// which `generate` orders by their bases.
+fn step(change: &Change) -> Option<(Phase, String, Risk)> {
+    let step = match change {
+        Change::TypeAdded(_) | Change::TypeRemoved(_) => return None,
+        // EdgeDB has no DDL for making a type final.
+        Change::FinalChanged { .. } => return None,
+        Change::AnnotationDeclared(annotation) => (Phase::Declare, declare_annotation(annotation), Risk::Safe),
+        Change::AbstractConstraintAdded(constraint) => {
+            (Phase::Declare, create_abstract_constraint(constraint), Risk::Safe)
+        },
+        Change::AbstractPropertyAdded(prop) => (Phase::Declare, create_abstract(&create_property(prop)), Risk::Safe),
+        Change::AbstractLinkAdded(link) => (Phase::Declare, create_abstract(&create_link(link)), Risk::Safe),
+        Change::TypeRenamed { from, to, scalar } => {
+            let kind = if *scalar { "SCALAR TYPE" } else { "TYPE" };
+            (Phase::Rename, format!("ALTER {} {} RENAME TO {};", kind, from, to), Risk::Safe)
+        },
+        Change::PointerRenamed { at, to } => (Phase::Rename, alter(at, &format!("RENAME TO {}", to)), Risk::Safe),
+        Change::FunctionRemoved(func) => (Phase::DropDependents, drop_function(func), Risk::Safe),
+        Change::AliasRemoved(alias) => (Phase::DropDependents, format!("DROP ALIAS {};", alias.ident), Risk::Safe),
+        Change::IndexDropped { ty, index } => {
+            let ddl = alter(&Location::ty(ty), &format!("DROP INDEX ON ({})", index.expr.ident));
+            (Phase::DropConstraints, ddl, Risk::Safe)
+        },
+        Change::ConstraintRemoved { at, constraint } => {
+            (Phase::DropConstraints, alter(at, &format!("DROP {}", constraint_ref(constraint))), Risk::Safe)
+        },
+        Change::PropertyAdded { at, prop } => {
+            let risk = if prop.required && !prop.is_computable() && prop.default.is_none() {
+                Risk::NeedsBackfill
+            } else {
+                Risk::Safe
+            };
+            let phase = if prop.is_computable() { Phase::CreateComputables } else { Phase::CreatePointers };
+            (phase, alter(&parent(at), &create_property(prop)), risk)
+        },
+        Change::LinkAdded { at, link } => {
+            let risk = if link.required && !link.is_computable() && link.default.is_none() {
+                Risk::NeedsBackfill
+            } else {
+                Risk::Safe
+            };
+            let phase = if link.is_computable() { Phase::CreateComputables } else { Phase::CreatePointers };
+            (phase, alter(&parent(at), &create_link(link)), risk)
+        },
+        Change::AbstractChanged { ty, abs } => {
+            let (ddl, risk) = if *abs { ("SET ABSTRACT", Risk::Destructive) } else { ("RESET ABSTRACT", Risk::Safe) };
+            (Phase::Alter, alter(&Location::ty(ty), ddl), risk)
+        },
+        Change::BasesChanged { ty, old, new } => {
+            let mut ddl: Vec<String> = new.iter()
+                .filter(|base| !old.contains(base))
+                .map(|base| format!("EXTENDING {}", base))
+                .collect();
+            let dropped: Vec<String> = old.iter()
+                .filter(|base| !new.contains(base))
+                .map(|base| format!("DROP EXTENDING {}", base))
+                .collect();
+            let risk = if dropped.is_empty() { Risk::Safe } else { Risk::Destructive };
+            ddl.extend(dropped);
+            (Phase::Alter, alter(&Location::ty(ty), &ddl.join("; ")), risk)
+        },
+        Change::CardinalityChanged { at, multi } => {
+            let (ddl, risk) = if *multi { ("SET MULTI", Risk::Safe) } else { ("SET SINGLE", Risk::Destructive) };
+            (Phase::Alter, alter(at, ddl), risk)
+        },
+        Change::RequiredChanged { at, required } => {
+            let (ddl, risk) = if *required {
+                ("SET REQUIRED", Risk::NeedsBackfill)
+            } else {
+                ("SET OPTIONAL", Risk::Safe)
+            };
+            (Phase::Alter, alter(at, ddl), risk)
+        },
+        Change::ReadonlyChanged { at, readonly } => {
+            (Phase::Alter, alter(at, &format!("SET readonly := {}", readonly)), Risk::Safe)
+        },
+        Change::TargetChanged { at, new, .. } => {
+            // Links to objects of the old type are lost; property
+            // values have to be cast to the new type.
+            let risk = if at.is_link && at.link_property.is_none() { Risk::Destructive } else { Risk::NeedsBackfill };
+            (Phase::Alter, alter(at, &format!("SET TYPE {}", new.as_deref().unwrap_or("anytype"))), risk)
+        },
+        Change::ExprChanged { at, old, new } => match (old, new) {
+            (_, Some(expr)) => {
+                let risk = if old.is_none() { Risk::Destructive } else { Risk::Safe };
+                (Phase::Alter, alter(at, &format!("USING ({})", expr.ident)), risk)
+            },
+            (_, None) => (Phase::Alter, alter(at, "RESET EXPRESSION"), Risk::NeedsBackfill)
+        },
+        Change::DefaultChanged { at, new, .. } => {
+            let ddl = match new {
+                Some(expr) => format!("SET default := ({})", expr.ident),
+                None => "RESET default".to_string()
+            };
+            (Phase::Alter, alter(at, &ddl), Risk::Safe)
+        },
+        Change::AnnotationChanged { at, ident, new, .. } => {
+            (Phase::Alter, alter(at, &format!("ALTER ANNOTATION {} := {}", ident, quote(new))), Risk::Safe)
+        },
+        Change::AnnotationAdded { at, annotation } => {
+            (Phase::Alter, alter(at, &create_annotation(annotation)), Risk::Safe)
+        },
+        Change::AnnotationRemoved { at, annotation } => {
+            (Phase::Alter, alter(at, &format!("DROP ANNOTATION {}", annotation.ident)), Risk::Safe)
+        },
+        Change::ConstraintAdded { at, constraint } => {
+            (Phase::Constrain, alter(at, &create_constraint(constraint)), Risk::NeedsBackfill)
+        },
+        Change::IndexAdded { ty, index } => {
+            (Phase::Constrain, alter(&Location::ty(ty), &format!("CREATE INDEX ON ({})", index.expr.ident)), Risk::Safe)
+        },
+        Change::AliasAdded(alias) => (Phase::CreateDependents, create_alias(alias), Risk::Safe),
+        Change::AliasChanged { new, .. } => {
+            let expr = new.alias_expr.expression().map_or(String::new(), |expr| expr.ident);
+            (Phase::CreateDependents, format!("ALTER ALIAS {} USING ({});", new.ident, expr), Risk::Safe)
+        },
+        Change::FunctionAdded(func) => (Phase::CreateDependents, create_function(func), Risk::Safe),
+        Change::FunctionChanged { old, new } => {
+            (Phase::CreateDependents, format!("{}\n{}", drop_function(old), create_function(new)), Risk::Safe)
+        },
+        Change::PropertyRemoved { at, prop } => {
+            let risk = if prop.is_computable() { Risk::Safe } else { Risk::Destructive };
+            (Phase::DropPointers, alter(&parent(at), &format!("DROP PROPERTY {}", prop.ident)), risk)
+        },
+        Change::LinkRemoved { at, link } => {
+            let risk = if link.is_computable() { Risk::Safe } else { Risk::Destructive };
+            (Phase::DropPointers, alter(&parent(at), &format!("DROP LINK {}", link.ident)), risk)
+        },
+        Change::AbstractConstraintRemoved(c) => {
+            (Phase::Undeclare, format!("DROP ABSTRACT CONSTRAINT {};", c.ident), Risk::Safe)
+        },
+        Change::AbstractPropertyRemoved(p) => {
+            (Phase::Undeclare, format!("DROP ABSTRACT PROPERTY {};", p.ident), Risk::Safe)
+        },
+        Change::AbstractLinkRemoved(l) => (Phase::Undeclare, format!("DROP ABSTRACT LINK {};", l.ident), Risk::Safe),
+        Change::AnnotationUndeclared(a) => {
+            (Phase::Undeclare, format!("DROP ABSTRACT ANNOTATION {};", a.ident), Risk::Safe)
+        }
+    };
+    Some(step)
+}
+
+/// DDL that undoes `change`, if it can be undone.
+fn down(change: &Change) -> Option<String> {
+    match change.inverse()? {
+        // Only removed scalars are added back; see `Change::inverse`.
+        Change::TypeAdded(ty) => Some(create_type(&ty)),
+        Change::TypeRemoved(ty) => Some(drop_type(&ty)),
+        inverse => step(&inverse).map(|(_, ddl, _)| ddl)
+    }
+}
+
+/// Types ordered so that every type comes after the bases it extends
+/// among `types`.
+fn by_bases<'a>(types: &[&'a Type]) -> Vec<&'a Type> {
+    let mut ordered: Vec<&Type> = Vec::new();
+    let mut pending: Vec<&Type> = types.to_vec();
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|ty| {
+            ty.extends.iter().flatten().all(|base| {
+                !pending.iter().any(|other| other.ident == *base && other.ident != ty.ident)
+            })
+        });
+        // A cycle cannot be ordered; keep the declared order for it.
+        ordered.push(pending.remove(ready.unwrap_or(0)));
+    }
+    ordered
+}
+
+/// The location of the type or link that `at` belongs to.
+fn parent(at: &Location) -> Location {
+    match at.link_property {
+        Some(_) => Location { link_property: None, ..at.clone() },
+        None => Location { pointer: None, is_link: false, ..at.clone() }
+    }
+}
+
+/// Wrap `ddl` in the `ALTER` blocks that lead to `at`.
+fn alter(at: &Location, ddl: &str) -> String {
+    let mut ddl = format!("{};", ddl);
+    if let Some(prop) = &at.link_property {
+        ddl = format!("ALTER PROPERTY {} {{ {} }};", prop, ddl);
+    }
+    if let Some(pointer) = &at.pointer {
+        let kind = if at.is_link { "LINK" } else { "PROPERTY" };
+        ddl = format!("ALTER {} {} {{ {} }};", kind, pointer, ddl);
+    }
+    let kind = if at.scalar { "SCALAR TYPE" } else { "TYPE" };
+    format!("ALTER {} {} {{ {} }};", kind, at.ty, ddl)
+}
+
+fn drop_type(ty: &Type) -> String {
+    format!("DROP {} {};", if ty.scalar { "SCALAR TYPE" } else { "TYPE" }, ty.ident)
+}
+
+fn create_type(ty: &Type) -> String {
+    let mut ddl = String::from("CREATE ");
+    if ty.abs {
+        ddl.push_str("ABSTRACT ");
+    }
+    ddl.push_str(if ty.scalar { "SCALAR TYPE " } else { "TYPE " });
+    ddl.push_str(&ty.ident);
+    if let Some(bases) = ty.extends.as_ref().filter(|bases| !bases.is_empty()) {
+        ddl.push_str(&format!(" EXTENDING {}", bases.join(", ")));
+    }
+    let mut body: Vec<String> = list(&ty.annotations).iter().map(create_annotation).collect();
+    if ty.scalar {
+        body.extend(list(&ty.constraints).iter().map(create_constraint));
+    }
+    format!("{}{};", ddl, block(&body))
+}
+
+fn create_property(prop: &Property) -> String {
+    let mut body: Vec<String> = Vec::new();
+    if let Some(default) = &prop.default {
+        body.push(format!("SET default := ({})", default.ident));
+    }
+    if prop.readonly && !prop.is_computable() {
+        body.push("SET readonly := true".to_string());
+    }
+    body.extend(list(&prop.constraints).iter().map(create_constraint));
+    body.extend(list(&prop.annotations).iter().map(create_annotation));
+    let head = pointer_head(
+        "PROPERTY", &prop.ident, prop.required, prop.multi, &prop.target, &prop.expr, &prop.extends);
+    format!("{}{}", head, block(&body))
+}
+
+fn create_link(link: &Link) -> String {
+    let mut body: Vec<String> = Vec::new();
+    if let Some(default) = &link.default {
+        body.push(format!("SET default := ({})", default.ident));
+    }
+    if link.readonly && !link.is_computable() {
+        body.push("SET readonly := true".to_string());
+    }
+    body.extend(list(&link.properties).iter().map(create_property));
+    body.extend(list(&link.constraints).iter().map(create_constraint));
+    body.extend(list(&link.annotations).iter().map(create_annotation));
+    let head = pointer_head(
+        "LINK", &link.ident, link.required, link.multi, &link.target, &link.expr, &link.extends);
+    format!("{}{}", head, block(&body))
+}
+
+fn pointer_head(
+    kind: &str,
+    ident: &str,
+    required: bool,
+    multi: bool,
+    target: &Option<String>,
+    expr: &Option<Expression>,
+    extends: &Option<Vec<String>>) -> String
+{
+    let mut ddl = String::from("CREATE ");
+    if required {
+        ddl.push_str("REQUIRED ");
+    }
+    if multi {
+        ddl.push_str("MULTI ");
+    }
+    ddl.push_str(&format!("{} {}", kind, ident));
+    if let Some(bases) = extends.as_ref().filter(|bases| !bases.is_empty()) {
+        ddl.push_str(&format!(" EXTENDING {}", bases.join(", ")));
+    }
+    match (expr, target) {
+        (Some(expr), _) => ddl.push_str(&format!(" := ({})", expr.ident)),
+        (None, Some(target)) => ddl.push_str(&format!(" -> {}", target)),
+        (None, None) => {}
+    }
+    ddl
+}
+
+fn create_abstract(ddl: &str) -> String {
+    format!("{};", ddl.replacen("CREATE ", "CREATE ABSTRACT ", 1))
+}
+
+fn constraint_ref(constraint: &Constraint) -> String {
+    let args: Vec<&str> = list(&constraint.args).iter().map(|arg| arg.ident.as_str()).collect();
+    let mut ddl = format!("CONSTRAINT {}", constraint.ident);
+    if !args.is_empty() {
+        ddl.push_str(&format!("({})", args.join(", ")));
+    }
+    if let Some(on) = &constraint.on {
+        ddl.push_str(&format!(" ON ({})", on.ident));
+    }
+    ddl
+}
+
+fn create_constraint(constraint: &Constraint) -> String {
+    let delegated = if constraint.delegated { "DELEGATED " } else { "" };
+    let body: Vec<String> = constraint.errmessage().iter()
+        .map(|errmessage| format!("SET errmessage := {}", quote(errmessage)))
+        .collect();
+    format!("CREATE {}{}{}", delegated, constraint_ref(constraint), block(&body))
+}
+
+fn create_abstract_constraint(constraint: &Constraint) -> String {
+    let params: Vec<String> = constraint.params.iter().flatten().map(parameter).collect();
+    let mut ddl = format!("CREATE ABSTRACT CONSTRAINT {}", constraint.ident);
+    if !params.is_empty() {
+        ddl.push_str(&format!("({})", params.join(", ")));
+    }
+    if let Some(bases) = constraint.extends.as_ref().filter(|bases| !bases.is_empty()) {
+        ddl.push_str(&format!(" EXTENDING {}", bases.join(", ")));
+    }
+    let mut body: Vec<String> = Vec::new();
+    if let Some(using) = constraint.using() {
+        body.push(format!("USING ({})", using.ident));
+    }
+    if let Some(errmessage) = constraint.errmessage() {
+        body.push(format!("SET errmessage := {}", quote(errmessage)));
+    }
+    format!("{}{};", ddl, block(&body))
+}
+
+fn create_annotation(annotation: &Annotation) -> String {
+    format!("CREATE ANNOTATION {} := {}", annotation.ident, quote(&annotation.value))
+}
+
+fn declare_annotation(annotation: &AbstractAnnotation) -> String {
+    let inheritable = if annotation.inheritable { "INHERITABLE " } else { "" };
+    format!("CREATE ABSTRACT {}ANNOTATION {};", inheritable, annotation.ident)
+}
+
+fn create_alias(alias: &Alias) -> String {
+    let expr = alias.alias_expr.expression().map_or(String::new(), |expr| expr.ident);
+    let body: Vec<String> = list(&alias.annotations).iter().map(create_annotation).collect();
+    if body.is_empty() {
+        format!("CREATE ALIAS {} := ({});", alias.ident, expr)
+    } else {
+        let mut body = body;
+        body.insert(0, format!("USING ({})", expr));
+        format!("CREATE ALIAS {}{};", alias.ident, block(&body))
+    }
+}
+
+fn create_function(func: &Function) -> String {
+    let mut body: Vec<String> = Vec::new();
+    let volatility = match func.volatility {
+        Volatility::Immutable => Some("Immutable"),
+        Volatility::Stable    => Some("Stable"),
+        Volatility::Volatile  => None
+    };
+    if let Some(volatility) = volatility {
+        body.push(format!("SET volatility := {}", quote(volatility)));
+    }
+    body.extend(list(&func.annotations).iter().map(create_annotation));
+    if let Some(expr) = &func.body {
+        body.push(format!("USING ({})", expr.ident));
+    }
+    format!("CREATE FUNCTION {}{};", signature(func), block(&body))
+}
+
+fn drop_function(func: &Function) -> String {
+    let params: Vec<String> = func.params.iter()
+        .map(|param| parameter(&Parameter { default: None, ..param.clone() }))
+        .collect();
+    format!("DROP FUNCTION {}({});", func.ident, params.join(", "))
+}
+
+fn parameter(param: &Parameter) -> String {
+    let kind = match param.kind {
+        ParameterKind::Positional => "",
+        ParameterKind::NamedOnly  => "NAMED ONLY ",
+        ParameterKind::Variadic   => "VARIADIC "
+    };
+    let typemod = match param.typemod {
+        TypeModifier::Singleton => "",
+        TypeModifier::Optional  => "OPTIONAL ",
+        TypeModifier::SetOf     => "SET OF "
+    };
+    let default = param.default.as_ref().map_or(String::new(), |d| format!(" = {}", d.ident));
+    format!("{}{}: {}{}{}", kind, param.ident, typemod, param.ty, default)
+}
+
+/// ` { a; b; }`, or nothing for an empty body.
+fn block(body: &[String]) -> String {
+    if body.is_empty() {
+        String::new()
+    } else {
+        format!(" {{ {}; }}", body.join("; "))
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{ConstraintBuilder, Expression, LinkBuilder, PropertyBuilder, TypeBuilder};
+    use crate::fixtures::shop;
+    use super::{migration, Risk};
+
+    #[test]
+    fn creates_types_before_their_pointers() {
+        let old = shop();
+        let mut new = shop();
+        let shelf = TypeBuilder::default()
+            .ident("Shelf")
+            .links(Box::new(vec![LinkBuilder::default().ident("items").target("Item").multi(true).build().unwrap()]))
+            .build()
+            .unwrap();
+        let corner = TypeBuilder::default().ident("CornerShelf").extends(vec!["Shelf".to_string()]).build().unwrap();
+        new.types.push(corner);
+        new.types.push(shelf);
+        let shelf_link = LinkBuilder::default().ident("shelf").target("Shelf").build().unwrap();
+        new.types[0].links = Some(Box::new(vec![shelf_link]));
+
+        let ddl: Vec<String> = migration(&old, &new).into_iter().map(|step| step.ddl).collect();
+        assert_eq!(ddl, vec![
+            "CREATE TYPE Shelf;",
+            "CREATE TYPE CornerShelf EXTENDING Shelf;",
+            "ALTER TYPE Shelf { CREATE MULTI LINK items -> Item; };",
+            "ALTER TYPE Item { CREATE LINK shelf -> Shelf; };"
+        ]);
+    }
+
+    #[test]
+    fn drops_links_between_removed_types_first() {
+        let old = {
+            let mut old = shop();
+            let link = |ident: &str, target: &str| LinkBuilder::default().ident(ident).target(target).build().unwrap();
+            for (ident, links) in [("Deck", vec![link("top", "Card")]), ("Card", vec![link("deck", "Deck")])] {
+                old.types.push(TypeBuilder::default().ident(ident).links(Box::new(links)).build().unwrap());
+            }
+            old
+        };
+        let steps = migration(&old, &shop());
+        let ddl: Vec<&str> = steps.iter().map(|step| step.ddl.as_str()).collect();
+        assert_eq!(ddl, vec![
+            "ALTER TYPE Deck { DROP LINK top; };",
+            "ALTER TYPE Card { DROP LINK deck; };",
+            "DROP TYPE Card;",
+            "DROP TYPE Deck;"
+        ]);
+        assert_eq!(steps[0].reason, "remove type `Deck`");
+        assert_eq!(steps[0].risk, Risk::Destructive);
+    }
+
+    #[test]
+    fn alters_scalar_types_as_scalars() {
+        let rating = || {
+            TypeBuilder::default().ident("Rating").scalar(true).extends(vec!["int16".to_string()]).build().unwrap()
+        };
+        let mut old = shop();
+        old.types.push(rating());
+        let mut new = shop();
+        new.types.push(rating());
+        let max_value = ConstraintBuilder::default()
+            .ident("max_value")
+            .args(Box::new(vec![Expression::new("5")]))
+            .build()
+            .unwrap();
+        new.types[1].constraints = Some(Box::new(vec![max_value]));
+
+        let ddl: Vec<String> = migration(&old, &new).into_iter().map(|step| step.ddl).collect();
+        assert_eq!(ddl, vec!["ALTER SCALAR TYPE Rating { CREATE CONSTRAINT max_value(5); };"]);
+        let ddl: Vec<String> = migration(&new, &old).into_iter().map(|step| step.ddl).collect();
+        assert_eq!(ddl, vec!["ALTER SCALAR TYPE Rating { DROP CONSTRAINT max_value(5); };"]);
+    }
+
+    #[test]
+    fn classifies_risky_steps() {
+        let old = shop();
+        let mut new = shop();
+        {
+            let props = new.types[0].properties.as_mut().unwrap();
+            props[1].multi = false;
+            props.push(PropertyBuilder::default().ident("sku").target("str").required(true).build().unwrap());
+            props.remove(0);
+        }
+        let steps = migration(&old, &new);
+        let summary: Vec<(&str, Risk)> = steps.iter().map(|step| (step.ddl.as_str(), step.risk)).collect();
+        assert_eq!(summary, vec![
+            ("ALTER TYPE Item { CREATE REQUIRED PROPERTY sku -> str; };", Risk::NeedsBackfill),
+            ("ALTER TYPE Item { ALTER PROPERTY tags { SET SINGLE; }; };", Risk::Destructive),
+            ("ALTER TYPE Item { DROP PROPERTY name; };", Risk::Destructive)
+        ]);
+        assert_eq!(steps[2].reason, "remove property `Item.name`");
+    }
+
+    #[test]
+    fn undoes_steps_that_drop_no_data() {
+        let old = shop();
+        let mut new = shop();
+        {
+            let props = new.types[0].properties.as_mut().unwrap();
+            props[0].required = false;
+            props[1].multi = false;
+            props.push(PropertyBuilder::default().ident("sku").target("str").build().unwrap());
+        }
+        let down: Vec<Option<String>> = migration(&old, &new).into_iter().map(|step| step.down).collect();
+        assert_eq!(down, vec![
+            Some("ALTER TYPE Item { DROP PROPERTY sku; };".to_string()),
+            Some("ALTER TYPE Item { ALTER PROPERTY name { SET REQUIRED; }; };".to_string()),
+            None
+        ]);
+    }
+}
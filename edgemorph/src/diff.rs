@@ -0,0 +1,621 @@
+//! Structural comparison of two schemas, e.g. the working tree
+//! against the last installed one.
+//!
+//! Items are matched by name, and expressions are compared in their
+//! canonical form, so reformatting a schema produces no changes.
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::datastructures::{
+    list, AbstractAnnotation, Alias, Annotation, Constraint, Expression, Function, Index, Link, Module, Property,
+    Type
+};
+use crate::fingerprint::canonical_expression;
+use crate::functions::signature;
+
+/// Where a change happened: a type, one of its pointers,
+/// or a property of one of its links.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub ty:            String,
+    /// Whether `ty` is a scalar type, which is altered
+    /// with `ALTER SCALAR TYPE`.
+    #[serde(default)]
+    pub scalar:        bool,
+    pub pointer:       Option<String>,
+    /// Whether `pointer` is a link rather than a property.
+    pub is_link:       bool,
+    pub link_property: Option<String>
+}
+
+impl Location {
+    pub fn ty(ty: &str) -> Location {
+        Location { ty: ty.to_string(), scalar: false, pointer: None, is_link: false, link_property: None }
+    }
+
+    pub fn of(ty: &Type) -> Location {
+        Location { scalar: ty.scalar, ..Location::ty(&ty.ident) }
+    }
+
+    pub fn property(&self, ident: &str) -> Location {
+        Location { pointer: Some(ident.to_string()), is_link: false, ..self.clone() }
+    }
+
+    pub fn link(&self, ident: &str) -> Location {
+        Location { pointer: Some(ident.to_string()), is_link: true, ..self.clone() }
+    }
+
+    pub fn link_property(&self, ident: &str) -> Location {
+        Location { link_property: Some(ident.to_string()), ..self.clone() }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ty)?;
+        if let Some(pointer) = &self.pointer {
+            write!(f, ".{}", pointer)?;
+        }
+        if let Some(prop) = &self.link_property {
+            write!(f, "@{}", prop)?;
+        }
+        Ok(())
+    }
+}
+
+/// One difference between two schemas. Changes that add or remove
+/// an item carry all of it; changes to an item carry the new value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Change {
+    TypeAdded(Type),
+    TypeRemoved(Type),
+    TypeRenamed { from: String, to: String, scalar: bool },
+    /// `at` names the pointer by its old name.
+    PointerRenamed { at: Location, to: String },
+    AbstractChanged { ty: String, abs: bool },
+    FinalChanged { ty: String, is_final: bool },
+    BasesChanged { ty: String, old: Vec<String>, new: Vec<String> },
+    /// A property of a type, or of a link when `at` has a `link_property`.
+    PropertyAdded { at: Location, prop: Property },
+    PropertyRemoved { at: Location, prop: Property },
+    LinkAdded { at: Location, link: Link },
+    LinkRemoved { at: Location, link: Link },
+    CardinalityChanged { at: Location, multi: bool },
+    RequiredChanged { at: Location, required: bool },
+    ReadonlyChanged { at: Location, readonly: bool },
+    TargetChanged { at: Location, old: Option<String>, new: Option<String> },
+    ExprChanged { at: Location, old: Option<Expression>, new: Option<Expression> },
+    DefaultChanged { at: Location, old: Option<Expression>, new: Option<Expression> },
+    ConstraintAdded { at: Location, constraint: Constraint },
+    ConstraintRemoved { at: Location, constraint: Constraint },
+    IndexAdded { ty: String, index: Index },
+    IndexDropped { ty: String, index: Index },
+    AnnotationAdded { at: Location, annotation: Annotation },
+    AnnotationRemoved { at: Location, annotation: Annotation },
+    AnnotationChanged { at: Location, ident: String, old: String, new: String },
+    AbstractConstraintAdded(Constraint),
+    AbstractConstraintRemoved(Constraint),
+    AbstractPropertyAdded(Property),
+    AbstractPropertyRemoved(Property),
+    AbstractLinkAdded(Link),
+    AbstractLinkRemoved(Link),
+    AnnotationDeclared(AbstractAnnotation),
+    AnnotationUndeclared(AbstractAnnotation),
+    AliasAdded(Alias),
+    AliasRemoved(Alias),
+    AliasChanged { old: Alias, new: Alias },
+    FunctionAdded(Function),
+    FunctionRemoved(Function),
+    FunctionChanged { old: Function, new: Function }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let toggle = |on: bool, yes: &'static str, no: &'static str| if on { yes } else { no };
+        let text = |expr: &Option<Expression>| expr.as_ref().map_or("{}".to_string(), |e| e.ident.clone());
+        match self {
+            Change::TypeAdded(ty) => write!(f, "add type `{}`", ty.ident),
+            Change::TypeRemoved(ty) => write!(f, "remove type `{}`", ty.ident),
+            Change::TypeRenamed { from, to, .. } => write!(f, "rename type `{}` to `{}`", from, to),
+            Change::PointerRenamed { at, to } => write!(
+                f, "rename {} `{}` to `{}`", toggle(at.is_link, "link", "property"), at, to),
+            Change::AbstractChanged { ty, abs } => write!(f, "make `{}` {}", ty, toggle(*abs, "abstract", "concrete")),
+            Change::FinalChanged { ty, is_final } => write!(
+                f, "make `{}` {}", ty, toggle(*is_final, "final", "extensible")),
+            Change::BasesChanged { ty, old, new } => write!(
+                f, "change bases of `{}` from ({}) to ({})", ty, old.join(", "), new.join(", ")),
+            Change::PropertyAdded { at, .. } => write!(f, "add property `{}`", at),
+            Change::PropertyRemoved { at, .. } => write!(f, "remove property `{}`", at),
+            Change::LinkAdded { at, .. } => write!(f, "add link `{}`", at),
+            Change::LinkRemoved { at, .. } => write!(f, "remove link `{}`", at),
+            Change::CardinalityChanged { at, multi } => write!(
+                f, "make `{}` {}", at, toggle(*multi, "multi", "single")),
+            Change::RequiredChanged { at, required } => write!(
+                f, "make `{}` {}", at, toggle(*required, "required", "optional")),
+            Change::ReadonlyChanged { at, readonly } => write!(
+                f, "make `{}` {}", at, toggle(*readonly, "readonly", "writable")),
+            Change::TargetChanged { at, old, new } => write!(
+                f, "change target of `{}` from `{}` to `{}`",
+                at, old.as_deref().unwrap_or("?"), new.as_deref().unwrap_or("?")),
+            Change::ExprChanged { at, old, new } => write!(
+                f, "change expression of `{}` from {} to {}", at, text(old), text(new)),
+            Change::DefaultChanged { at, old, new } => write!(
+                f, "change default of `{}` from {} to {}", at, text(old), text(new)),
+            Change::ConstraintAdded { at, constraint } => write!(
+                f, "add constraint {} to `{}`", describe_constraint(constraint), at),
+            Change::ConstraintRemoved { at, constraint } => write!(
+                f, "remove constraint {} from `{}`", describe_constraint(constraint), at),
+            Change::IndexAdded { ty, index } => write!(f, "add index on ({}) to `{}`", index.expr.ident, ty),
+            Change::IndexDropped { ty, index } => write!(f, "drop index on ({}) from `{}`", index.expr.ident, ty),
+            Change::AnnotationAdded { at, annotation } => write!(
+                f, "set annotation `{}` of `{}` to '{}'", annotation.ident, at, annotation.value),
+            Change::AnnotationRemoved { at, annotation } => write!(
+                f, "remove annotation `{}` from `{}`", annotation.ident, at),
+            Change::AnnotationChanged { at, ident, old, new } => write!(
+                f, "change annotation `{}` of `{}` from '{}' to '{}'", ident, at, old, new),
+            Change::AbstractConstraintAdded(c) => write!(f, "add abstract constraint `{}`", c.ident),
+            Change::AbstractConstraintRemoved(c) => write!(f, "remove abstract constraint `{}`", c.ident),
+            Change::AbstractPropertyAdded(p) => write!(f, "add abstract property `{}`", p.ident),
+            Change::AbstractPropertyRemoved(p) => write!(f, "remove abstract property `{}`", p.ident),
+            Change::AbstractLinkAdded(l) => write!(f, "add abstract link `{}`", l.ident),
+            Change::AbstractLinkRemoved(l) => write!(f, "remove abstract link `{}`", l.ident),
+            Change::AnnotationDeclared(a) => write!(f, "declare annotation `{}`", a.ident),
+            Change::AnnotationUndeclared(a) => write!(f, "remove annotation declaration `{}`", a.ident),
+            Change::AliasAdded(alias) => write!(f, "add alias `{}`", alias.ident),
+            Change::AliasRemoved(alias) => write!(f, "remove alias `{}`", alias.ident),
+            Change::AliasChanged { new, .. } => write!(f, "change alias `{}`", new.ident),
+            Change::FunctionAdded(func) => write!(f, "add function `{}`", signature(func)),
+            Change::FunctionRemoved(func) => write!(f, "remove function `{}`", signature(func)),
+            Change::FunctionChanged { new, .. } => write!(f, "change function `{}`", signature(new))
+        }
+    }
+}
+
+impl Change {
+    /// The change that undoes this one, or `None` when undoing it
+    /// would have to bring back data that it dropped: removed types
+    /// and stored pointers, single pointers that were multi, and so on.
+    pub fn inverse(&self) -> Option<Change> {
+        let inverse = match self {
+            Change::TypeAdded(ty) => Change::TypeRemoved(ty.clone()),
+            Change::TypeRemoved(ty) if ty.scalar => Change::TypeAdded(ty.clone()),
+            Change::TypeRemoved(_) => return None,
+            Change::TypeRenamed { from, to, scalar } => {
+                Change::TypeRenamed { from: to.clone(), to: from.clone(), scalar: *scalar }
+            },
+            Change::PointerRenamed { at, to } => {
+                let (at, from) = match &at.link_property {
+                    Some(from) => (at.link_property(to), from.clone()),
+                    None => (Location { pointer: Some(to.clone()), ..at.clone() }, at.pointer.clone()?)
+                };
+                Change::PointerRenamed { at, to: from }
+            },
+            Change::AbstractChanged { abs: true, .. } => return None,
+            Change::AbstractChanged { ty, abs } => Change::AbstractChanged { ty: ty.clone(), abs: !abs },
+            Change::FinalChanged { ty, is_final } => Change::FinalChanged { ty: ty.clone(), is_final: !is_final },
+            Change::BasesChanged { ty, old, new } => {
+                if old.iter().any(|base| !new.contains(base)) {
+                    return None;
+                }
+                Change::BasesChanged { ty: ty.clone(), old: new.clone(), new: old.clone() }
+            },
+            Change::PropertyAdded { at, prop } => Change::PropertyRemoved { at: at.clone(), prop: prop.clone() },
+            Change::PropertyRemoved { at, prop } if prop.is_computable() => {
+                Change::PropertyAdded { at: at.clone(), prop: prop.clone() }
+            },
+            Change::LinkAdded { at, link } => Change::LinkRemoved { at: at.clone(), link: link.clone() },
+            Change::LinkRemoved { at, link } if link.is_computable() => {
+                Change::LinkAdded { at: at.clone(), link: link.clone() }
+            },
+            Change::PropertyRemoved { .. } | Change::LinkRemoved { .. } => return None,
+            Change::CardinalityChanged { multi: false, .. } => return None,
+            Change::CardinalityChanged { at, .. } => Change::CardinalityChanged { at: at.clone(), multi: false },
+            Change::RequiredChanged { at, required } => {
+                Change::RequiredChanged { at: at.clone(), required: !required }
+            },
+            Change::ReadonlyChanged { at, readonly } => {
+                Change::ReadonlyChanged { at: at.clone(), readonly: !readonly }
+            },
+            // Objects that the link pointed to are not linked anymore.
+            Change::TargetChanged { at, .. } if at.is_link && at.link_property.is_none() => return None,
+            Change::TargetChanged { at, old, new } => {
+                Change::TargetChanged { at: at.clone(), old: new.clone(), new: old.clone() }
+            },
+            // The stored values were dropped.
+            Change::ExprChanged { old: None, .. } => return None,
+            Change::ExprChanged { at, old, new } => {
+                Change::ExprChanged { at: at.clone(), old: new.clone(), new: old.clone() }
+            },
+            Change::DefaultChanged { at, old, new } => {
+                Change::DefaultChanged { at: at.clone(), old: new.clone(), new: old.clone() }
+            },
+            Change::ConstraintAdded { at, constraint } => {
+                Change::ConstraintRemoved { at: at.clone(), constraint: constraint.clone() }
+            },
+            Change::ConstraintRemoved { at, constraint } => {
+                Change::ConstraintAdded { at: at.clone(), constraint: constraint.clone() }
+            },
+            Change::IndexAdded { ty, index } => Change::IndexDropped { ty: ty.clone(), index: index.clone() },
+            Change::IndexDropped { ty, index } => Change::IndexAdded { ty: ty.clone(), index: index.clone() },
+            Change::AnnotationAdded { at, annotation } => {
+                Change::AnnotationRemoved { at: at.clone(), annotation: annotation.clone() }
+            },
+            Change::AnnotationRemoved { at, annotation } => {
+                Change::AnnotationAdded { at: at.clone(), annotation: annotation.clone() }
+            },
+            Change::AnnotationChanged { at, ident, old, new } => {
+                Change::AnnotationChanged { at: at.clone(), ident: ident.clone(), old: new.clone(), new: old.clone() }
+            },
+            Change::AbstractConstraintAdded(c) => Change::AbstractConstraintRemoved(c.clone()),
+            Change::AbstractConstraintRemoved(c) => Change::AbstractConstraintAdded(c.clone()),
+            Change::AbstractPropertyAdded(p) => Change::AbstractPropertyRemoved(p.clone()),
+            Change::AbstractPropertyRemoved(p) => Change::AbstractPropertyAdded(p.clone()),
+            Change::AbstractLinkAdded(l) => Change::AbstractLinkRemoved(l.clone()),
+            Change::AbstractLinkRemoved(l) => Change::AbstractLinkAdded(l.clone()),
+            Change::AnnotationDeclared(a) => Change::AnnotationUndeclared(a.clone()),
+            Change::AnnotationUndeclared(a) => Change::AnnotationDeclared(a.clone()),
+            Change::AliasAdded(alias) => Change::AliasRemoved(alias.clone()),
+            Change::AliasRemoved(alias) => Change::AliasAdded(alias.clone()),
+            Change::AliasChanged { old, new } => Change::AliasChanged { old: new.clone(), new: old.clone() },
+            Change::FunctionAdded(func) => Change::FunctionRemoved(func.clone()),
+            Change::FunctionRemoved(func) => Change::FunctionAdded(func.clone()),
+            Change::FunctionChanged { old, new } => Change::FunctionChanged { old: new.clone(), new: old.clone() }
+        };
+        Some(inverse)
+    }
+}
+
+/// Every change that turns `old` into `new`: declarations first,
+/// then removed, added and altered types, then aliases and functions.
+pub fn diff(old: &Module, new: &Module) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let (removed, added, common) = pair(&old.constraints, &new.constraints, |c| c.ident.clone());
+    changes.extend(removed.into_iter().cloned().map(Change::AbstractConstraintRemoved));
+    for (old_c, new_c) in common.into_iter().filter(|(a, b)| !same_json(*a, *b)) {
+        changes.push(Change::AbstractConstraintRemoved(old_c.clone()));
+        changes.push(Change::AbstractConstraintAdded(new_c.clone()));
+    }
+    changes.extend(added.into_iter().cloned().map(Change::AbstractConstraintAdded));
+
+    let (removed, added, common) = pair(&old.properties, &new.properties, |p| p.ident.clone());
+    changes.extend(removed.into_iter().cloned().map(Change::AbstractPropertyRemoved));
+    for (old_p, new_p) in common.into_iter().filter(|(a, b)| !same_json(*a, *b)) {
+        changes.push(Change::AbstractPropertyRemoved(old_p.clone()));
+        changes.push(Change::AbstractPropertyAdded(new_p.clone()));
+    }
+    changes.extend(added.into_iter().cloned().map(Change::AbstractPropertyAdded));
+
+    let (removed, added, common) = pair(&old.links, &new.links, |l| l.ident.clone());
+    changes.extend(removed.into_iter().cloned().map(Change::AbstractLinkRemoved));
+    for (old_l, new_l) in common.into_iter().filter(|(a, b)| !same_json(*a, *b)) {
+        changes.push(Change::AbstractLinkRemoved(old_l.clone()));
+        changes.push(Change::AbstractLinkAdded(new_l.clone()));
+    }
+    changes.extend(added.into_iter().cloned().map(Change::AbstractLinkAdded));
+
+    let (removed, added, common) = pair(&old.annotations, &new.annotations, |a| a.ident.clone());
+    changes.extend(removed.into_iter().cloned().map(Change::AnnotationUndeclared));
+    for (old_a, new_a) in common.into_iter().filter(|(a, b)| a != b) {
+        changes.push(Change::AnnotationUndeclared(old_a.clone()));
+        changes.push(Change::AnnotationDeclared(new_a.clone()));
+    }
+    changes.extend(added.into_iter().cloned().map(Change::AnnotationDeclared));
+
+    // Pseudo-types are derived from aliases, which are compared below;
+    // builtin and internal types belong to EdgeDB, not to the schema.
+    let old_types: Vec<Type> = old.codegen_types().filter(|ty| !ty.alias).cloned().collect();
+    let new_types: Vec<Type> = new.codegen_types().filter(|ty| !ty.alias).cloned().collect();
+    let (removed, added, common) = pair(&old_types, &new_types, |ty| ty.ident.clone());
+    let (retyped, common): (Vec<_>, Vec<_>) = common.into_iter().partition(|(a, b)| a.scalar != b.scalar);
+    changes.extend(removed.into_iter().chain(retyped.iter().map(|(a, _)| *a)).cloned().map(Change::TypeRemoved));
+    changes.extend(added.into_iter().chain(retyped.iter().map(|(_, b)| *b)).cloned().map(Change::TypeAdded));
+    for (old_ty, new_ty) in common {
+        diff_type(old_ty, new_ty, &mut changes);
+    }
+
+    let (removed, added, common) = pair(&old.aliases, &new.aliases, |alias| alias.ident.clone());
+    changes.extend(removed.into_iter().cloned().map(Change::AliasRemoved));
+    changes.extend(added.into_iter().cloned().map(Change::AliasAdded));
+    for (old_alias, new_alias) in common {
+        let expression = |alias: &Alias| alias.alias_expr.expression();
+        if !same_expression(expression(old_alias).as_ref(), expression(new_alias).as_ref())
+            || !same_json(&old_alias.annotations, &new_alias.annotations)
+        {
+            changes.push(Change::AliasChanged { old: old_alias.clone(), new: new_alias.clone() });
+        }
+    }
+
+    let (removed, added, common) = pair(&old.functions, &new.functions, overload_key);
+    changes.extend(removed.into_iter().cloned().map(Change::FunctionRemoved));
+    changes.extend(added.into_iter().cloned().map(Change::FunctionAdded));
+    for (old_func, new_func) in common {
+        let same = signature(old_func) == signature(new_func)
+            && same_expression(old_func.body.as_ref(), new_func.body.as_ref())
+            && old_func.volatility == new_func.volatility
+            && old_func.language == new_func.language
+            && same_json(&old_func.annotations, &new_func.annotations);
+        if !same {
+            changes.push(Change::FunctionChanged { old: old_func.clone(), new: new_func.clone() });
+        }
+    }
+    changes
+}
+
+fn diff_type(old: &Type, new: &Type, changes: &mut Vec<Change>) {
+    let ty = new.ident.clone();
+    let at = Location::of(new);
+    if old.abs != new.abs {
+        changes.push(Change::AbstractChanged { ty: ty.clone(), abs: new.abs });
+    }
+    if old.is_final != new.is_final {
+        changes.push(Change::FinalChanged { ty: ty.clone(), is_final: new.is_final });
+    }
+    let bases = |ty: &Type| ty.extends.clone().unwrap_or_default();
+    if bases(old) != bases(new) {
+        changes.push(Change::BasesChanged { ty: ty.clone(), old: bases(old), new: bases(new) });
+    }
+    diff_annotations(&at, list(&old.annotations), list(&new.annotations), changes);
+    diff_constraints(&at, list(&old.constraints), list(&new.constraints), changes);
+
+    let (removed, added, common) = pair(list(&old.properties), list(&new.properties), |p| p.ident.clone());
+    for prop in removed {
+        changes.push(Change::PropertyRemoved { at: at.property(&prop.ident), prop: prop.clone() });
+    }
+    for prop in added {
+        changes.push(Change::PropertyAdded { at: at.property(&prop.ident), prop: prop.clone() });
+    }
+    for (old_p, new_p) in common {
+        diff_pointer(&at.property(&new_p.ident), Fields::from(old_p), Fields::from(new_p), changes);
+    }
+
+    let (removed, added, common) = pair(list(&old.links), list(&new.links), |l| l.ident.clone());
+    for link in removed {
+        changes.push(Change::LinkRemoved { at: at.link(&link.ident), link: link.clone() });
+    }
+    for link in added {
+        changes.push(Change::LinkAdded { at: at.link(&link.ident), link: link.clone() });
+    }
+    for (old_l, new_l) in common {
+        let link_at = at.link(&new_l.ident);
+        diff_pointer(&link_at, Fields::from(old_l), Fields::from(new_l), changes);
+        let (removed, added, common) = pair(list(&old_l.properties), list(&new_l.properties), |p| p.ident.clone());
+        for prop in removed {
+            changes.push(Change::PropertyRemoved { at: link_at.link_property(&prop.ident), prop: prop.clone() });
+        }
+        for prop in added {
+            changes.push(Change::PropertyAdded { at: link_at.link_property(&prop.ident), prop: prop.clone() });
+        }
+        for (old_p, new_p) in common {
+            diff_pointer(&link_at.link_property(&new_p.ident), Fields::from(old_p), Fields::from(new_p), changes);
+        }
+    }
+
+    let (removed, added, _) = pair(list(&old.indices), list(&new.indices), |index| canonical_expression(&index.expr));
+    for index in removed {
+        changes.push(Change::IndexDropped { ty: ty.clone(), index: index.clone() });
+    }
+    for index in added {
+        changes.push(Change::IndexAdded { ty: ty.clone(), index: index.clone() });
+    }
+}
+
+/// The parts of a property or link that can change in place.
+struct Fields<'a> {
+    multi:       bool,
+    required:    bool,
+    readonly:    bool,
+    target:      Option<&'a String>,
+    expr:        Option<&'a Expression>,
+    default:     Option<&'a Expression>,
+    constraints: &'a [Constraint],
+    annotations: &'a [Annotation]
+}
+
+impl<'a> From<&'a Property> for Fields<'a> {
+    fn from(prop: &'a Property) -> Fields<'a> {
+        Fields {
+            multi: prop.multi,
+            required: prop.required,
+            readonly: prop.readonly,
+            target: prop.target.as_ref(),
+            expr: prop.expr.as_ref(),
+            default: prop.default.as_ref(),
+            constraints: list(&prop.constraints),
+            annotations: list(&prop.annotations)
+        }
+    }
+}
+
+impl<'a> From<&'a Link> for Fields<'a> {
+    fn from(link: &'a Link) -> Fields<'a> {
+        Fields {
+            multi: link.multi,
+            required: link.required,
+            readonly: link.readonly,
+            target: link.target.as_ref(),
+            expr: link.expr.as_ref(),
+            default: link.default.as_ref(),
+            constraints: list(&link.constraints),
+            annotations: list(&link.annotations)
+        }
+    }
+}
+
+fn diff_pointer(at: &Location, old: Fields, new: Fields, changes: &mut Vec<Change>) {
+    if old.multi != new.multi {
+        changes.push(Change::CardinalityChanged { at: at.clone(), multi: new.multi });
+    }
+    if old.required != new.required {
+        changes.push(Change::RequiredChanged { at: at.clone(), required: new.required });
+    }
+    if old.readonly != new.readonly {
+        changes.push(Change::ReadonlyChanged { at: at.clone(), readonly: new.readonly });
+    }
+    if old.target != new.target {
+        changes.push(Change::TargetChanged { at: at.clone(), old: old.target.cloned(), new: new.target.cloned() });
+    }
+    if !same_expression(old.expr, new.expr) {
+        changes.push(Change::ExprChanged { at: at.clone(), old: old.expr.cloned(), new: new.expr.cloned() });
+    }
+    if !same_expression(old.default, new.default) {
+        changes.push(Change::DefaultChanged { at: at.clone(), old: old.default.cloned(), new: new.default.cloned() });
+    }
+    diff_constraints(at, old.constraints, new.constraints, changes);
+    diff_annotations(at, old.annotations, new.annotations, changes);
+}
+
+fn diff_constraints(at: &Location, old: &[Constraint], new: &[Constraint], changes: &mut Vec<Change>) {
+    let (removed, added, _) = pair(old, new, constraint_key);
+    for constraint in removed {
+        changes.push(Change::ConstraintRemoved { at: at.clone(), constraint: constraint.clone() });
+    }
+    for constraint in added {
+        changes.push(Change::ConstraintAdded { at: at.clone(), constraint: constraint.clone() });
+    }
+}
+
+fn diff_annotations(at: &Location, old: &[Annotation], new: &[Annotation], changes: &mut Vec<Change>) {
+    let ident = |anno: &Annotation| anno.ident.trim_start_matches("std::").to_string();
+    let (removed, added, common) = pair(old, new, ident);
+    for annotation in removed {
+        changes.push(Change::AnnotationRemoved { at: at.clone(), annotation: annotation.clone() });
+    }
+    for annotation in added {
+        changes.push(Change::AnnotationAdded { at: at.clone(), annotation: annotation.clone() });
+    }
+    for (old_a, new_a) in common.into_iter().filter(|(a, b)| a.value != b.value) {
+        changes.push(Change::AnnotationChanged {
+            at: at.clone(),
+            ident: new_a.ident.clone(),
+            old: old_a.value.clone(),
+            new: new_a.value.clone()
+        });
+    }
+}
+
+/// Split two lists into the items only in `old`, the items only
+/// in `new`, and the pairs of items present in both.
+#[allow(clippy::type_complexity)]
+fn pair<'a, T, K: PartialEq>(
+    old: &'a [T],
+    new: &'a [T],
+    key: impl Fn(&T) -> K) -> (Vec<&'a T>, Vec<&'a T>, Vec<(&'a T, &'a T)>)
+{
+    let old_keys: Vec<K> = old.iter().map(&key).collect();
+    let new_keys: Vec<K> = new.iter().map(&key).collect();
+    let removed = old.iter().zip(old_keys.iter())
+        .filter(|(_, k)| !new_keys.contains(k))
+        .map(|(item, _)| item)
+        .collect();
+    let mut added = Vec::new();
+    let mut common = Vec::new();
+    for (item, k) in new.iter().zip(new_keys.iter()) {
+        match old_keys.iter().position(|other| other == k) {
+            Some(i) => common.push((&old[i], item)),
+            None => added.push(item)
+        }
+    }
+    (removed, added, common)
+}
+
+fn same_expression(a: Option<&Expression>, b: Option<&Expression>) -> bool {
+    a.map(canonical_expression) == b.map(canonical_expression)
+}
+
+pub(crate) fn same_json<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Constraints with the same name, arguments, subject expression and
+/// error message are the same constraint.
+pub(crate) fn constraint_key(constraint: &Constraint) -> String {
+    let args: Vec<String> = list(&constraint.args).iter().map(canonical_expression).collect();
+    let errmessage = constraint.errmessage().unwrap_or_default();
+    format!("{}({}) on {:?} delegated {} {}",
+            constraint.ident.trim_start_matches("std::"), args.join(", "),
+            constraint.on.as_ref().map(canonical_expression), constraint.delegated, errmessage)
+}
+
+/// Overloads are told apart by their parameter types.
+pub(crate) fn overload_key(func: &Function) -> String {
+    let params: Vec<String> = func.params.iter()
+        .map(|param| format!("{:?} {:?} {}", param.kind, param.typemod, param.ty))
+        .collect();
+    format!("{}({})", func.ident, params.join(", "))
+}
+
+fn describe_constraint(constraint: &Constraint) -> String {
+    let args: Vec<&str> = list(&constraint.args).iter().map(|arg| arg.ident.as_str()).collect();
+    let mut text = format!("`{}", constraint.ident);
+    if !args.is_empty() {
+        text.push_str(&format!("({})", args.join(", ")));
+    }
+    if let Some(on) = &constraint.on {
+        text.push_str(&format!(" on ({})", on.ident));
+    }
+    text.push('`');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{ConstraintBuilder, Expression, TypeBuilder};
+    use crate::fixtures::catalog;
+    use super::{diff, Change};
+
+    #[test]
+    fn ignores_builtin_and_internal_types() {
+        let old = catalog();
+        let mut new = catalog();
+        new.types.push(TypeBuilder::default().ident("BaseObject").builtin(true).build().unwrap());
+        new.types.push(TypeBuilder::default().ident("Migration").is_internal(true).build().unwrap());
+        assert!(diff(&old, &new).is_empty(), "{:?}", diff(&old, &new));
+        assert!(diff(&new, &old).is_empty(), "{:?}", diff(&new, &old));
+        assert_eq!(new.codegen_types().map(|ty| ty.ident.as_str()).collect::<Vec<_>>(), vec!["Item"]);
+    }
+
+    #[test]
+    fn reformatting_is_not_a_change() {
+        let old = catalog();
+        let mut new = catalog();
+        new.types[0].properties.as_mut().unwrap().reverse();
+        let label = &mut new.types[0].properties.as_mut().unwrap()[0];
+        label.expr = Some(Expression::new(".name   ++ ' ' # joined\n ++ <str>.price"));
+        assert!(diff(&old, &new).is_empty(), "{:?}", diff(&old, &new));
+    }
+
+    #[test]
+    fn lists_structural_changes() {
+        let old = catalog();
+        let mut new = catalog();
+        {
+            let item = &mut new.types[0];
+            let props = item.properties.as_mut().unwrap();
+            props.retain(|prop| prop.ident != "price");
+            props[0].multi = true;
+            let max_len = ConstraintBuilder::default()
+                .ident("max_len_value")
+                .args(Box::new(vec![Expression::new("80")]))
+                .build()
+                .unwrap();
+            props[0].constraints = Some(Box::new(vec![max_len]));
+            let related = &mut item.links.as_mut().unwrap()[0];
+            related.target = Some("Product".to_string());
+            related.properties.as_mut().unwrap()[0].target = Some("float64".to_string());
+            item.indices = None;
+        }
+        new.types.push(TypeBuilder::default().ident("Product").build().unwrap());
+
+        let changes: Vec<String> = diff(&old, &new).iter().map(|change| change.to_string()).collect();
+        assert_eq!(changes, vec![
+            "add type `Product`",
+            "remove property `Item.price`",
+            "make `Item.name` multi",
+            "add constraint `max_len_value(80)` to `Item.name`",
+            "change target of `Item.related` from `Item` to `Product`",
+            "change target of `Item.related@weight` from `float32` to `float64`",
+            "drop index on (.name) from `Item`"
+        ]);
+        assert!(matches!(&diff(&new, &old)[0], Change::TypeRemoved(ty) if ty.ident == "Product"));
+    }
+}
@@ -0,0 +1,1193 @@
+//! A small EdgeQL expression parser.
+//!
+//! It covers the subset of EdgeQL that shows up inside of SDL:
+//! computable pointers, `using` clauses, index and alias expressions,
+//! including shapes and simple `SELECT ... FILTER ... ORDER BY` queries,
+//! and the `WITH`, `FOR`, `INSERT`, `UPDATE` and `DELETE` statements that
+//! migration hooks are made of. Full queries are still the business of
+//! the EdgeDB server.
+
+use crate::visit::{walk_expr, walk_path, Visitor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Path(Path),
+    Call { func: String, args: Vec<Expr> },
+    Cast { ty: String, expr: Box<Expr> },
+    UnaryOp { op: String, operand: Box<Expr> },
+    BinaryOp { left: Box<Expr>, op: String, right: Box<Expr> },
+    IfElse { then: Box<Expr>, condition: Box<Expr>, otherwise: Box<Expr> },
+    Index { expr: Box<Expr>, index: Box<Expr> },
+    Tuple(Vec<Expr>),
+    Array(Vec<Expr>),
+    Set(Vec<Expr>),
+    /// `Setting { name, of_group := .<settings }`
+    Shape { expr: Box<Expr>, elements: Vec<ShapeElement> },
+    Select {
+        expr:     Box<Expr>,
+        filter:   Option<Box<Expr>>,
+        order_by: Vec<OrderBy>,
+        offset:   Option<Box<Expr>>,
+        limit:    Option<Box<Expr>>
+    },
+    /// `WITH name := <expr>, ... <body>`
+    With { bindings: Vec<(String, Expr)>, body: Box<Expr> },
+    /// `FOR name IN <iter> UNION <body>`
+    For { var: String, iter: Box<Expr>, body: Box<Expr> },
+    /// `INSERT Type { name := <expr>, ... }`
+    Insert { ty: String, elements: Vec<ShapeElement> },
+    /// `UPDATE <expr> FILTER <filter> SET { name := <expr>, ... }`
+    Update { expr: Box<Expr>, filter: Option<Box<Expr>>, elements: Vec<ShapeElement> },
+    /// `DELETE <expr> FILTER <filter>`
+    Delete { expr: Box<Expr>, filter: Option<Box<Expr>> }
+}
+
+/// One element of a shape: `name`, `name: { ... }` or `name := <expr>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeElement {
+    /// The pointer name, with a leading `@` for link properties.
+    pub ident: String,
+    /// Set for computed elements.
+    pub expr:  Option<Expr>,
+    /// Elements of a nested shape on a link.
+    pub shape: Vec<ShapeElement>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub expr:       Expr,
+    pub descending: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool)
+}
+
+/// A path such as `.deck.cost`, `__subject__.name` or `.<parent[IS Tree]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub source: PathSource,
+    pub steps:  Vec<PathStep>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSource {
+    /// A path that starts with `.`, `.<` or `@`, implicitly
+    /// rooted at whatever is being defined.
+    Partial,
+    Subject,
+    Source,
+    Type(String),
+    Expr(Box<Expr>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathStep {
+    Forward(String),
+    Backward(String),
+    LinkProperty(String),
+    TypeFilter(String)
+}
+
+/// Parse a single EdgeQL expression. Errors start with the
+/// `line:column` where parsing failed, e.g. `1:9: Expected ...`.
+pub fn parse(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        Token::Eof => Ok(expr),
+        tok => Err(parser.error(parser.pos, format!("Unexpected {} after the end of the expression.", tok)))
+    }
+}
+
+/// Parse EdgeQL statements separated by `;`, e.g. a migration hook.
+pub fn parse_statements(source: &str) -> Result<Vec<Expr>, String> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let mut statements = Vec::new();
+    loop {
+        while parser.eat_op(";") {}
+        if let Token::Eof = parser.peek() {
+            return Ok(statements);
+        }
+        statements.push(parser.expr(0)?);
+        if !matches!(parser.peek(), Token::Eof) {
+            parser.expect_op(";")?;
+        }
+    }
+}
+
+/// Type names that `source`, one or more EdgeQL statements, refers to,
+/// as written, e.g. `User`, `str` or `schema::ObjectType`: the roots of
+/// paths, `[IS ...]` filters, casts and what `INSERT` inserts. Names
+/// bound by `WITH` or `FOR` are not types where they are bound.
+pub fn type_references(source: &str) -> Result<Vec<String>, String> {
+    let mut references = TypeReferences { bound: Vec::new(), types: Vec::new() };
+    for statement in parse_statements(source)? {
+        references.visit_expr(&statement);
+    }
+    Ok(references.types)
+}
+
+struct TypeReferences {
+    /// Names bound by the enclosing `WITH`s and `FOR`s.
+    bound: Vec<String>,
+    types: Vec<String>
+}
+
+impl TypeReferences {
+    fn add(&mut self, name: &str) {
+        if !self.types.iter().any(|ty| ty == name) {
+            self.types.push(name.to_string());
+        }
+    }
+
+    /// The types in a type expression such as `array<tuple<a: str, User>>`.
+    fn add_type_expr(&mut self, ty: &str) {
+        for element in ty.split(['<', '>', ',']) {
+            let name = element.rsplit(':').next().unwrap_or_default().trim();
+            if !matches!(name, "" | "array" | "tuple") {
+                self.add(name);
+            }
+        }
+    }
+}
+
+impl Visitor for TypeReferences {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::With { bindings, body } => {
+                let outer = self.bound.len();
+                for (name, binding) in bindings {
+                    self.visit_expr(binding);
+                    self.bound.push(name.clone());
+                }
+                self.visit_expr(body);
+                self.bound.truncate(outer);
+            },
+            Expr::For { var, iter, body } => {
+                self.visit_expr(iter);
+                self.bound.push(var.clone());
+                self.visit_expr(body);
+                self.bound.pop();
+            },
+            Expr::Cast { ty, .. } => {
+                self.add_type_expr(ty);
+                walk_expr(self, expr);
+            },
+            Expr::Insert { ty, .. } => {
+                self.add(ty);
+                walk_expr(self, expr);
+            },
+            _ => walk_expr(self, expr)
+        }
+    }
+
+    fn visit_path(&mut self, path: &Path) {
+        if let PathSource::Type(name) = &path.source {
+            if !self.bound.contains(name) {
+                self.add(name);
+            }
+        }
+        for step in path.steps.iter() {
+            if let PathStep::TypeFilter(ty) = step {
+                self.add(ty);
+            }
+        }
+        walk_path(self, path);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Op(&'static str),
+    Eof
+}
+
+/// Where a token starts: line and column, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Location {
+    pub(crate) line:   usize,
+    pub(crate) column: usize
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Str(s)       => write!(f, "'{}'", s),
+            Token::Int(i)       => write!(f, "`{}`", i),
+            Token::Float(x)     => write!(f, "`{}`", x),
+            Token::Op(op)       => write!(f, "`{}`", op),
+            Token::Eof          => write!(f, "end of input")
+        }
+    }
+}
+
+// Longest operators first, so that `?!=` is not read as `?` + `!=`.
+const OPERATORS: &[&str] = &[
+    "?!=", "?=", "!=", "<=", ">=", ":=", "::", "++", "//", "??", ".<",
+    "(", ")", "[", "]", "{", "}", ",", ";", ":", ".", "@",
+    "<", ">", "=", "+", "-", "*", "/", "%", "^",
+];
+
+pub(crate) fn tokenize(source: &str) -> Result<Vec<(Token, Location)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(chars.iter().enumerate().filter(|(_, ch)| **ch == '\n').map(|(i, _)| i + 1))
+        .collect();
+    let locate = |offset: usize| {
+        let line = match line_starts.binary_search(&offset) {
+            Ok(line) => line + 1,
+            Err(line) => line
+        };
+        Location { line, column: offset - line_starts[line - 1] + 1 }
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        } else if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("{}: Unterminated string literal.", locate(start))),
+                    Some('\\') => {
+                        if let Some(escaped) = chars.get(i + 1) {
+                            value.push(match escaped {
+                                'n' => '\n',
+                                't' => '\t',
+                                other => *other
+                            });
+                        }
+                        i += 2;
+                    },
+                    Some(ch) if *ch == c => {
+                        i += 1;
+                        break;
+                    },
+                    Some(ch) => {
+                        value.push(*ch);
+                        i += 1;
+                    }
+                }
+            }
+            Token::Str(value)
+        } else if c == '`' {
+            i += 1;
+            while i < chars.len() && chars[i] != '`' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(format!("{}: Unterminated quoted identifier.", locate(start)));
+            }
+            i += 1;
+            Token::Ident(chars[start + 1..i - 1].iter().collect())
+        } else if c.is_ascii_digit() {
+            let mut float = false;
+            while i < chars.len() {
+                let ch = chars[i];
+                let fraction = ch == '.'
+                    && !float
+                    && matches!(chars.get(i + 1), Some(next) if next.is_ascii_digit());
+                if ch.is_ascii_digit() || ch == '_' {
+                    i += 1;
+                } else if fraction {
+                    float = true;
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            let text: String = chars[start..i].iter().filter(|ch| **ch != '_').collect();
+            let invalid = format!("{}: Invalid number `{}`.", locate(start), text);
+            if float {
+                Token::Float(text.parse().map_err(|_| invalid)?)
+            } else {
+                Token::Int(text.parse().map_err(|_| invalid)?)
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Token::Ident(chars[start..i].iter().collect())
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    i += op.len();
+                    Token::Op(op)
+                },
+                None => return Err(format!("{}: Unexpected character `{}`.", locate(start), c))
+            }
+        };
+        tokens.push((token, locate(start)));
+    }
+    tokens.push((Token::Eof, locate(chars.len())));
+    Ok(tokens)
+}
+
+/// Binding power of infix operators; higher binds tighter.
+fn infix_power(tok: &Token) -> Option<(u8, &'static str)> {
+    let op = match tok {
+        Token::Op(op) => op.to_string(),
+        Token::Ident(ident) => ident.to_uppercase(),
+        _ => return None
+    };
+    let (power, op) = match op.as_str() {
+        "UNION"  => (1, "UNION"),
+        "IF"     => (2, "IF"),
+        "OR"     => (3, "OR"),
+        "AND"    => (4, "AND"),
+        "="      => (6, "="),
+        "!="     => (6, "!="),
+        "?="     => (6, "?="),
+        "?!="    => (6, "?!="),
+        "<"      => (6, "<"),
+        ">"      => (6, ">"),
+        "<="     => (6, "<="),
+        ">="     => (6, ">="),
+        "LIKE"   => (7, "LIKE"),
+        "ILIKE"  => (7, "ILIKE"),
+        "IN"     => (7, "IN"),
+        "NOT"    => (7, "NOT IN"),
+        "??"     => (8, "??"),
+        "++"     => (9, "++"),
+        "+"      => (9, "+"),
+        "-"      => (9, "-"),
+        "*"      => (10, "*"),
+        "/"      => (10, "/"),
+        "//"     => (10, "//"),
+        "%"      => (10, "%"),
+        "^"      => (11, "^"),
+        _        => return None
+    };
+    Some((power, op))
+}
+
+const PREFIX_POWER: u8 = 12;
+const NOT_POWER: u8 = 5;
+
+pub(crate) struct Parser {
+    pub(crate) tokens: Vec<(Token, Location)>,
+    pub(crate) pos:    usize
+}
+
+impl Parser {
+    pub(crate) fn peek(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)].0
+    }
+
+    pub(crate) fn peek_at(&self, offset: usize) -> &Token {
+        &self.tokens[(self.pos + offset).min(self.tokens.len() - 1)].0
+    }
+
+    /// `message`, prefixed with where the token at `pos` starts.
+    pub(crate) fn error(&self, pos: usize, message: String) -> String {
+        format!("{}: {}", self.tokens[pos.min(self.tokens.len() - 1)].1, message)
+    }
+
+    pub(crate) fn next(&mut self) -> Token {
+        let tok = self.peek().clone();
+        self.pos += 1;
+        tok
+    }
+
+    pub(crate) fn at_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Token::Op(o) if *o == op)
+    }
+
+    pub(crate) fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident.eq_ignore_ascii_case(keyword))
+    }
+
+    pub(crate) fn eat_op(&mut self, op: &str) -> bool {
+        let found = self.at_op(op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub(crate) fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub(crate) fn expect_op(&mut self, op: &str) -> Result<(), String> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(self.error(self.pos, format!("Expected `{}` but found {}.", op, self.peek())))
+        }
+    }
+
+    pub(crate) fn ident(&mut self) -> Result<String, String> {
+        match self.next() {
+            Token::Ident(ident) => Ok(ident),
+            tok => Err(self.error(self.pos - 1, format!("Expected an identifier but found {}.", tok)))
+        }
+    }
+
+    /// A possibly module-qualified name, e.g. `cal::local_date`.
+    pub(crate) fn qualified_ident(&mut self) -> Result<String, String> {
+        let mut ident = self.ident()?;
+        while self.eat_op("::") {
+            ident = format!("{}::{}", ident, self.ident()?);
+        }
+        Ok(ident)
+    }
+
+    /// A type expression such as `str`, `array<int64>` or `tuple<str, int64>`.
+    pub(crate) fn type_expr(&mut self) -> Result<String, String> {
+        let ident = self.qualified_ident()?;
+        if !self.eat_op("<") {
+            return Ok(ident);
+        }
+        let mut elements = Vec::new();
+        loop {
+            let element = if matches!(self.peek_at(1), Token::Op(":")) {
+                let name = self.ident()?;
+                self.expect_op(":")?;
+                format!("{}: {}", name, self.type_expr()?)
+            } else {
+                self.type_expr()?
+            };
+            elements.push(element);
+            if !self.eat_op(",") {
+                break;
+            }
+        }
+        self.expect_op(">")?;
+        Ok(format!("{}<{}>", ident, elements.join(", ")))
+    }
+
+    pub(crate) fn expr(&mut self, min_power: u8) -> Result<Expr, String> {
+        let mut left = self.prefix()?;
+        while let Some((power, op)) = infix_power(self.peek()) {
+            if power <= min_power {
+                break;
+            }
+            if op == "NOT IN" {
+                if !matches!(self.peek_at(1), Token::Ident(ident) if ident.eq_ignore_ascii_case("IN")) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            if op == "IF" {
+                let condition = self.expr(power)?;
+                if !self.eat_keyword("ELSE") {
+                    return Err(self.error(self.pos, format!("Expected `ELSE` but found {}.", self.peek())));
+                }
+                let otherwise = self.expr(power - 1)?;
+                left = Expr::IfElse {
+                    then: Box::new(left),
+                    condition: Box::new(condition),
+                    otherwise: Box::new(otherwise)
+                };
+                continue;
+            }
+            // `^` is the only right-associative operator.
+            let right = if op == "^" { self.expr(power - 1)? } else { self.expr(power)? };
+            left = Expr::BinaryOp { left: Box::new(left), op: op.to_string(), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, String> {
+        if self.eat_op("-") || self.eat_op("+") {
+            let op = if let Token::Op(op) = self.tokens[self.pos - 1].0 { op } else { "-" };
+            let operand = self.expr(PREFIX_POWER)?;
+            return Ok(Expr::UnaryOp { op: op.to_string(), operand: Box::new(operand) });
+        }
+        for keyword in &["EXISTS", "DISTINCT"] {
+            if self.eat_keyword(keyword) {
+                let operand = self.expr(PREFIX_POWER)?;
+                return Ok(Expr::UnaryOp { op: keyword.to_string(), operand: Box::new(operand) });
+            }
+        }
+        if self.eat_keyword("NOT") {
+            let operand = self.expr(NOT_POWER)?;
+            return Ok(Expr::UnaryOp { op: "NOT".to_string(), operand: Box::new(operand) });
+        }
+        if self.eat_keyword("SELECT") {
+            return self.select();
+        }
+        if self.eat_keyword("WITH") {
+            return self.with();
+        }
+        if self.eat_keyword("FOR") {
+            let var = self.ident()?;
+            if !self.eat_keyword("IN") {
+                return Err(self.error(self.pos, format!("Expected `IN` but found {}.", self.peek())));
+            }
+            // Stop before `UNION`, the loosest infix operator.
+            let iter = self.expr(1)?;
+            if !self.eat_keyword("UNION") {
+                return Err(self.error(self.pos, format!("Expected `UNION` but found {}.", self.peek())));
+            }
+            let body = self.expr(1)?;
+            return Ok(Expr::For { var, iter: Box::new(iter), body: Box::new(body) });
+        }
+        if self.eat_keyword("INSERT") {
+            let ty = self.qualified_ident()?;
+            let elements = if self.eat_op("{") { self.shape()? } else { Vec::new() };
+            return Ok(Expr::Insert { ty, elements });
+        }
+        if self.eat_keyword("UPDATE") {
+            let expr = Box::new(self.expr(0)?);
+            let filter = if self.eat_keyword("FILTER") { Some(Box::new(self.expr(0)?)) } else { None };
+            if !self.eat_keyword("SET") {
+                return Err(self.error(self.pos, format!("Expected `SET` but found {}.", self.peek())));
+            }
+            self.expect_op("{")?;
+            return Ok(Expr::Update { expr, filter, elements: self.shape()? });
+        }
+        if self.eat_keyword("DELETE") {
+            let expr = Box::new(self.expr(0)?);
+            let filter = if self.eat_keyword("FILTER") { Some(Box::new(self.expr(0)?)) } else { None };
+            return Ok(Expr::Delete { expr, filter });
+        }
+        if self.eat_op("<") {
+            let ty = self.type_expr()?;
+            self.expect_op(">")?;
+            let expr = self.expr(PREFIX_POWER)?;
+            return Ok(Expr::Cast { ty, expr: Box::new(expr) });
+        }
+        let primary = self.primary()?;
+        self.postfix(primary)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Token::Str(s)   => Ok(Expr::Literal(Literal::Str(s))),
+            Token::Int(i)   => Ok(Expr::Literal(Literal::Int(i))),
+            Token::Float(x) => Ok(Expr::Literal(Literal::Float(x))),
+            Token::Op(".")  => {
+                let step = PathStep::Forward(self.ident()?);
+                Ok(Expr::Path(Path { source: PathSource::Partial, steps: vec![step] }))
+            },
+            Token::Op(".<") => {
+                let step = PathStep::Backward(self.ident()?);
+                Ok(Expr::Path(Path { source: PathSource::Partial, steps: vec![step] }))
+            },
+            Token::Op("@")  => {
+                let step = PathStep::LinkProperty(self.ident()?);
+                Ok(Expr::Path(Path { source: PathSource::Partial, steps: vec![step] }))
+            },
+            Token::Op("(")  => {
+                if self.eat_op(")") {
+                    return Ok(Expr::Tuple(Vec::new()));
+                }
+                let first = self.expr(0)?;
+                if self.eat_op(")") {
+                    return Ok(first);
+                }
+                let mut elements = vec![first];
+                while self.eat_op(",") {
+                    if self.at_op(")") {
+                        break;
+                    }
+                    elements.push(self.expr(0)?);
+                }
+                self.expect_op(")")?;
+                Ok(Expr::Tuple(elements))
+            },
+            Token::Op("[")  => Ok(Expr::Array(self.list("]")?)),
+            Token::Op("{")  => Ok(Expr::Set(self.list("}")?)),
+            Token::Ident(ident) => {
+                match ident.to_uppercase().as_str() {
+                    "TRUE"  => return Ok(Expr::Literal(Literal::Bool(true))),
+                    "FALSE" => return Ok(Expr::Literal(Literal::Bool(false))),
+                    _ => {}
+                }
+                match ident.as_str() {
+                    "__subject__" => return Ok(Expr::Path(Path { source: PathSource::Subject, steps: vec![] })),
+                    "__source__"  => return Ok(Expr::Path(Path { source: PathSource::Source, steps: vec![] })),
+                    _ => {}
+                }
+                let mut name = ident;
+                while self.eat_op("::") {
+                    name = format!("{}::{}", name, self.ident()?);
+                }
+                if self.eat_op("(") {
+                    let args = self.list(")")?;
+                    return Ok(Expr::Call { func: name, args });
+                }
+                Ok(Expr::Path(Path { source: PathSource::Type(name), steps: vec![] }))
+            },
+            tok => Err(self.error(self.pos - 1, format!("Unexpected {} in expression.", tok)))
+        }
+    }
+
+    fn select(&mut self) -> Result<Expr, String> {
+        let expr = Box::new(self.expr(0)?);
+        let filter = if self.eat_keyword("FILTER") { Some(Box::new(self.expr(0)?)) } else { None };
+        let mut order_by = Vec::new();
+        if self.eat_keyword("ORDER") {
+            if !self.eat_keyword("BY") {
+                return Err(self.error(self.pos, format!("Expected `BY` but found {}.", self.peek())));
+            }
+            loop {
+                let expr = self.expr(0)?;
+                let descending = self.eat_keyword("DESC");
+                if !descending {
+                    self.eat_keyword("ASC");
+                }
+                if self.eat_keyword("EMPTY") && !(self.eat_keyword("FIRST") || self.eat_keyword("LAST")) {
+                    let found = format!("Expected `FIRST` or `LAST` but found {}.", self.peek());
+                    return Err(self.error(self.pos, found));
+                }
+                order_by.push(OrderBy { expr, descending });
+                if !self.eat_keyword("THEN") {
+                    break;
+                }
+            }
+        }
+        let offset = if self.eat_keyword("OFFSET") { Some(Box::new(self.expr(0)?)) } else { None };
+        let limit = if self.eat_keyword("LIMIT") { Some(Box::new(self.expr(0)?)) } else { None };
+        Ok(Expr::Select { expr, filter, order_by, offset, limit })
+    }
+
+    /// `name := <expr>, ...` and the body after it, after `WITH`.
+    fn with(&mut self) -> Result<Expr, String> {
+        let mut bindings = Vec::new();
+        loop {
+            let name = self.ident()?;
+            self.expect_op(":=")?;
+            bindings.push((name, self.expr(0)?));
+            if !self.eat_op(",") {
+                break;
+            }
+        }
+        let body = self.expr(0)?;
+        Ok(Expr::With { bindings, body: Box::new(body) })
+    }
+
+    /// Elements of a shape, after the opening `{`.
+    fn shape(&mut self) -> Result<Vec<ShapeElement>, String> {
+        let mut elements = Vec::new();
+        while !self.eat_op("}") {
+            let ident = if self.eat_op("@") { format!("@{}", self.ident()?) } else { self.ident()? };
+            let element = if self.eat_op(":=") {
+                ShapeElement { ident, expr: Some(self.expr(0)?), shape: Vec::new() }
+            } else if self.eat_op(":") {
+                self.expect_op("{")?;
+                ShapeElement { ident, expr: None, shape: self.shape()? }
+            } else {
+                ShapeElement { ident, expr: None, shape: Vec::new() }
+            };
+            elements.push(element);
+            if !self.eat_op(",") && !self.eat_op(";") {
+                self.expect_op("}")?;
+                break;
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Comma-separated expressions up to and including `close`.
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>, String> {
+        let mut items = Vec::new();
+        while !self.eat_op(close) {
+            items.push(self.expr(0)?);
+            if !self.eat_op(",") {
+                self.expect_op(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn postfix(&mut self, mut expr: Expr) -> Result<Expr, String> {
+        loop {
+            let step = if self.eat_op(".") {
+                PathStep::Forward(self.ident()?)
+            } else if self.eat_op("@") {
+                PathStep::LinkProperty(self.ident()?)
+            } else if self.eat_op(".<") {
+                PathStep::Backward(self.ident()?)
+            } else if self.at_op("[") && matches!(self.peek_at(1), Token::Ident(ident) if ident.eq_ignore_ascii_case("IS")) {
+                self.pos += 2;
+                let ty = self.qualified_ident()?;
+                self.expect_op("]")?;
+                PathStep::TypeFilter(ty)
+            } else if self.eat_op("{") {
+                expr = Expr::Shape { expr: Box::new(expr), elements: self.shape()? };
+                continue;
+            } else if self.eat_op("[") {
+                let index = self.expr(0)?;
+                self.expect_op("]")?;
+                expr = Expr::Index { expr: Box::new(expr), index: Box::new(index) };
+                continue;
+            } else {
+                return Ok(expr);
+            };
+            expr = match expr {
+                Expr::Path(mut path) => {
+                    path.steps.push(step);
+                    Expr::Path(path)
+                },
+                other => Expr::Path(Path { source: PathSource::Expr(Box::new(other)), steps: vec![step] })
+            };
+        }
+    }
+}
+
+/// Binding power of the outermost operator of `expr`, as `expr(..)`
+/// parses it; primaries and postfix expressions bind tightest.
+fn power(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BinaryOp { op, .. } => {
+            // `NOT IN` is found by its first word, like the parser does.
+            let first = op.split(' ').next().unwrap_or_default();
+            infix_power(&Token::Ident(first.to_string())).map_or(0, |(power, _)| power)
+        },
+        Expr::IfElse { .. } => 2,
+        Expr::UnaryOp { op, .. } if op == "NOT" => NOT_POWER,
+        Expr::UnaryOp { .. } | Expr::Cast { .. } => PREFIX_POWER,
+        Expr::Select { .. } | Expr::With { .. } | Expr::For { .. } => 0,
+        Expr::Insert { .. } | Expr::Update { .. } | Expr::Delete { .. } => 0,
+        _ => PREFIX_POWER + 1
+    }
+}
+
+/// `name`, in backquotes unless it is a plain identifier.
+fn quoted(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        name.to_string()
+    } else {
+        format!("`{}`", name)
+    }
+}
+
+fn qualified(name: &str) -> String {
+    name.split("::").map(quoted).collect::<Vec<_>>().join("::")
+}
+
+/// `expr`, in parentheses unless it binds at least as tightly as `min`.
+fn operand(expr: &Expr, min: u8) -> String {
+    if power(expr) >= min {
+        expr.to_string()
+    } else {
+        format!("({})", expr)
+    }
+}
+
+fn operands(exprs: &[Expr]) -> String {
+    exprs.iter().map(|expr| operand(expr, 1)).collect::<Vec<_>>().join(", ")
+}
+
+/// Renders the expression as EdgeQL in a canonical form, which
+/// `parse` reads back to an equal expression:
+///
+/// * one space around infix operators and keywords, none inside
+///   brackets, and `, ` between elements;
+/// * keywords in capitals, e.g. `NOT IN`, `IF ... ELSE`, `ORDER BY`;
+/// * parentheses only where precedence needs them, and around nested
+///   `SELECT`s;
+/// * strings in single quotes, with `\\`, `\'`, `\n` and `\t`
+///   escaped, and floats with at least one decimal;
+/// * identifiers in backquotes when they are not plain identifiers.
+///
+/// Schema fingerprints hash this form, so changing it changes them.
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expr::Literal(Literal::Str(s)) => {
+                let escaped = s.replace('\\', "\\\\").replace('\'', "\\'").replace('\n', "\\n").replace('\t', "\\t");
+                write!(f, "'{}'", escaped)
+            },
+            Expr::Literal(Literal::Int(i)) => write!(f, "{}", i),
+            Expr::Literal(Literal::Float(x)) if x.fract() == 0.0 => write!(f, "{}.0", x),
+            Expr::Literal(Literal::Float(x)) => write!(f, "{}", x),
+            Expr::Literal(Literal::Bool(b)) => f.write_str(if *b { "true" } else { "false" }),
+            Expr::Path(path) => write!(f, "{}", path),
+            Expr::Call { func, args } => write!(f, "{}({})", qualified(func), operands(args)),
+            Expr::Cast { ty, expr } => write!(f, "<{}>{}", ty, operand(expr, PREFIX_POWER + 1)),
+            Expr::UnaryOp { op, operand: inner } => {
+                let min = if op == "NOT" { NOT_POWER + 1 } else { PREFIX_POWER + 1 };
+                let space = if op == "-" || op == "+" { "" } else { " " };
+                write!(f, "{}{}{}", op, space, operand(inner, min))
+            },
+            Expr::BinaryOp { left, op, right } => {
+                let p = power(self);
+                // `^` is the only right-associative operator.
+                let (left_min, right_min) = if op == "^" { (p + 1, p) } else { (p, p + 1) };
+                write!(f, "{} {} {}", operand(left, left_min), op, operand(right, right_min))
+            },
+            Expr::IfElse { then, condition, otherwise } => {
+                write!(f, "{} IF {} ELSE {}", operand(then, 3), operand(condition, 3), operand(otherwise, 2))
+            },
+            Expr::Index { expr, index } => write!(f, "{}[{}]", operand(expr, PREFIX_POWER + 1), operand(index, 1)),
+            Expr::Tuple(items) if items.len() == 1 => write!(f, "({},)", operands(items)),
+            Expr::Tuple(items) => write!(f, "({})", operands(items)),
+            Expr::Array(items) => write!(f, "[{}]", operands(items)),
+            Expr::Set(items) => write!(f, "{{{}}}", operands(items)),
+            Expr::Shape { expr, elements } => {
+                write!(f, "{} {}", operand(expr, PREFIX_POWER + 1), ShapeElements(elements))
+            },
+            Expr::Select { expr, filter, order_by, offset, limit } => {
+                write!(f, "SELECT {}", operand(expr, 1))?;
+                if let Some(filter) = filter {
+                    write!(f, " FILTER {}", operand(filter, 1))?;
+                }
+                for (i, order) in order_by.iter().enumerate() {
+                    f.write_str(if i == 0 { " ORDER BY " } else { " THEN " })?;
+                    write!(f, "{}{}", operand(&order.expr, 1), if order.descending { " DESC" } else { "" })?;
+                }
+                if let Some(offset) = offset {
+                    write!(f, " OFFSET {}", operand(offset, 1))?;
+                }
+                if let Some(limit) = limit {
+                    write!(f, " LIMIT {}", operand(limit, 1))?;
+                }
+                Ok(())
+            },
+            Expr::With { bindings, body } => {
+                f.write_str("WITH ")?;
+                for (i, (name, binding)) in bindings.iter().enumerate() {
+                    write!(f, "{}{} := {}", if i == 0 { "" } else { ", " }, quoted(name), operand(binding, 1))?;
+                }
+                write!(f, " {}", body)
+            },
+            Expr::For { var, iter, body } => {
+                write!(f, "FOR {} IN {} UNION {}", quoted(var), operand(iter, 2), operand(body, 2))
+            },
+            Expr::Insert { ty, elements } => write!(f, "INSERT {} {}", qualified(ty), ShapeElements(elements)),
+            Expr::Update { expr, filter, elements } => {
+                write!(f, "UPDATE {}", operand(expr, 1))?;
+                if let Some(filter) = filter {
+                    write!(f, " FILTER {}", operand(filter, 1))?;
+                }
+                write!(f, " SET {}", ShapeElements(elements))
+            },
+            Expr::Delete { expr, filter } => {
+                write!(f, "DELETE {}", operand(expr, 1))?;
+                if let Some(filter) = filter {
+                    write!(f, " FILTER {}", operand(filter, 1))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.source {
+            PathSource::Partial => {},
+            PathSource::Subject => f.write_str("__subject__")?,
+            PathSource::Source => f.write_str("__source__")?,
+            PathSource::Type(ident) => f.write_str(&qualified(ident))?,
+            PathSource::Expr(expr) => f.write_str(&operand(expr, PREFIX_POWER + 1))?
+        }
+        for step in self.steps.iter() {
+            match step {
+                PathStep::Forward(ident) => write!(f, ".{}", quoted(ident))?,
+                PathStep::Backward(ident) => write!(f, ".<{}", quoted(ident))?,
+                PathStep::LinkProperty(ident) => write!(f, "@{}", quoted(ident))?,
+                PathStep::TypeFilter(ident) => write!(f, "[IS {}]", qualified(ident))?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The braces of a shape and what they hold.
+struct ShapeElements<'a>(&'a [ShapeElement]);
+
+impl std::fmt::Display for ShapeElements<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("{}");
+        }
+        f.write_str("{ ")?;
+        for (i, element) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match element.ident.strip_prefix('@') {
+                Some(ident) => write!(f, "@{}", quoted(ident))?,
+                None => f.write_str(&quoted(&element.ident))?
+            }
+            if let Some(expr) = &element.expr {
+                write!(f, " := {}", operand(expr, 1))?;
+            } else if !element.shape.is_empty() {
+                write!(f, ": {}", ShapeElements(&element.shape))?;
+            }
+        }
+        f.write_str(" }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(source: PathSource, steps: Vec<PathStep>) -> Expr {
+        Expr::Path(Path { source, steps })
+    }
+
+    fn partial(ident: &str) -> Expr {
+        path(PathSource::Partial, vec![PathStep::Forward(ident.to_string())])
+    }
+
+    fn binary(left: Expr, op: &str, right: Expr) -> Expr {
+        Expr::BinaryOp { left: Box::new(left), op: op.to_string(), right: Box::new(right) }
+    }
+
+    fn int(i: i64) -> Expr {
+        Expr::Literal(Literal::Int(i))
+    }
+
+    #[test]
+    fn binds_operators_by_precedence() {
+        assert_eq!(parse("1 + 2 * 3").unwrap(), binary(int(1), "+", binary(int(2), "*", int(3))));
+        assert_eq!(parse("1 - 2 - 3").unwrap(), binary(binary(int(1), "-", int(2)), "-", int(3)));
+        assert_eq!(parse("2 ^ 3 ^ 2").unwrap(), binary(int(2), "^", binary(int(3), "^", int(2))));
+        assert_eq!(
+            parse(".a = 1 OR NOT .b AND .c not in {1}").unwrap(),
+            binary(
+                binary(partial("a"), "=", int(1)),
+                "OR",
+                binary(
+                    Expr::UnaryOp { op: "NOT".to_string(), operand: Box::new(partial("b")) },
+                    "AND",
+                    binary(partial("c"), "NOT IN", Expr::Set(vec![int(1)]))
+                )
+            )
+        );
+        let negative = Expr::UnaryOp { op: "-".to_string(), operand: Box::new(int(1)) };
+        assert_eq!(parse("-1 ?? 2").unwrap(), binary(negative, "??", int(2)));
+        assert_eq!(
+            parse("1 IF .a ELSE 2 IF .b ELSE 3").unwrap(),
+            Expr::IfElse {
+                then: Box::new(int(1)),
+                condition: Box::new(partial("a")),
+                otherwise: Box::new(Expr::IfElse {
+                    then: Box::new(int(2)),
+                    condition: Box::new(partial("b")),
+                    otherwise: Box::new(int(3))
+                })
+            }
+        );
+    }
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(
+            parse(".<deck[IS User].name").unwrap(),
+            path(PathSource::Partial, vec![
+                PathStep::Backward("deck".to_string()),
+                PathStep::TypeFilter("User".to_string()),
+                PathStep::Forward("name".to_string())
+            ])
+        );
+        assert_eq!(
+            parse("__subject__.deck@count").unwrap(),
+            path(PathSource::Subject, vec![
+                PathStep::Forward("deck".to_string()),
+                PathStep::LinkProperty("count".to_string())
+            ])
+        );
+        assert_eq!(
+            parse("schema::ObjectType").unwrap(),
+            path(PathSource::Type("schema::ObjectType".to_string()), vec![])
+        );
+        assert_eq!(
+            parse("(.a ?? .b).c").unwrap(),
+            path(
+                PathSource::Expr(Box::new(binary(partial("a"), "??", partial("b")))),
+                vec![PathStep::Forward("c".to_string())]
+            )
+        );
+        assert_eq!(
+            parse(".tags[0]").unwrap(),
+            Expr::Index { expr: Box::new(partial("tags")), index: Box::new(int(0)) }
+        );
+    }
+
+    #[test]
+    fn parses_shapes_and_selects() {
+        let element = |ident: &str, expr: Option<Expr>, shape: Vec<ShapeElement>| {
+            ShapeElement { ident: ident.to_string(), expr, shape }
+        };
+        assert_eq!(
+            parse("SELECT User { name, deck: { @count }, total := sum(.deck.cost) } \
+                   FILTER .name = 'x' ORDER BY .name DESC LIMIT 1").unwrap(),
+            Expr::Select {
+                expr: Box::new(Expr::Shape {
+                    expr: Box::new(path(PathSource::Type("User".to_string()), vec![])),
+                    elements: vec![
+                        element("name", None, vec![]),
+                        element("deck", None, vec![element("@count", None, vec![])]),
+                        element("total", Some(parse("sum(.deck.cost)").unwrap()), vec![])
+                    ]
+                }),
+                filter: Some(Box::new(binary(partial("name"), "=", Expr::Literal(Literal::Str("x".to_string()))))),
+                order_by: vec![OrderBy { expr: partial("name"), descending: true }],
+                offset: None,
+                limit: Some(Box::new(int(1)))
+            }
+        );
+    }
+
+    #[test]
+    fn parses_casts() {
+        assert_eq!(
+            parse("<array<tuple<a: str, int64>>>.x").unwrap(),
+            Expr::Cast { ty: "array<tuple<a: str, int64>>".to_string(), expr: Box::new(partial("x")) }
+        );
+        // A cast binds tighter than any infix operator.
+        assert_eq!(
+            parse("<str>.cost ++ 'x'").unwrap(),
+            binary(
+                Expr::Cast { ty: "str".to_string(), expr: Box::new(partial("cost")) },
+                "++",
+                Expr::Literal(Literal::Str("x".to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn parses_statements() {
+        let user = || Box::new(path(PathSource::Type("User".to_string()), vec![]));
+        let name = ShapeElement {
+            ident: "name".to_string(),
+            expr: Some(Expr::Literal(Literal::Str("x".to_string()))),
+            shape: vec![]
+        };
+        assert_eq!(
+            parse_statements("WITH U := (SELECT User) UPDATE U SET { name := 'x' };\nDELETE User;").unwrap(),
+            vec![
+                Expr::With {
+                    bindings: vec![("U".to_string(), Expr::Select {
+                        expr: user(),
+                        filter: None,
+                        order_by: vec![],
+                        offset: None,
+                        limit: None
+                    })],
+                    body: Box::new(Expr::Update {
+                        expr: Box::new(path(PathSource::Type("U".to_string()), vec![])),
+                        filter: None,
+                        elements: vec![name.clone()]
+                    })
+                },
+                Expr::Delete { expr: user(), filter: None }
+            ]
+        );
+        assert_eq!(
+            parse("FOR x IN {'x'} UNION (INSERT User { name := x })").unwrap(),
+            Expr::For {
+                var: "x".to_string(),
+                iter: Box::new(Expr::Set(vec![Expr::Literal(Literal::Str("x".to_string()))])),
+                body: Box::new(Expr::Insert {
+                    ty: "User".to_string(),
+                    elements: vec![ShapeElement { expr: Some(path(PathSource::Type("x".to_string()), vec![])), ..name }]
+                })
+            }
+        );
+        assert_eq!(parse_statements(" ; ").unwrap(), vec![]);
+        assert_eq!(parse_statements("DELETE User DELETE User").unwrap_err(), "1:13: Expected `;` but found `DELETE`.");
+    }
+
+    #[test]
+    fn finds_type_references() {
+        let references = |source: &str| type_references(source).unwrap();
+        assert_eq!(references("WITH U := (SELECT User) UPDATE U SET { name := 'x' }"), vec!["User"]);
+        assert_eq!(
+            references("UPDATE user FILTER .deck[IS default::Deck].cost > <int64>'5' SET { n := count(Card) };\
+                        DELETE log"),
+            vec!["user", "default::Deck", "int64", "Card", "log"]
+        );
+        assert_eq!(references("FOR x IN {1, 2} UNION (INSERT Item { n := x, t := <array<tuple<a: Tag>>>[] })"), vec![
+            "Item", "Tag"
+        ]);
+        // A binding is only in scope in its own statement.
+        assert_eq!(references("WITH U := User SELECT U; SELECT U"), vec!["User", "U"]);
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        assert_eq!(parse("1 +").unwrap_err(), "1:4: Unexpected end of input in expression.");
+        assert_eq!(parse("(1, 2").unwrap_err(), "1:6: Expected `)` but found end of input.");
+        assert_eq!(parse("sum(.deck.)").unwrap_err(), "1:11: Expected an identifier but found `)`.");
+        assert_eq!(parse("1 IF .a").unwrap_err(), "1:8: Expected `ELSE` but found end of input.");
+        assert_eq!(parse(".a 1").unwrap_err(), "1:4: Unexpected `1` after the end of the expression.");
+        assert_eq!(parse("SELECT .a\nORDER .b").unwrap_err(), "2:7: Expected `BY` but found `.`.");
+        assert_eq!(parse("'abc").unwrap_err(), "1:1: Unterminated string literal.");
+        assert_eq!(parse("1 +\n  `a").unwrap_err(), "2:3: Unterminated quoted identifier.");
+        assert_eq!(parse(".a $ 1").unwrap_err(), "1:4: Unexpected character `$`.");
+        assert_eq!(parse("99999999999999999999").unwrap_err(), "1:1: Invalid number `99999999999999999999`.");
+    }
+
+    #[test]
+    fn renders_expressions_that_parse_back() {
+        let canonical = |source: &str| parse(source).unwrap().to_string();
+        assert_eq!(canonical("  .first ++ ' '  # full name\n ++ .last"), ".first ++ ' ' ++ .last");
+        assert_eq!(canonical("(1 + 2) * 3 - (4 - 5)"), "(1 + 2) * 3 - (4 - 5)");
+        assert_eq!(canonical("(2 ^ 3) ^ 2 + 2 ^ (3 ^ 2)"), "(2 ^ 3) ^ 2 + 2 ^ 3 ^ 2");
+        assert_eq!(canonical("not (.a and .b) or exists .c"), "NOT (.a AND .b) OR EXISTS .c");
+        assert_eq!(canonical("(1 if .a else 2) if .b else 3"), "(1 IF .a ELSE 2) IF .b ELSE 3");
+        assert_eq!(canonical("<str>(.cost * 2.0)"), "<str>(.cost * 2.0)");
+        assert_eq!(canonical("('it\\'s', (1,), `my type`.x)"), "('it\\'s', (1,), `my type`.x)");
+        assert_eq!(
+            canonical("select User { name, deck: { @count }, n := count((select .deck)) } order by .name desc then .x"),
+            "SELECT User { name, deck: { @count }, n := count((SELECT .deck)) } ORDER BY .name DESC THEN .x"
+        );
+        let sources = &[
+            ".<deck[IS default::User].name", "__subject__@count", "{1, 2.5, true}[0]", "-.a ?? +1", ".c NOT IN {1}",
+            "(.a ?? .b).c", "(SELECT .a FILTER .b OFFSET 1 LIMIT 2)", "[(), 'a\\nb']", "DISTINCT .a UNION .b",
+            "1 IF .a ELSE 2 IF .b ELSE 3", ".a = (NOT .b)", "<array<tuple<a: str, int64>>>.x",
+            "WITH a := (SELECT User), b := 1 UPDATE a FILTER .n = b SET { n := .n + 1 }",
+            "FOR x IN {1, 2} UNION (INSERT Item { n := x })", "DELETE Item FILTER .n > 1", "INSERT Item {}"
+        ];
+        for source in sources {
+            let expr = parse(source).unwrap();
+            assert_eq!(parse(&expr.to_string()), Ok(expr), "`{}` does not render back", source);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let sources = &[
+            "", ")", "{1,", "User {", "User { name := }", "<str .x", "[IS User]", "SELECT", ".a[IS]", "1 not 2",
+            "UPDATE Item { n := 1 }", "FOR x {1} UNION x", "WITH a SELECT a", "INSERT"
+        ];
+        for source in sources {
+            assert!(parse(source).is_err(), "`{}` parsed", source);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Content-addressed hashes of modules and schemas.
+//!
+//! Two modules get the same fingerprint when they declare the same
+//! schema, however their SDL is formatted or commented and in whatever
+//! order their items are declared. Order still counts where it does in
+//! SDL: in `extending` lists, parameter lists and constraint arguments.
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::datastructures::{Alias, AliasExpr, Constraint, Expression, Link, Module, Property, Type};
+use crate::visit::{
+    walk_alias_mut, walk_constraint_mut, walk_link_mut, walk_module_mut, walk_property_mut, walk_type_mut, VisitorMut
+};
+
+impl Module {
+    /// Hex-encoded SHA-256 of the module's canonical form.
+    pub fn fingerprint(&self) -> String {
+        let mut canonical = self.clone();
+        Canonicalize.visit_module_mut(&mut canonical);
+        let bytes = serde_json::to_vec(&canonical).expect("the schema model always serializes");
+        hex::encode(Sha256::digest(&bytes))
+    }
+}
+
+/// Fingerprint of a whole schema, independent of the order that
+/// its modules are listed in.
+pub fn schema_fingerprint(modules: &[Module]) -> String {
+    let mut fingerprints: Vec<(&str, String)> = modules.iter()
+        .map(|module| (module.ident.as_str(), module.fingerprint()))
+        .collect();
+    fingerprints.sort();
+    let mut hasher = Sha256::new();
+    for (ident, fingerprint) in fingerprints {
+        hasher.update(format!("{}:{}\n", ident, fingerprint));
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Rewrites expressions to a formatting-independent form and sorts
+/// every list whose order SDL ignores.
+struct Canonicalize;
+
+impl VisitorMut for Canonicalize {
+    fn visit_module_mut(&mut self, module: &mut Module) {
+        walk_module_mut(self, module);
+        sort(&mut module.types);
+        sort(&mut module.constraints);
+        sort(&mut module.properties);
+        sort(&mut module.links);
+        sort(&mut module.annotations);
+        sort(&mut module.aliases);
+        sort(&mut module.functions);
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        walk_type_mut(self, ty);
+        ty.annotations.iter_mut().for_each(|annos| sort(annos));
+        ty.properties.iter_mut().for_each(|props| sort(props));
+        ty.links.iter_mut().for_each(|links| sort(links));
+        ty.constraints.iter_mut().for_each(|cs| sort(cs));
+        ty.indices.iter_mut().for_each(|indices| sort(indices));
+    }
+
+    fn visit_property_mut(&mut self, prop: &mut Property) {
+        walk_property_mut(self, prop);
+        prop.constraints.iter_mut().for_each(|cs| sort(cs));
+        prop.annotations.iter_mut().for_each(|annos| sort(annos));
+    }
+
+    fn visit_link_mut(&mut self, link: &mut Link) {
+        walk_link_mut(self, link);
+        link.properties.iter_mut().for_each(|props| sort(props));
+        link.constraints.iter_mut().for_each(|cs| sort(cs));
+        link.annotations.iter_mut().for_each(|annos| sort(annos));
+    }
+
+    fn visit_constraint_mut(&mut self, constraint: &mut Constraint) {
+        walk_constraint_mut(self, constraint);
+        constraint.subcommands.iter_mut().for_each(|subcommands| sort(subcommands));
+    }
+
+    fn visit_alias_mut(&mut self, alias: &mut Alias) {
+        // Only the expression matters, not the statement it was read from.
+        alias.alias_expr = AliasExpr { ddl: None, sdl: None, expr: alias.alias_expr.expression() };
+        walk_alias_mut(self, alias);
+        alias.annotations.iter_mut().for_each(|annos| sort(annos));
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        expression.ident = canonical_expression(expression);
+    }
+}
+
+/// A form of `expression` that does not depend on its formatting or
+/// comments: the EdgeQL that `Expr`'s `Display` renders, whose format
+/// is documented there. Expressions that do not parse fall back to
+/// their text with whitespace collapsed.
+pub(crate) fn canonical_expression(expression: &Expression) -> String {
+    match expression.parse() {
+        Ok(expr) => expr.to_string(),
+        Err(_) => expression.ident.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Sort by serialized form, which stands in for a total order
+/// on the schema model.
+fn sort<T: Serialize>(items: &mut [T]) {
+    items.sort_by_cached_key(|item| serde_json::to_string(item).expect("the schema model always serializes"));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{Module, PropertyBuilder, TypeBuilder};
+    use crate::fixtures;
+    use super::{canonical_expression, schema_fingerprint};
+
+    fn module(name_expr: &str, reversed: bool) -> Module {
+        let mut props = vec![
+            PropertyBuilder::default().ident("first").target("str").build().unwrap(),
+            PropertyBuilder::default().ident("last").target("str").build().unwrap(),
+            PropertyBuilder::default().ident("name").computable(name_expr).build().unwrap()
+        ];
+        if reversed {
+            props.reverse();
+        }
+        let person = TypeBuilder::default().ident("Person").properties(Box::new(props)).build().unwrap();
+        fixtures::module(vec![person])
+    }
+
+    #[test]
+    fn ignores_formatting_and_order() {
+        let a = module(".first ++ ' ' ++ .last", false);
+        let b = module("  .first   ++ ' '  # full name\n ++ .last", true);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.fingerprint().len(), 64);
+
+        let c = module(".last ++ ' ' ++ .first", false);
+        assert_ne!(a.fingerprint(), c.fingerprint());
+
+        let mut other = Module::new("other");
+        other.types = a.types.clone();
+        assert_eq!(schema_fingerprint(&[a.clone(), other.clone()]), schema_fingerprint(&[other, b]));
+        assert_ne!(schema_fingerprint(&[a]), schema_fingerprint(&[c]));
+    }
+
+    // Fingerprints are stored in migration headers: if this fails, every
+    // existing migration history would be reported as damaged.
+    #[test]
+    fn is_stable() {
+        let expr = "( .first++' ' )  ++.last  # full name".into();
+        assert_eq!(canonical_expression(&expr), ".first ++ ' ' ++ .last");
+        assert_eq!(
+            module(".first ++ ' ' ++ .last", false).fingerprint(),
+            "101dbf102976d5665d641d3c68e423a04f31b6c8933250db8dc258c5d99af4c2"
+        );
+    }
+}
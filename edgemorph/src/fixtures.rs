@@ -0,0 +1,57 @@
+//! Schemas that the tests of several modules start from.
+use crate::datastructures::{IndexBuilder, LinkBuilder, Module, Property, PropertyBuilder, Type, TypeBuilder};
+
+/// Module `default`, declaring `types`.
+pub fn module(types: Vec<Type>) -> Module {
+    let mut module = Module::new("default");
+    module.types = types;
+    module
+}
+
+/// `property <ident> -> <target>`.
+pub fn prop(ident: &str, target: &str) -> Property {
+    PropertyBuilder::default().ident(ident).target(target).build().unwrap()
+}
+
+/// A type `User` with `props`.
+pub fn users(props: Vec<Property>) -> Module {
+    module(vec![TypeBuilder::default().ident("User").properties(Box::new(props)).build().unwrap()])
+}
+
+/// `Item`, with a required `name` and multi `tags`.
+pub fn item() -> Type {
+    TypeBuilder::default()
+        .ident("Item")
+        .properties(Box::new(vec![
+            PropertyBuilder::default().ident("name").target("str").required(true).build().unwrap(),
+            PropertyBuilder::default().ident("tags").target("str").multi(true).build().unwrap()
+        ]))
+        .build()
+        .unwrap()
+}
+
+/// Module `default`, declaring `item`.
+pub fn shop() -> Module {
+    module(vec![item()])
+}
+
+/// `shop`, with `Item` also priced, labelled, linked to related items
+/// with a weight on the link, and indexed on its name.
+pub fn catalog() -> Module {
+    let mut catalog = shop();
+    let item = &mut catalog.types[0];
+    let props = item.properties.as_mut().unwrap();
+    props.push(prop("price", "int64"));
+    props.push(PropertyBuilder::default().ident("label").computable(".name ++ ' ' ++ <str>.price").build().unwrap());
+    item.links = Some(Box::new(vec![
+        LinkBuilder::default()
+            .ident("related")
+            .target("Item")
+            .multi(true)
+            .properties(Box::new(vec![prop("weight", "float32")]))
+            .build()
+            .unwrap()
+    ]));
+    item.indices = Some(Box::new(vec![IndexBuilder::default().expr(".name").build().unwrap()]));
+    catalog
+}
@@ -0,0 +1,348 @@
+//! Function signatures: the catalog of functions that EdgeDB ships
+//! in `std`, and the matching of a call against a function's overloads.
+use once_cell::sync::Lazy;
+use crate::constraints::assignable;
+use crate::datastructures::{
+    Expression, Function, FunctionBuilder, Language, Module, Parameter, ParameterKind, TypeModifier, Volatility
+};
+
+/// Signatures of the `std` functions that schema expressions tend to
+/// use. Generic parameters (`anytype`, `anyreal`, ...) take on the
+/// type of the argument they are given.
+pub fn std_functions() -> &'static [Function] {
+    &STD_FUNCTIONS
+}
+
+/// Parsed once, on first use.
+static STD_FUNCTIONS: Lazy<Vec<Function>> = Lazy::new(|| {
+    const SIGNATURES: &[&str] = &[
+        "count(s: SET OF anytype) -> int64",
+        "sum(s: SET OF int32) -> int64",
+        "sum(s: SET OF int64) -> int64",
+        "sum(s: SET OF bigint) -> bigint",
+        "sum(s: SET OF float32) -> float32",
+        "sum(s: SET OF float64) -> float64",
+        "sum(s: SET OF decimal) -> decimal",
+        "min(vals: SET OF anytype) -> OPTIONAL anytype",
+        "max(vals: SET OF anytype) -> OPTIONAL anytype",
+        "all(vals: SET OF bool) -> bool",
+        "any(vals: SET OF bool) -> bool",
+        "assert_single(input: SET OF anytype) -> OPTIONAL anytype",
+        "assert_exists(input: SET OF anytype) -> SET OF anytype",
+        "assert_distinct(input: SET OF anytype) -> SET OF anytype",
+        "math::mean(vals: SET OF anyreal) -> float64",
+        "math::stddev(vals: SET OF anyreal) -> float64",
+        "math::var(vals: SET OF anyreal) -> float64",
+        "array_agg(s: SET OF anytype) -> array<anytype>",
+        "array_unpack(array: array<anytype>) -> SET OF anytype",
+        "array_join(array: array<str>, delimiter: str) -> str",
+        "len(str: str) -> int64",
+        "len(bytes: bytes) -> int64",
+        "len(array: array<anytype>) -> int64",
+        "contains(haystack: str, needle: str) -> bool",
+        "contains(haystack: bytes, needle: bytes) -> bool",
+        "contains(haystack: array<anytype>, needle: anytype) -> bool",
+        "find(haystack: str, needle: str) -> int64",
+        "find(haystack: array<anytype>, needle: anytype, from_pos: int64 = 0) -> int64",
+        "re_test(pattern: str, str: str) -> bool",
+        "str_upper(s: str) -> str",
+        "str_lower(s: str) -> str",
+        "str_trim(s: str, tr: str = ' ') -> str",
+        "str_trim_start(s: str, tr: str = ' ') -> str",
+        "str_trim_end(s: str, tr: str = ' ') -> str",
+        "str_repeat(s: str, n: int64) -> str",
+        "str_pad_start(s: str, n: int64, fill: str = ' ') -> str",
+        "str_pad_end(s: str, n: int64, fill: str = ' ') -> str",
+        "to_str(x: anyscalar, fmt: OPTIONAL str = {}) -> str",
+        "to_json(str: str) -> json",
+        "to_int16(s: str, fmt: OPTIONAL str = {}) -> int16",
+        "to_int32(s: str, fmt: OPTIONAL str = {}) -> int32",
+        "to_int64(s: str, fmt: OPTIONAL str = {}) -> int64",
+        "to_bigint(s: str, fmt: OPTIONAL str = {}) -> bigint",
+        "to_float32(s: str, fmt: OPTIONAL str = {}) -> float32",
+        "to_float64(s: str, fmt: OPTIONAL str = {}) -> float64",
+        "to_decimal(s: str, fmt: OPTIONAL str = {}) -> decimal",
+        "to_datetime(s: str, fmt: OPTIONAL str = {}) -> datetime",
+        "to_duration(NAMED ONLY hours: int64 = 0, NAMED ONLY minutes: int64 = 0, \
+         NAMED ONLY seconds: float64 = 0, NAMED ONLY microseconds: int64 = 0) -> duration",
+        "datetime_current() -> datetime",
+        "datetime_of_statement() -> datetime",
+        "datetime_of_transaction() -> datetime",
+        "uuid_generate_v1mc() -> uuid",
+        "random() -> float64",
+    ];
+    SIGNATURES.iter()
+        .map(|decl| {
+            let mut func = parse_signature(decl).expect("malformed std function signature");
+            // Functions of other builtin modules, like `math::mean`,
+            // keep their module.
+            if !func.ident.contains("::") {
+                func.ident = format!("std::{}", func.ident);
+            }
+            func.volatility = match bare(&func.ident) {
+                "datetime_current" | "uuid_generate_v1mc" | "random" => Volatility::Volatile,
+                "datetime_of_statement" | "datetime_of_transaction" => Volatility::Stable,
+                _ => Volatility::Immutable
+            };
+            func
+        })
+        .collect()
+});
+
+/// Parse a signature such as `str_pad_start(s: str, n: int64, fill: str = ' ') -> str`
+/// into a builtin function. Parameters may be marked `VARIADIC` or `NAMED ONLY`,
+/// and parameter and return types may be prefixed with `SET OF` or `OPTIONAL`.
+pub fn parse_signature(decl: &str) -> Result<Function, String> {
+    let decl = decl.trim();
+    let open = decl.find('(').ok_or_else(|| format!("`{}` has no parameter list.", decl))?;
+    let close = split_top_level(&decl[open + 1..], ')').0.len() + open + 1;
+    if close >= decl.len() {
+        return Err(format!("`{}` has an unclosed parameter list.", decl));
+    }
+    let ret = decl[close + 1..].trim().strip_prefix("->")
+        .ok_or_else(|| format!("`{}` has no return type.", decl))?;
+    let (return_typemod, return_type) = typemod(ret.trim());
+    let mut params = Vec::new();
+    let mut rest = &decl[open + 1..close];
+    while !rest.trim().is_empty() {
+        let (param, tail) = split_top_level(rest, ',');
+        params.push(parse_param(param.trim())?);
+        rest = tail;
+    }
+    FunctionBuilder::default()
+        .ident(decl[..open].trim())
+        .params(params)
+        .return_type(return_type)
+        .return_typemod(return_typemod)
+        .volatility(Volatility::Immutable)
+        .language(Language::Builtin)
+        .build()
+}
+
+fn parse_param(text: &str) -> Result<Parameter, String> {
+    let (kind, text) = if let Some(rest) = strip_keyword(text, "VARIADIC") {
+        (ParameterKind::Variadic, rest)
+    } else if let Some(rest) = strip_keyword(text, "NAMED ONLY") {
+        (ParameterKind::NamedOnly, rest)
+    } else {
+        (ParameterKind::Positional, text)
+    };
+    let colon = text.match_indices(':')
+        .map(|(i, _)| i)
+        .find(|i| !text[*i..].starts_with("::"))
+        .ok_or_else(|| format!("Parameter `{}` has no type.", text))?;
+    let (ty, default) = match text[colon + 1..].split_once(" = ") {
+        Some((ty, default)) => (ty, Some(Expression::new(default.trim()))),
+        None => (&text[colon + 1..], None)
+    };
+    let (typemod, ty) = typemod(ty.trim());
+    Ok(Parameter { ident: text[..colon].trim().to_string(), ty, kind, typemod, default })
+}
+
+fn typemod(ty: &str) -> (TypeModifier, String) {
+    if let Some(rest) = strip_keyword(ty, "SET OF") {
+        (TypeModifier::SetOf, rest.trim().to_string())
+    } else if let Some(rest) = strip_keyword(ty, "OPTIONAL") {
+        (TypeModifier::Optional, rest.trim().to_string())
+    } else {
+        (TypeModifier::Singleton, ty.to_string())
+    }
+}
+
+fn strip_keyword<'t>(text: &'t str, keyword: &str) -> Option<&'t str> {
+    let head = text.get(..keyword.len())?;
+    let rest = &text[keyword.len()..];
+    if head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+/// Split `text` at the first `sep` that is not nested in brackets or quotes.
+fn split_top_level(text: &str, sep: char) -> (&str, &str) {
+    let mut depth = 0i32;
+    let mut quote: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, c) if c == sep && depth == 0 => return (&text[..i], &text[i + 1..]),
+            (None, '(') | (None, '<') | (None, '[') | (None, '{') => depth += 1,
+            (None, ')') | (None, '>') | (None, ']') | (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    (text, "")
+}
+
+fn bare(ident: &str) -> &str {
+    ident.trim_start_matches("std::")
+}
+
+impl Module {
+    /// The overloads of function `ident`: those declared in this
+    /// module, or else those in `std`.
+    pub fn function_overloads(&self, ident: &str) -> Vec<Function> {
+        let declared: Vec<Function> = self.get_functions(ident).cloned().collect();
+        if !declared.is_empty() {
+            return declared;
+        }
+        std_functions().iter()
+            .filter(|func| bare(&func.ident) == bare(ident))
+            .cloned()
+            .collect()
+    }
+
+    /// Find the overload of `ident` that accepts positional arguments
+    /// of type `args`. Returns the overload together with its return
+    /// type, with generic types replaced by those of the arguments.
+    pub fn match_call(&self, ident: &str, args: &[&str]) -> Result<(Function, String), String> {
+        let overloads = self.function_overloads(ident);
+        if overloads.is_empty() {
+            return Err(format!("Unknown function `{}`.", ident));
+        }
+        // Prefer an overload that takes the arguments as they are
+        // over one that needs them implicitly cast.
+        for exact in [true, false].iter() {
+            for func in overloads.iter() {
+                if let Some(bindings) = self.bind_args(func, args, *exact) {
+                    let ret = substitute(&func.return_type, &bindings);
+                    return Ok((func.clone(), ret));
+                }
+            }
+        }
+        let candidates: Vec<String> = overloads.iter().map(signature).collect();
+        Err(format!(
+            "No overload of `{}` accepts ({}). Candidates are:\n    {}",
+            ident, args.join(", "), candidates.join("\n    ")))
+    }
+
+    fn bind_args(&self, func: &Function, args: &[&str], exact: bool) -> Option<Vec<(String, String)>> {
+        let positional: Vec<&Parameter> = func.positional().collect();
+        let required = positional.iter().filter(|param| param.default.is_none()).count();
+        let variadic = func.variadic();
+        if args.len() < required || (variadic.is_none() && args.len() > positional.len()) {
+            return None;
+        }
+        let mut bindings = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let param = positional.get(i).copied().or(variadic)?;
+            if !self.accepts(&param.ty, arg, exact, &mut bindings) {
+                return None;
+            }
+        }
+        Some(bindings)
+    }
+
+    /// Whether a parameter of type `param` accepts an argument of
+    /// type `arg`, binding any generic type it mentions. Unless `exact`,
+    /// the argument may be implicitly cast to the parameter type.
+    fn accepts(&self, param: &str, arg: &str, exact: bool, bindings: &mut Vec<(String, String)>) -> bool {
+        let param = bare(param);
+        // The type of `{}`, which fits anywhere.
+        if arg == "anytype" {
+            return true;
+        }
+        let fits = match param {
+            "anytype"   => true,
+            "anyscalar" => self.is_scalar(arg),
+            "anyint"    => matches!(self.scalar_base(arg).as_str(), "int16" | "int32" | "int64" | "bigint"),
+            "anyfloat"  => matches!(self.scalar_base(arg).as_str(), "float32" | "float64"),
+            "anyreal"   => matches!(self.scalar_base(arg).as_str(),
+                "int16" | "int32" | "int64" | "bigint" | "float32" | "float64" | "decimal"),
+            _ => {
+                return match (array_element(param), array_element(arg)) {
+                    (Some(param), Some(arg)) => self.accepts(param, arg, exact, bindings),
+                    (Some(_), None) | (None, Some(_)) => false,
+                    (None, None) if exact => self.scalar_base(arg) == self.scalar_base(param),
+                    (None, None) => assignable(self, arg, param)
+                };
+            }
+        };
+        if !fits {
+            return false;
+        }
+        match bindings.iter().find(|(generic, _)| generic == param) {
+            Some((_, bound)) => bound == arg || assignable(self, arg, bound),
+            None => {
+                bindings.push((param.to_string(), arg.to_string()));
+                true
+            }
+        }
+    }
+}
+
+fn array_element(ty: &str) -> Option<&str> {
+    bare(ty).strip_prefix("array<").and_then(|t| t.strip_suffix('>'))
+}
+
+fn substitute(ty: &str, bindings: &[(String, String)]) -> String {
+    match array_element(ty) {
+        Some(element) => format!("array<{}>", substitute(element, bindings)),
+        None => bindings.iter()
+            .find(|(generic, _)| generic == bare(ty))
+            .map_or(ty.to_string(), |(_, bound)| bound.clone())
+    }
+}
+
+/// Render a signature the way it is declared, for error messages.
+pub fn signature(func: &Function) -> String {
+    let typemod = |typemod: TypeModifier| match typemod {
+        TypeModifier::Singleton => "",
+        TypeModifier::Optional  => "OPTIONAL ",
+        TypeModifier::SetOf     => "SET OF "
+    };
+    let params: Vec<String> = func.params.iter()
+        .map(|param| {
+            let kind = match param.kind {
+                ParameterKind::Positional => "",
+                ParameterKind::NamedOnly  => "NAMED ONLY ",
+                ParameterKind::Variadic   => "VARIADIC "
+            };
+            let default = param.default.as_ref().map_or(String::new(), |d| format!(" = {}", d.ident));
+            format!("{}{}: {}{}{}", kind, param.ident, typemod(param.typemod), param.ty, default)
+        })
+        .collect();
+    format!("{}({}) -> {}{}", func.ident, params.join(", "), typemod(func.return_typemod), func.return_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signatures() {
+        let func = parse_signature(
+            "str_pad_start(s: str, n: int64, fill: str = ' ', NAMED ONLY f: OPTIONAL str = {}) -> str").unwrap();
+        assert_eq!(func.params.len(), 4);
+        assert_eq!(func.params[2].default, Some(Expression::new("' '")));
+        assert_eq!(func.params[3].kind, ParameterKind::NamedOnly);
+        assert_eq!(func.params[3].typemod, TypeModifier::Optional);
+        assert_eq!(signature(&func),
+                   "str_pad_start(s: str, n: int64, fill: str = ' ', NAMED ONLY f: OPTIONAL str = {}) -> str");
+
+        let func = parse_signature("array_unpack(array: array<anytype>) -> SET OF anytype").unwrap();
+        assert_eq!(func.return_typemod, TypeModifier::SetOf);
+        assert!(parse_signature("f(VARIADIC a: int64, b: int64) -> int64").is_err());
+        assert!(parse_signature("f(a: int64 = 1, b: int64) -> int64").is_err());
+    }
+
+    #[test]
+    fn matches_calls_against_overloads() {
+        let module = Module::new("default");
+        let (_, ret) = module.match_call("len", &["array<int64>"]).unwrap();
+        assert_eq!(ret, "int64");
+        let (func, ret) = module.match_call("std::sum", &["float64"]).unwrap();
+        assert_eq!((func.params[0].typemod, ret.as_str()), (TypeModifier::SetOf, "float64"));
+        let (_, ret) = module.match_call("array_unpack", &["array<str>"]).unwrap();
+        assert_eq!(ret, "str");
+        assert!(module.match_call("str_trim", &["str"]).is_ok());
+        assert!(module.match_call("str_upper", &["int64"]).is_err());
+        assert!(module.match_call("str_repeat", &["str"]).is_err());
+        let (func, ret) = module.match_call("math::mean", &["int64"]).unwrap();
+        assert_eq!((func.ident.as_str(), ret.as_str()), ("math::mean", "float64"));
+        assert_eq!(module.match_call("count", &["str"]).unwrap().0.ident, "std::count");
+    }
+}
@@ -0,0 +1,208 @@
+//! Checks for `index on (...)` declarations.
+use crate::datastructures::{Module, Pointer, Type};
+use crate::edgeql::{Expr, Path, PathSource, PathStep};
+use crate::inference;
+use crate::visit::{fold_path, walk_path, Fold, Visitor};
+
+impl Module {
+    /// Verify the index declarations of every type in the module.
+    ///
+    /// An index expression may only refer to properties of the host
+    /// type; computed properties are accepted when they are themselves
+    /// derived from stored properties. The expression must yield at most
+    /// one value per object, and no type may declare an index that it
+    /// already has, either directly or through one of its supertypes.
+    pub fn validate_indexes(&self) -> Result<(), String> {
+        match self.index_issues().into_iter().next() {
+            Some((_, issue)) => Err(issue),
+            None => Ok(())
+        }
+    }
+
+    /// Every problem with the index declarations in the module, with
+    /// the type that declares the index.
+    pub(crate) fn index_issues(&self) -> Vec<(String, String)> {
+        let mut issues = Vec::new();
+        for ty in self.types.iter() {
+            let mut push = |issue: String| issues.push((ty.ident.clone(), issue));
+            let mut seen: Vec<(String, Expr)> = Vec::new();
+            for base in self.ancestors(ty).into_iter().rev() {
+                for index in base.indices.iter().flat_map(|indices| indices.iter()) {
+                    if let Ok(expr) = index.expr.parse() {
+                        seen.push((base.ident.clone(), normalize(expr)));
+                    }
+                }
+            }
+            for index in ty.indices.iter().flat_map(|indices| indices.iter()) {
+                let source = &index.expr.ident;
+                let checked = index.expr.parse()
+                    .and_then(|expr| self.check_index_paths(ty, &expr, &mut Vec::new()).map(|_| expr))
+                    .and_then(|expr| inference::infer(self, &ty.ident, &expr).map(|inferred| (expr, inferred)));
+                let (expr, inferred) = match checked {
+                    Ok(checked) => checked,
+                    Err(e) => {
+                        push(format!("In index on ({}) of `{}`: {}", source, ty.ident, e));
+                        continue;
+                    }
+                };
+                if inferred.cardinality.is_multi() {
+                    push(format!(
+                        "Index on ({}) of `{}` yields more than one value per object.", source, ty.ident));
+                }
+                let key = normalize(expr);
+                if let Some((owner, _)) = seen.iter().find(|(_, other)| *other == key) {
+                    push(if *owner == ty.ident {
+                        format!("`{}` declares index on ({}) more than once.", ty.ident, source)
+                    } else {
+                        format!("`{}` declares index on ({}), which it already inherits from `{}`.",
+                                ty.ident, source, owner)
+                    });
+                }
+                seen.push((ty.ident.clone(), key));
+            }
+        }
+        issues
+    }
+
+    // `visiting` holds the computables being expanded, to stop
+    // ones that are defined in terms of each other.
+    fn check_index_paths(&self, ty: &Type, expr: &Expr, visiting: &mut Vec<String>) -> Result<(), String> {
+        let mut paths = Paths::default();
+        paths.visit_expr(expr);
+        for path in paths.0.iter() {
+            match &path.source {
+                PathSource::Partial | PathSource::Subject => {},
+                PathSource::Source => return Err("`__source__` is not available in an index.".to_string()),
+                PathSource::Type(ident) => return Err(format!(
+                    "`{}` is not a property of `{}`; indexes may only refer to the host type.", ident, ty.ident)),
+                PathSource::Expr(_) => return Err(
+                    "Indexes may only follow paths that start at the host type.".to_string())
+            }
+            let ident = match path.steps.as_slice() {
+                [] => continue,
+                [PathStep::Forward(ident)] => ident,
+                [PathStep::Forward(ident), ..] => return Err(format!(
+                    "Indexes cannot reach past `.{}` of `{}`.", ident, ty.ident)),
+                _ => return Err("Indexes may only refer to properties of the host type.".to_string())
+            };
+            if ident == "id" {
+                continue;
+            }
+            let pointer = self.find_pointer(ty, ident).ok_or_else(|| format!(
+                "`{}` has no property called `{}`.", ty.ident, ident))?;
+            if pointer.multi() {
+                return Err(format!("`{}.{}` is a multi pointer and cannot be indexed.", ty.ident, ident));
+            }
+            if let Pointer::Property(prop) = pointer {
+                if let Some(computed) = prop.expr.as_ref().filter(|_| prop.is_computable()) {
+                    if visiting.contains(&prop.ident) {
+                        return Err(format!("Computable `{}.{}` is defined in terms of itself.", ty.ident, ident));
+                    }
+                    visiting.push(prop.ident.clone());
+                    let computed = computed.parse()?;
+                    self.check_index_paths(ty, &computed, visiting).map_err(|e| format!(
+                        "Computable `{}.{}` cannot be indexed: {}", ty.ident, ident, e))?;
+                    visiting.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects every path in an expression, outermost first.
+#[derive(Default)]
+struct Paths(Vec<Path>);
+
+impl Visitor for Paths {
+    fn visit_path(&mut self, path: &Path) {
+        self.0.push(path.clone());
+        walk_path(self, path);
+    }
+}
+
+/// Rewrites `__subject__.x` as `.x`, so that the two spellings of
+/// an index compare as equal.
+struct Normalize;
+
+impl Fold for Normalize {
+    fn fold_path(&mut self, path: Path) -> Path {
+        let path = match path.source {
+            PathSource::Subject => Path { source: PathSource::Partial, ..path },
+            _ => path
+        };
+        fold_path(self, path)
+    }
+}
+
+fn normalize(expr: Expr) -> Expr {
+    Normalize.fold_expr(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{IndexBuilder, LinkBuilder, Module, PropertyBuilder, TypeBuilder};
+    use crate::fixtures;
+
+    fn people() -> Module {
+        let named = TypeBuilder::default()
+            .ident("Named")
+            .abs(true)
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("name").target("str").required(true).build().unwrap()
+            ]))
+            .indices(Box::new(vec![IndexBuilder::default().expr("__subject__.name").build().unwrap()]))
+            .build()
+            .unwrap();
+        let person = TypeBuilder::default()
+            .ident("Person")
+            .extends(vec!["Named".to_string()])
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("first_name").target("str").build().unwrap(),
+                PropertyBuilder::default().ident("last_name").target("str").build().unwrap(),
+                PropertyBuilder::default().ident("nicknames").target("str").multi(true).build().unwrap(),
+                PropertyBuilder::default()
+                    .ident("full_name")
+                    .computable(".first_name ++ ' ' ++ .last_name")
+                    .build()
+                    .unwrap()
+            ]))
+            .links(Box::new(vec![
+                LinkBuilder::default().ident("friends").target("Person").multi(true).build().unwrap()
+            ]))
+            .indices(Box::new(vec![
+                IndexBuilder::default().expr("(.first_name, .last_name)").build().unwrap(),
+                IndexBuilder::default().expr("str_lower(.full_name)").build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        fixtures::module(vec![named, person])
+    }
+
+    fn with_index(module: &Module, expr: &str) -> Module {
+        let mut module = module.clone();
+        let person = &mut module.types[1];
+        person.indices.as_mut().unwrap().push(IndexBuilder::default().expr(expr).build().unwrap());
+        module
+    }
+
+    #[test]
+    fn checks_index_expressions() {
+        let module = people();
+        assert!(module.validate_indexes().is_ok());
+
+        assert!(with_index(&module, ".age").validate_indexes().is_err());
+        assert!(with_index(&module, ".nicknames").validate_indexes().is_err());
+        assert!(with_index(&module, ".friends.name").validate_indexes().is_err());
+        assert!(with_index(&module, "(.last_name, .first_name)").validate_indexes().is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicate_indexes() {
+        let module = people();
+        let err = with_index(&module, ".name").validate_indexes().unwrap_err();
+        assert!(err.contains("inherits from `Named`"), "{}", err);
+        let err = with_index(&module, "( __subject__.first_name, .last_name )").validate_indexes().unwrap_err();
+        assert!(err.contains("more than once"), "{}", err);
+    }
+}
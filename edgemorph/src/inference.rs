@@ -0,0 +1,646 @@
+//! Result type and cardinality inference for EdgeQL expressions
+//! that appear in a schema, such as the bodies of computables.
+use crate::datastructures::{Link, Module, Parameter, Pointer, Type, TypeModifier};
+use crate::edgeql::{Expr, Literal, Path, PathSource, PathStep, ShapeElement};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Cardinality {
+    One,
+    AtMostOne,
+    AtLeastOne,
+    Many
+}
+
+impl Cardinality {
+    pub fn from_bounds(required: bool, multi: bool) -> Cardinality {
+        match (required, multi) {
+            (true, false)  => Cardinality::One,
+            (false, false) => Cardinality::AtMostOne,
+            (true, true)   => Cardinality::AtLeastOne,
+            (false, true)  => Cardinality::Many
+        }
+    }
+
+    pub fn is_required(self) -> bool {
+        matches!(self, Cardinality::One | Cardinality::AtLeastOne)
+    }
+
+    pub fn is_multi(self) -> bool {
+        matches!(self, Cardinality::AtLeastOne | Cardinality::Many)
+    }
+
+    /// Cardinality of following a path step, or of applying an
+    /// element-wise operator to two sets.
+    pub fn product(self, other: Cardinality) -> Cardinality {
+        Cardinality::from_bounds(
+            self.is_required() && other.is_required(),
+            self.is_multi() || other.is_multi())
+    }
+
+    pub fn optional(self) -> Cardinality {
+        Cardinality::from_bounds(false, self.is_multi())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InferredType {
+    pub ty:          String,
+    pub cardinality: Cardinality,
+    pub object:      bool
+}
+
+impl InferredType {
+    pub fn scalar(ty: &str, cardinality: Cardinality) -> InferredType {
+        InferredType { ty: ty.to_string(), cardinality, object: false }
+    }
+
+    pub fn object(ty: &str, cardinality: Cardinality) -> InferredType {
+        InferredType { ty: ty.to_string(), cardinality, object: true }
+    }
+}
+
+pub(crate) const BASE_OBJECT: &str = "std::BaseObject";
+
+/// Infer the type of `expr` where `.`, `__subject__` and `__source__`
+/// refer to a single value of type `subject`.
+pub fn infer(module: &Module, subject: &str, expr: &Expr) -> Result<InferredType, String> {
+    Inference::new(module).expr(subject, None, expr)
+}
+
+/// Like `infer`, but bare names in `bindings` (such as the parameters
+/// of an abstract constraint) are treated as values of the given type.
+pub fn infer_with(
+    module: &Module,
+    subject: &str,
+    bindings: &[(String, InferredType)],
+    expr: &Expr) -> Result<InferredType, String>
+{
+    let mut inference = Inference::new(module);
+    inference.bindings = bindings.to_vec();
+    inference.expr(subject, None, expr)
+}
+
+/// Infer the type of a pointer declared on `host`. Concrete pointers
+/// are taken at their word; computables have their expression inferred.
+pub fn infer_pointer(module: &Module, host: &Type, pointer: Pointer) -> Result<InferredType, String> {
+    Inference::new(module).pointer(host, pointer)
+}
+
+impl Module {
+    /// Infer the target type and cardinality of every computable
+    /// property and link, and record them on the pointer.
+    pub fn resolve_computables(&mut self) -> Result<(), String> {
+        let mut resolved = Vec::new();
+        for (t, ty) in self.types.iter().enumerate() {
+            for (p, prop) in ty.properties.iter().flat_map(|props| props.iter()).enumerate() {
+                if prop.is_computable() {
+                    let inferred = infer_pointer(self, ty, Pointer::Property(prop))?;
+                    if inferred.object {
+                        return Err(format!(
+                            "Computable property `{}.{}` yields object type `{}`; declare it as a link.",
+                            ty.ident, prop.ident, inferred.ty));
+                    }
+                    resolved.push((t, false, p, inferred));
+                }
+            }
+            for (l, link) in ty.links.iter().flat_map(|links| links.iter()).enumerate() {
+                if link.is_computable() {
+                    let inferred = infer_pointer(self, ty, Pointer::Link(link))?;
+                    if !inferred.object {
+                        return Err(format!(
+                            "Computable link `{}.{}` yields scalar type `{}`; declare it as a property.",
+                            ty.ident, link.ident, inferred.ty));
+                    }
+                    resolved.push((t, true, l, inferred));
+                }
+            }
+        }
+        for (t, is_link, p, inferred) in resolved {
+            let ty = &mut self.types[t];
+            if is_link {
+                let link = &mut ty.links.as_mut().unwrap()[p];
+                link.target = Some(inferred.ty);
+                link.multi = inferred.cardinality.is_multi();
+                link.readonly = true;
+            } else {
+                let prop = &mut ty.properties.as_mut().unwrap()[p];
+                prop.target = Some(inferred.ty);
+                prop.multi = inferred.cardinality.is_multi();
+                prop.readonly = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Inference<'m> {
+    module:   &'m Module,
+    bindings: Vec<(String, InferredType)>,
+    // Computables currently being inferred, to catch ones that refer to themselves.
+    visiting: Vec<(String, String)>
+}
+
+impl<'m> Inference<'m> {
+    fn new(module: &'m Module) -> Inference<'m> {
+        Inference { module, bindings: Vec::new(), visiting: Vec::new() }
+    }
+
+    fn pointer(&mut self, host: &Type, pointer: Pointer) -> Result<InferredType, String> {
+        let key = (host.ident.clone(), pointer.ident().to_string());
+        let expr = match pointer.expr() {
+            Some(expr) if pointer.is_computable() => expr,
+            _ => {
+                let target = pointer.target().ok_or_else(|| format!(
+                    "`{}.{}` has no target type.", host.ident, pointer.ident()))?;
+                let cardinality = Cardinality::from_bounds(pointer.required(), pointer.multi());
+                return Ok(InferredType {
+                    ty: target.to_string(),
+                    cardinality,
+                    object: pointer.is_link()
+                });
+            }
+        };
+        if self.visiting.contains(&key) {
+            return Err(format!("Computable `{}.{}` is defined in terms of itself.", key.0, key.1));
+        }
+        self.visiting.push(key);
+        let link = match pointer {
+            Pointer::Link(link) => Some(link),
+            Pointer::Property(_) => None
+        };
+        let parsed = expr.parse()
+            .map_err(|e| format!("In computable `{}.{}`: {}", host.ident, pointer.ident(), e))?;
+        let inferred = self.expr(&host.ident, link, &parsed);
+        self.visiting.pop();
+        inferred.map_err(|e| format!("In computable `{}.{}`: {}", host.ident, pointer.ident(), e))
+    }
+
+    fn expr(&mut self, subject: &str, link: Option<&Link>, expr: &Expr) -> Result<InferredType, String> {
+        use Cardinality::*;
+        match expr {
+            Expr::Literal(lit) => Ok(InferredType::scalar(literal_type(lit), One)),
+            Expr::Path(path) => self.path(subject, link, path),
+            Expr::Cast { ty, expr } => {
+                let inner = self.expr(subject, link, expr)?;
+                Ok(InferredType::scalar(ty, inner.cardinality))
+            },
+            Expr::Call { func, args } => {
+                let args = args.iter()
+                    .map(|arg| self.expr(subject, link, arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(func, &args)
+            },
+            Expr::UnaryOp { op, operand } => {
+                let operand = self.expr(subject, link, operand)?;
+                match op.as_str() {
+                    "EXISTS"   => Ok(InferredType::scalar("bool", One)),
+                    "NOT"      => Ok(InferredType::scalar("bool", operand.cardinality)),
+                    "DISTINCT" => Ok(operand),
+                    _          => {
+                        expect_numeric(&operand, op)?;
+                        Ok(operand)
+                    }
+                }
+            },
+            Expr::BinaryOp { left, op, right } => {
+                let left = self.expr(subject, link, left)?;
+                let right = self.expr(subject, link, right)?;
+                binary(&left, op, &right)
+            },
+            Expr::IfElse { then, condition, otherwise } => {
+                let condition = self.expr(subject, link, condition)?;
+                let then = self.expr(subject, link, then)?;
+                let otherwise = self.expr(subject, link, otherwise)?;
+                let ty = common_type(&then, &otherwise)?;
+                let cardinality = Cardinality::from_bounds(
+                    then.cardinality.is_required() && otherwise.cardinality.is_required(),
+                    then.cardinality.is_multi() || otherwise.cardinality.is_multi())
+                    .product(condition.cardinality);
+                Ok(InferredType { cardinality, ..ty })
+            },
+            Expr::Index { expr, index } => {
+                let base = self.expr(subject, link, expr)?;
+                let index = self.expr(subject, link, index)?;
+                let ty = match base.ty.strip_prefix("array<").and_then(|t| t.strip_suffix('>')) {
+                    Some(element) => element.to_string(),
+                    None => base.ty.clone()
+                };
+                Ok(InferredType::scalar(&ty, base.cardinality.product(index.cardinality)))
+            },
+            Expr::Tuple(elements) => {
+                let elements = elements.iter()
+                    .map(|el| self.expr(subject, link, el))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let cardinality = elements.iter().fold(One, |card, el| card.product(el.cardinality));
+                let names: Vec<&str> = elements.iter().map(|el| el.ty.as_str()).collect();
+                Ok(InferredType::scalar(&format!("tuple<{}>", names.join(", ")), cardinality))
+            },
+            Expr::Array(elements) => {
+                let elements = elements.iter()
+                    .map(|el| self.expr(subject, link, el))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let cardinality = elements.iter().fold(One, |card, el| card.product(el.cardinality));
+                let element = elements.iter().skip(1)
+                    .try_fold(elements.first().cloned(), |acc, el| match acc {
+                        Some(acc) => common_type(&acc, el).map(Some),
+                        None => Ok(None)
+                    })?;
+                let element = element.map_or("anytype".to_string(), |el| el.ty);
+                Ok(InferredType::scalar(&format!("array<{}>", element), cardinality))
+            },
+            Expr::Set(elements) => {
+                let elements = elements.iter()
+                    .map(|el| self.expr(subject, link, el))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match elements.split_first() {
+                    None => Ok(InferredType::scalar("anytype", AtMostOne)),
+                    Some((first, rest)) => rest.iter().try_fold(first.clone(), |acc, el| union(&acc, el))
+                }
+            },
+            Expr::Shape { expr, elements } => {
+                let base = self.expr(subject, link, expr)?;
+                self.shape(&base, elements)?;
+                Ok(base)
+            },
+            Expr::Select { expr, filter, order_by, offset, limit } => {
+                let selected = self.expr(subject, link, expr)?;
+                // Partial paths in the clauses refer to the selected objects.
+                let scope = if selected.object { selected.ty.clone() } else { subject.to_string() };
+                if let Some(filter) = filter {
+                    let filter = self.expr(&scope, None, filter)?;
+                    if bare(&filter.ty) != "bool" {
+                        return Err(format!("`FILTER` expects a bool, not `{}`.", filter.ty));
+                    }
+                }
+                for order in order_by.iter() {
+                    self.expr(&scope, None, &order.expr)?;
+                }
+                for clause in offset.iter().chain(limit.iter()) {
+                    let clause = self.expr(subject, link, clause)?;
+                    if bare(&clause.ty) != "int64" {
+                        return Err(format!("`OFFSET` and `LIMIT` expect an int64, not `{}`.", clause.ty));
+                    }
+                }
+                let mut cardinality = selected.cardinality;
+                if filter.is_some() || offset.is_some() || limit.is_some() {
+                    cardinality = cardinality.optional();
+                }
+                if matches!(limit.as_deref(), Some(Expr::Literal(Literal::Int(n))) if *n <= 1) {
+                    cardinality = AtMostOne;
+                }
+                Ok(InferredType { cardinality, ..selected })
+            },
+            Expr::With { .. } | Expr::For { .. } | Expr::Insert { .. } | Expr::Update { .. } | Expr::Delete { .. } => {
+                Err(format!("Cannot infer the type of `{}`.", expr))
+            }
+        }
+    }
+
+    /// Type a call by matching it against the function's overloads.
+    /// Arguments for singleton parameters make the call element-wise;
+    /// `OPTIONAL` ones do not make the result optional, and `SET OF`
+    /// ones are consumed whole.
+    fn call(&self, func: &str, args: &[InferredType]) -> Result<InferredType, String> {
+        use Cardinality::*;
+        let arg_types: Vec<&str> = args.iter().map(|arg| arg.ty.as_str()).collect();
+        let (function, ty) = self.module.match_call(func, &arg_types)?;
+        let positional: Vec<&Parameter> = function.positional().collect();
+        let mut cardinality = One;
+        for (i, arg) in args.iter().enumerate() {
+            let param = positional.get(i).copied().or_else(|| function.variadic()).unwrap();
+            cardinality = match param.typemod {
+                TypeModifier::Singleton => cardinality.product(arg.cardinality),
+                TypeModifier::Optional  => cardinality.product(Cardinality::from_bounds(true, arg.cardinality.is_multi())),
+                TypeModifier::SetOf     => cardinality
+            };
+        }
+        cardinality = match function.return_typemod {
+            TypeModifier::Singleton => cardinality,
+            TypeModifier::Optional  => cardinality.optional(),
+            TypeModifier::SetOf     => Many
+        };
+        // The assertions pass their input through, so they keep what is known of it.
+        match (bare(func), args.first()) {
+            ("assert_exists", Some(arg)) => cardinality = Cardinality::from_bounds(true, arg.cardinality.is_multi()),
+            ("assert_distinct", Some(arg)) => cardinality = arg.cardinality,
+            _ => {}
+        }
+        let object = ty == BASE_OBJECT || self.module.is_object(&ty);
+        Ok(InferredType { ty, cardinality, object })
+    }
+
+    /// Check the elements of a shape applied to `base`.
+    fn shape(&mut self, base: &InferredType, elements: &[ShapeElement]) -> Result<(), String> {
+        if !base.object {
+            return Err(format!("Cannot apply a shape to scalar type `{}`.", base.ty));
+        }
+        let single = InferredType { cardinality: Cardinality::One, ..base.clone() };
+        for element in elements {
+            match &element.expr {
+                Some(expr) => {
+                    self.expr(&base.ty, None, expr)
+                        .map_err(|e| format!("In shape element `{}`: {}", element.ident, e))?;
+                },
+                // Link properties are only known once the link is.
+                None if element.ident.starts_with('@') => {},
+                None => {
+                    let (target, _) = self.forward(&single, &element.ident)?;
+                    if !element.shape.is_empty() {
+                        self.shape(&target, &element.shape)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&mut self, subject: &str, link: Option<&Link>, path: &Path) -> Result<InferredType, String> {
+        let mut current = match &path.source {
+            PathSource::Partial | PathSource::Subject | PathSource::Source => {
+                if self.module.is_object(subject) {
+                    InferredType::object(subject, Cardinality::One)
+                } else {
+                    InferredType::scalar(subject, Cardinality::One)
+                }
+            },
+            PathSource::Type(ident) if self.bindings.iter().any(|(name, _)| name == ident) => {
+                self.bindings.iter().find(|(name, _)| name == ident).unwrap().1.clone()
+            },
+            PathSource::Type(ident) => {
+                if !self.module.is_object(ident) {
+                    return Err(format!("`{}` is not an object type.", ident));
+                }
+                InferredType::object(ident, Cardinality::Many)
+            },
+            PathSource::Expr(expr) => self.expr(subject, link, expr)?
+        };
+        // A leading `@` refers to the link being defined.
+        let mut last_link: Option<Link> = match path.source {
+            PathSource::Partial => link.cloned(),
+            _ => None
+        };
+        for step in path.steps.iter() {
+            match step {
+                PathStep::Forward(ident) => {
+                    if !current.object {
+                        return Err(format!("Cannot follow `.{}` from scalar type `{}`.", ident, current.ty));
+                    }
+                    let (next, next_link) = self.forward(&current, ident)?;
+                    last_link = next_link;
+                    current = next;
+                },
+                PathStep::LinkProperty(ident) => {
+                    let prop = last_link.as_ref()
+                        .and_then(|link| link.link_property(ident).cloned())
+                        .ok_or_else(|| format!("`@{}` does not refer to a link property.", ident))?;
+                    let target = match (&prop.target, &last_link) {
+                        (Some(target), _) => target.clone(),
+                        (None, Some(link)) if prop.is_computable() => {
+                            let expr = prop.expr.as_ref()
+                                .ok_or_else(|| format!("Computable link property `@{}` has no expression.", ident))?
+                                .parse()?;
+                            let link = link.clone();
+                            self.expr(link.target.as_deref().unwrap_or(BASE_OBJECT), Some(&link), &expr)?.ty
+                        },
+                        _ => return Err(format!("Link property `@{}` has no target type.", ident))
+                    };
+                    let cardinality = Cardinality::from_bounds(prop.required, false);
+                    current = InferredType::scalar(&target, current.cardinality.product(cardinality));
+                    last_link = None;
+                },
+                PathStep::Backward(ident) => {
+                    if !current.object {
+                        return Err(format!("Cannot follow `.<{}` from scalar type `{}`.", ident, current.ty));
+                    }
+                    let linked = self.module.types.iter().any(|ty| {
+                        matches!(self.module.find_pointer(ty, ident), Some(Pointer::Link(_)))
+                    });
+                    if !linked {
+                        return Err(format!("No type has a link called `{}`.", ident));
+                    }
+                    current = InferredType::object(BASE_OBJECT, current.cardinality.product(Cardinality::Many));
+                    last_link = None;
+                },
+                PathStep::TypeFilter(ident) => {
+                    if !self.module.is_object(ident) {
+                        return Err(format!("`[IS {}]` does not name an object type.", ident));
+                    }
+                    current = InferredType::object(ident, current.cardinality.optional());
+                }
+            }
+        }
+        Ok(current)
+    }
+
+    fn forward(&mut self, current: &InferredType, ident: &str) -> Result<(InferredType, Option<Link>), String> {
+        if ident == "id" {
+            return Ok((InferredType::scalar("uuid", current.cardinality), None));
+        }
+        let module = self.module;
+        let ty = module.get_type(&current.ty)
+            .ok_or_else(|| format!("`{}` has no pointer called `{}`.", current.ty, ident))?;
+        let pointer = module.find_pointer(ty, ident)
+            .ok_or_else(|| format!("`{}` has no pointer called `{}`.", ty.ident, ident))?;
+        let mut target = self.pointer(ty, pointer)?;
+        target.cardinality = current.cardinality.product(target.cardinality);
+        let link = match pointer {
+            Pointer::Link(link) => Some(link.clone()),
+            Pointer::Property(_) => None
+        };
+        Ok((target, link))
+    }
+}
+
+fn literal_type(lit: &Literal) -> &'static str {
+    match lit {
+        Literal::Str(_)   => "str",
+        Literal::Int(_)   => "int64",
+        Literal::Float(_) => "float64",
+        Literal::Bool(_)  => "bool"
+    }
+}
+
+fn bare(ty: &str) -> &str {
+    ty.trim_start_matches("std::")
+}
+
+fn is_numeric(ty: &str) -> bool {
+    matches!(bare(ty),
+        "int16" | "int32" | "int64" | "bigint" | "float32" | "float64" | "decimal"
+        | "anyint" | "anyfloat" | "anyreal")
+}
+
+fn is_float(ty: &str) -> bool {
+    matches!(bare(ty), "float32" | "float64" | "anyfloat")
+}
+
+fn expect_numeric(operand: &InferredType, op: &str) -> Result<(), String> {
+    if is_numeric(&operand.ty) {
+        Ok(())
+    } else {
+        Err(format!("Operator `{}` cannot be applied to `{}`.", op, operand.ty))
+    }
+}
+
+/// The numeric type that both operands are implicitly cast to.
+fn numeric_type(left: &str, right: &str) -> &'static str {
+    let (left, right) = (bare(left), bare(right));
+    if is_float(left) || is_float(right) {
+        "float64"
+    } else if left == "decimal" || right == "decimal" {
+        "decimal"
+    } else if left == "bigint" || right == "bigint" {
+        "bigint"
+    } else {
+        "int64"
+    }
+}
+
+/// The type that values of both `left` and `right` can be treated as.
+fn common_type(left: &InferredType, right: &InferredType) -> Result<InferredType, String> {
+    let ty = if bare(&left.ty) == bare(&right.ty) || right.ty == "anytype" {
+        left.ty.clone()
+    } else if left.ty == "anytype" {
+        right.ty.clone()
+    } else if is_numeric(&left.ty) && is_numeric(&right.ty) {
+        numeric_type(&left.ty, &right.ty).to_string()
+    } else if left.object && right.object {
+        BASE_OBJECT.to_string()
+    } else {
+        return Err(format!("Types `{}` and `{}` are incompatible.", left.ty, right.ty));
+    };
+    Ok(InferredType { ty, cardinality: left.cardinality, object: left.object && right.object })
+}
+
+fn union(left: &InferredType, right: &InferredType) -> Result<InferredType, String> {
+    let ty = common_type(left, right)?;
+    let cardinality = Cardinality::from_bounds(
+        left.cardinality.is_required() || right.cardinality.is_required(),
+        true);
+    Ok(InferredType { cardinality, ..ty })
+}
+
+fn binary(left: &InferredType, op: &str, right: &InferredType) -> Result<InferredType, String> {
+    let cardinality = left.cardinality.product(right.cardinality);
+    match op {
+        "UNION" => union(left, right),
+        "??" => {
+            let ty = common_type(left, right)?;
+            let cardinality = if left.cardinality.is_required() {
+                left.cardinality
+            } else {
+                Cardinality::from_bounds(
+                    right.cardinality.is_required(),
+                    left.cardinality.is_multi() || right.cardinality.is_multi())
+            };
+            Ok(InferredType { cardinality, ..ty })
+        },
+        "?=" | "?!=" => Ok(InferredType::scalar("bool", Cardinality::One)),
+        "IN" | "NOT IN" => Ok(InferredType::scalar("bool", left.cardinality)),
+        "=" | "!=" | "<" | ">" | "<=" | ">=" => {
+            common_type(left, right)?;
+            Ok(InferredType::scalar("bool", cardinality))
+        },
+        "AND" | "OR" | "LIKE" | "ILIKE" => Ok(InferredType::scalar("bool", cardinality)),
+        "++" => {
+            let ty = common_type(left, right)?;
+            Ok(InferredType { cardinality, ..ty })
+        },
+        "/" => {
+            expect_numeric(left, op)?;
+            expect_numeric(right, op)?;
+            let ty = match numeric_type(&left.ty, &right.ty) {
+                "decimal" | "bigint" => "decimal",
+                _ => "float64"
+            };
+            Ok(InferredType::scalar(ty, cardinality))
+        },
+        _ => {
+            expect_numeric(left, op)?;
+            expect_numeric(right, op)?;
+            Ok(InferredType::scalar(numeric_type(&left.ty, &right.ty), cardinality))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{LinkBuilder, PropertyBuilder, TypeBuilder};
+    use crate::fixtures;
+
+    fn cards() -> Module {
+        let named = TypeBuilder::default()
+            .ident("Named")
+            .abs(true)
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("name").target("str").required(true).build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let card = TypeBuilder::default()
+            .ident("Card")
+            .extends(vec!["Named".to_string()])
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("cost").target("int64").required(true).build().unwrap(),
+                PropertyBuilder::default().ident("element").target("str").required(true).build().unwrap(),
+                PropertyBuilder::default()
+                    .ident("elemental_cost")
+                    .computable("<str>.cost ++ ' ' ++ .element")
+                    .build()
+                    .unwrap()
+            ]))
+            .links(Box::new(vec![
+                LinkBuilder::default()
+                    .ident("owners")
+                    .computable("__source__.<deck[IS User]")
+                    .build()
+                    .unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let user = TypeBuilder::default()
+            .ident("User")
+            .extends(vec!["Named".to_string()])
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("deck_cost").computable("sum(.deck.cost)").build().unwrap()
+            ]))
+            .links(Box::new(vec![
+                LinkBuilder::default().ident("deck").target("Card").multi(true).build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        fixtures::module(vec![named, card, user])
+    }
+
+    #[test]
+    fn infers_computables() {
+        let mut module = cards();
+        module.resolve_computables().unwrap();
+
+        let user = module.get_type("User").unwrap();
+        let deck_cost = module.find_pointer(user, "deck_cost").unwrap();
+        assert_eq!(deck_cost.target(), Some("int64"));
+        assert!(!deck_cost.multi());
+
+        let card = module.get_type("Card").unwrap();
+        let owners = module.find_pointer(card, "owners").unwrap();
+        assert_eq!(owners.target(), Some("User"));
+        assert!(owners.multi());
+
+        let elemental = module.find_pointer(card, "elemental_cost").unwrap();
+        assert_eq!(elemental.target(), Some("str"));
+        assert!(matches!(elemental, Pointer::Property(prop) if prop.readonly));
+    }
+
+    #[test]
+    fn rejects_unknown_pointers() {
+        let module = cards();
+        let expr = crate::edgeql::parse("sum(.deck.price)").unwrap();
+        assert!(infer(&module, "User", &expr).is_err());
+
+        let expr = crate::edgeql::parse("EXISTS (.deck)").unwrap();
+        let inferred = infer(&module, "User", &expr).unwrap();
+        assert_eq!(inferred, InferredType::scalar("bool", Cardinality::One));
+    }
+}
@@ -0,0 +1,183 @@
+//! Merging of inherited schema items into the items that extend them.
+use crate::constraints::same_constraint;
+use crate::datastructures::{Annotation, Constraint, Link, Module, Property};
+
+impl Module {
+    /// Merge abstract properties and links into the concrete pointers that
+    /// extend them. Each pointer gains the constraints and annotations of
+    /// its bases, and takes their target and `readonly` flag when it has
+    /// none of its own. Abstract pointers may themselves extend others.
+    pub fn resolve_abstract_pointers(&mut self) -> Result<(), String> {
+        let mut properties = Vec::new();
+        for prop in self.properties.iter() {
+            if !prop.is_abstract() {
+                return Err(format!("Module-level property `{}` must be abstract.", prop.ident));
+            }
+            properties.push(self.flatten_property(prop, &mut Vec::new())?);
+        }
+        let mut links = Vec::new();
+        for link in self.links.iter() {
+            if !link.is_abstract() {
+                return Err(format!("Module-level link `{}` must be abstract.", link.ident));
+            }
+            links.push(self.flatten_link(link, &mut Vec::new())?);
+        }
+        self.properties = properties;
+        self.links = links;
+
+        let module = self.clone();
+        for ty in self.types.iter_mut() {
+            let ty_ident = ty.ident.clone();
+            for prop in ty.properties.iter_mut().flat_map(|props| props.iter_mut()) {
+                for base in prop.extends.clone().unwrap_or_default() {
+                    let base = module.get_property(&base).ok_or_else(|| format!(
+                        "`{}.{}` extends unknown abstract property `{}`.", ty_ident, prop.ident, base))?;
+                    merge_property(prop, base);
+                }
+            }
+            for link in ty.links.iter_mut().flat_map(|links| links.iter_mut()) {
+                for base in link.extends.clone().unwrap_or_default() {
+                    let base = module.get_link(&base).ok_or_else(|| format!(
+                        "`{}.{}` extends unknown abstract link `{}`.", ty_ident, link.ident, base))?;
+                    merge_link(link, base);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flatten_property(&self, prop: &Property, seen: &mut Vec<String>) -> Result<Property, String> {
+        if seen.contains(&prop.ident) {
+            return Err(format!("Abstract property `{}` extends itself.", prop.ident));
+        }
+        seen.push(prop.ident.clone());
+        let mut flat = prop.clone();
+        for base in prop.extends.iter().flat_map(|bases| bases.iter()) {
+            let base = self.get_property(base).ok_or_else(|| format!(
+                "Abstract property `{}` extends unknown abstract property `{}`.", prop.ident, base))?;
+            let base = self.flatten_property(base, seen)?;
+            merge_property(&mut flat, &base);
+        }
+        seen.pop();
+        Ok(flat)
+    }
+
+    fn flatten_link(&self, link: &Link, seen: &mut Vec<String>) -> Result<Link, String> {
+        if seen.contains(&link.ident) {
+            return Err(format!("Abstract link `{}` extends itself.", link.ident));
+        }
+        seen.push(link.ident.clone());
+        let mut flat = link.clone();
+        for base in link.extends.iter().flat_map(|bases| bases.iter()) {
+            let base = self.get_link(base).ok_or_else(|| format!(
+                "Abstract link `{}` extends unknown abstract link `{}`.", link.ident, base))?;
+            let base = self.flatten_link(base, seen)?;
+            merge_link(&mut flat, &base);
+        }
+        seen.pop();
+        Ok(flat)
+    }
+}
+
+fn merge_property(prop: &mut Property, base: &Property) {
+    if prop.target.is_none() {
+        prop.target = base.target.clone();
+    }
+    prop.readonly |= base.readonly;
+    if let Some(inherited) = &base.constraints {
+        merge_constraints(prop.constraints.get_or_insert_with(Default::default), inherited);
+    }
+    if let Some(inherited) = &base.annotations {
+        merge_annotations(prop.annotations.get_or_insert_with(Default::default), inherited);
+    }
+}
+
+fn merge_link(link: &mut Link, base: &Link) {
+    if link.target.is_none() {
+        link.target = base.target.clone();
+    }
+    link.readonly |= base.readonly;
+    if let Some(inherited) = &base.constraints {
+        merge_constraints(link.constraints.get_or_insert_with(Default::default), inherited);
+    }
+    if let Some(inherited) = &base.annotations {
+        merge_annotations(link.annotations.get_or_insert_with(Default::default), inherited);
+    }
+    for base_prop in base.properties.iter().flat_map(|props| props.iter()) {
+        let props = link.properties.get_or_insert_with(Default::default);
+        match props.iter_mut().find(|prop| prop.ident == base_prop.ident) {
+            Some(prop) => merge_property(prop, base_prop),
+            None => props.push(base_prop.clone())
+        }
+    }
+}
+
+fn merge_constraints(own: &mut Vec<Constraint>, inherited: &[Constraint]) {
+    for constraint in inherited {
+        if !own.iter().any(|seen| same_constraint(seen, constraint)) {
+            own.push(constraint.clone());
+        }
+    }
+}
+
+/// Annotations set on the inheriting item win over inherited ones.
+pub(crate) fn merge_annotations(own: &mut Vec<Annotation>, inherited: &[Annotation]) {
+    for annotation in inherited {
+        if !own.iter().any(|seen| seen.ident == annotation.ident) {
+            own.push(annotation.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{AnnotationBuilder, ConstraintBuilder, Expression, PropertyBuilder, TypeBuilder};
+
+    #[test]
+    fn concrete_pointers_inherit_abstract_ones() {
+        let max_len = ConstraintBuilder::default()
+            .ident("max_len_value")
+            .args(Box::new(vec![Expression::new("100")]))
+            .build()
+            .unwrap();
+        let description = AnnotationBuilder::default()
+            .ident("description")
+            .value("A human readable title")
+            .build()
+            .unwrap();
+        let title = PropertyBuilder::default()
+            .ident("title")
+            .abs(true)
+            .target("str")
+            .constraints(Box::new(vec![max_len]))
+            .annotations(Box::new(vec![description]))
+            .build()
+            .unwrap();
+        let book_title = PropertyBuilder::default()
+            .ident("title")
+            .required(true)
+            .extends(vec!["title".to_string()])
+            .build()
+            .unwrap();
+        let book = TypeBuilder::default()
+            .ident("Book")
+            .properties(Box::new(vec![book_title]))
+            .build()
+            .unwrap();
+        let mut module = Module::new("default");
+        module.properties.push(title);
+        module.types.push(book);
+        module.resolve_abstract_pointers().unwrap();
+
+        let prop = &module.types[0].properties.as_ref().unwrap()[0];
+        assert_eq!(prop.target.as_deref(), Some("str"));
+        assert!(prop.required);
+        assert_eq!(prop.constraints.as_ref().unwrap().len(), 1);
+        assert_eq!(prop.annotations.as_ref().unwrap()[0].ident, "description");
+        assert!(module.validate_constraints().is_ok());
+
+        module.types[0].properties.as_mut().unwrap()[0].extends = Some(vec!["subtitle".to_string()]);
+        assert!(module.resolve_abstract_pointers().is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//! Building the schema model from what a running EdgeDB instance
+//! reports about itself, so that a deployed schema can be compared
+//! with the one checked in.
+//!
+//! Run `INTROSPECT_SCHEMA` with JSON output and hand the result to
+//! `Module::from_introspection`, or hand the raw data chunks of the
+//! response to `Module::from_introspection_data`.
+use serde::Deserialize;
+use serde_json::Value;
+use crate::datastructures::{
+    Annotation, Constraint, Expression, Function, Index, Language, Link, LinkKind, Module, Parameter,
+    ParameterKind, Property, PropertyKind, Subcommand, Type, TypeModifier, Volatility
+};
+
+/// Selects every type and function of module `$module`, in the
+/// shape that the loader expects.
+pub const INTROSPECT_SCHEMA: &str = r#"
+WITH MODULE schema
+SELECT {
+    object_types := (
+        SELECT ObjectType {
+            name, is_abstract, is_final, builtin, internal,
+            bases: { name },
+            annotations: { name, @value },
+            constraints: { name, delegated, subjectexpr, errmessage, params: { name, @value } },
+            indexes: { expr },
+            pointers: {
+                __type__: { name },
+                name, cardinality, required, readonly, expr, default, @is_owned,
+                target: { name },
+                annotations: { name, @value },
+                constraints: { name, delegated, subjectexpr, errmessage, params: { name, @value } },
+                properties: {
+                    name, cardinality, required, readonly, expr, default,
+                    target: { name }
+                } FILTER .name NOT IN { 'source', 'target' }
+            }
+        } FILTER .name LIKE <str>$module ++ '::%'
+    ),
+    scalar_types := (
+        SELECT ScalarType {
+            name, is_abstract, is_final, builtin, internal,
+            bases: { name },
+            annotations: { name, @value },
+            constraints: { name, delegated, subjectexpr, errmessage, params: { name, @value } }
+        } FILTER .name LIKE <str>$module ++ '::%'
+    ),
+    functions := (
+        SELECT `Function` {
+            name, volatility, language, body,
+            annotations: { name, @value },
+            params: { kind, name, num, typemod, type: { name }, default } ORDER BY .num,
+            return_typemod,
+            return_type: { name }
+        } FILTER .name LIKE <str>$module ++ '::%'
+    )
+};
+"#;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Introspection {
+    object_types: Vec<IntrospectedType>,
+    scalar_types: Vec<IntrospectedType>,
+    functions:    Vec<IntrospectedFunction>
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    name: String
+}
+
+#[derive(Debug, Deserialize)]
+struct NamedValue {
+    name:  String,
+    #[serde(rename = "@value")]
+    value: Option<String>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IntrospectedType {
+    name:        String,
+    is_abstract: Option<bool>,
+    is_final:    Option<bool>,
+    builtin:     bool,
+    internal:    bool,
+    bases:       Vec<Named>,
+    annotations: Vec<NamedValue>,
+    constraints: Vec<IntrospectedConstraint>,
+    indexes:     Vec<IntrospectedIndex>,
+    pointers:    Vec<IntrospectedPointer>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IntrospectedConstraint {
+    name:        String,
+    delegated:   bool,
+    subjectexpr: Option<String>,
+    errmessage:  Option<String>,
+    params:      Vec<NamedValue>
+}
+
+#[derive(Debug, Deserialize)]
+struct IntrospectedIndex {
+    expr: String
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IntrospectedPointer {
+    #[serde(rename = "__type__")]
+    kind:        Option<Named>,
+    name:        String,
+    cardinality: Option<String>,
+    required:    Option<bool>,
+    readonly:    Option<bool>,
+    expr:        Option<String>,
+    default:     Option<String>,
+    #[serde(rename = "@is_owned")]
+    is_owned:    Option<bool>,
+    target:      Option<Named>,
+    annotations: Vec<NamedValue>,
+    constraints: Vec<IntrospectedConstraint>,
+    properties:  Vec<IntrospectedPointer>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IntrospectedFunction {
+    name:           String,
+    volatility:     Option<String>,
+    language:       Option<String>,
+    body:           Option<String>,
+    annotations:    Vec<NamedValue>,
+    params:         Vec<IntrospectedParameter>,
+    return_typemod: Option<String>,
+    return_type:    Option<Named>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IntrospectedParameter {
+    kind:    Option<String>,
+    name:    String,
+    typemod: Option<String>,
+    #[serde(rename = "type")]
+    ty:      Option<Named>,
+    default: Option<String>
+}
+
+impl Module {
+    /// Build module `ident` from the JSON result of `INTROSPECT_SCHEMA`,
+    /// either as a single object or as an array of them.
+    pub fn from_introspection(ident: &str, json: &str) -> Result<Module, String> {
+        Module::from_introspection_data(ident, &[json])
+    }
+
+    /// Like `from_introspection`, for a response that arrived in several
+    /// data chunks, each holding JSON text.
+    pub fn from_introspection_data<B: AsRef<[u8]>>(ident: &str, chunks: &[B]) -> Result<Module, String> {
+        let mut results: Vec<Introspection> = Vec::new();
+        for chunk in chunks {
+            let value: Value = serde_json::from_slice(chunk.as_ref())
+                .map_err(|e| format!("Introspection data is not valid JSON: {}", e))?;
+            let values = match value {
+                Value::Array(values) => values,
+                value => vec![value]
+            };
+            for value in values {
+                results.push(serde_json::from_value(value)
+                    .map_err(|e| format!("Unexpected introspection data: {}", e))?);
+            }
+        }
+        let loader = Loader { module: ident };
+        let mut module = Module::new(ident);
+        for result in results {
+            for ty in result.scalar_types.iter() {
+                module.types.push(loader.ty(ty, true)?);
+            }
+            for ty in result.object_types.iter() {
+                module.types.push(loader.ty(ty, false)?);
+            }
+            for func in result.functions.iter() {
+                module.functions.push(loader.function(func)?);
+            }
+        }
+        Ok(module)
+    }
+}
+
+/// Implicit pointers that every object type has.
+const IMPLICIT_POINTERS: &[&str] = &["id", "__type__"];
+
+struct Loader<'a> {
+    module: &'a str
+}
+
+impl Loader<'_> {
+    /// Turn a fully-qualified name into the one the model uses: local
+    /// names for this module's items and bare names for `std` ones,
+    /// also inside of collection types such as `array<std::str>`.
+    fn name(&self, name: &str) -> String {
+        let local = format!("{}::", self.module);
+        let mut result = String::new();
+        let mut rest = name;
+        loop {
+            let end = rest.find(['<', '>', ',', ' ']).unwrap_or(rest.len());
+            let part = &rest[..end];
+            result.push_str(part.strip_prefix(local.as_str()).or_else(|| part.strip_prefix("std::")).unwrap_or(part));
+            match rest[end..].chars().next() {
+                Some(separator) => {
+                    result.push(separator);
+                    rest = &rest[end + 1..];
+                },
+                None => return result
+            }
+        }
+    }
+
+    fn ty(&self, ty: &IntrospectedType, scalar: bool) -> Result<Type, String> {
+        let context = |e: String| format!("In `{}`: {}", ty.name, e);
+        let mut properties = Vec::new();
+        let mut links = Vec::new();
+        for pointer in ty.pointers.iter() {
+            if IMPLICIT_POINTERS.contains(&pointer.name.as_str()) || pointer.is_owned == Some(false) {
+                continue;
+            }
+            let is_link = matches!(&pointer.kind, Some(kind) if kind.name == "schema::Link");
+            if is_link {
+                links.push(self.link(pointer).map_err(context)?);
+            } else {
+                properties.push(self.property(pointer).map_err(context)?);
+            }
+        }
+        let bases: Vec<String> = ty.bases.iter().map(|base| self.name(&base.name)).collect();
+        Ok(Type {
+            ident: self.name(&ty.name),
+            abs: ty.is_abstract.unwrap_or(false),
+            scalar,
+            is_final: ty.is_final.unwrap_or(false),
+            builtin: ty.builtin,
+            is_internal: ty.internal,
+            alias: false,
+            extends: if bases.is_empty() { None } else { Some(bases) },
+            properties: boxed(properties),
+            annotations: boxed(annotations(&ty.annotations)),
+            links: boxed(links),
+            constraints: boxed(ty.constraints.iter().map(|c| self.constraint(c)).collect()),
+            indices: boxed(ty.indexes.iter()
+                .map(|index| Index { expr: Expression::new(&index.expr), annotations: None })
+                .collect())
+        })
+    }
+
+    fn property(&self, pointer: &IntrospectedPointer) -> Result<Property, String> {
+        Ok(Property {
+            ident: pointer.name.clone(),
+            overloaded: false,
+            abs: false,
+            readonly: pointer.readonly.unwrap_or(false) || pointer.expr.is_some(),
+            required: pointer.required.unwrap_or(false),
+            multi: multi(pointer)?,
+            kind: if pointer.expr.is_some() { PropertyKind::COMPUTABLE } else { PropertyKind::CONCRETE },
+            target: pointer.target.as_ref().map(|target| self.name(&target.name)),
+            expr: pointer.expr.as_deref().map(Expression::new),
+            default: pointer.default.as_deref().map(Expression::new),
+            constraints: boxed(pointer.constraints.iter().map(|c| self.constraint(c)).collect()),
+            annotations: boxed(annotations(&pointer.annotations)),
+            extends: None,
+            module: None
+        })
+    }
+
+    fn link(&self, pointer: &IntrospectedPointer) -> Result<Link, String> {
+        let properties = pointer.properties.iter()
+            .filter(|prop| !["source", "target"].contains(&prop.name.as_str()))
+            .map(|prop| self.property(prop))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Link {
+            ident: pointer.name.clone(),
+            overloaded: false,
+            abs: false,
+            readonly: pointer.readonly.unwrap_or(false) || pointer.expr.is_some(),
+            required: pointer.required.unwrap_or(false),
+            multi: multi(pointer)?,
+            kind: if pointer.expr.is_some() { LinkKind::COMPUTABLE } else { LinkKind::CONCRETE },
+            target: pointer.target.as_ref().map(|target| self.name(&target.name)),
+            expr: pointer.expr.as_deref().map(Expression::new),
+            properties: boxed(properties),
+            default: pointer.default.as_deref().map(Expression::new),
+            constraints: boxed(pointer.constraints.iter().map(|c| self.constraint(c)).collect()),
+            annotations: boxed(annotations(&pointer.annotations)),
+            extends: None,
+            module: None
+        })
+    }
+
+    fn constraint(&self, constraint: &IntrospectedConstraint) -> Constraint {
+        // The first parameter of every constraint is its subject,
+        // which has no value of its own.
+        let args: Vec<Expression> = constraint.params.iter()
+            .filter(|param| param.name != "__subject__")
+            .filter_map(|param| param.value.as_deref().map(Expression::new))
+            .collect();
+        let subcommands: Vec<Subcommand> = constraint.errmessage.iter()
+            .map(|errmessage| Subcommand { using: None, err_message: Some(errmessage.clone()), annotation: None })
+            .collect();
+        Constraint {
+            ident: self.name(&constraint.name),
+            extends: None,
+            abs: false,
+            delegated: constraint.delegated,
+            on_abstract_types: false,
+            on_concrete_scalar_types: false,
+            on_concrete_object_types: false,
+            args: boxed(args),
+            on: constraint.subjectexpr.as_deref().map(Expression::new),
+            params: None,
+            subcommands: boxed(subcommands)
+        }
+    }
+
+    fn function(&self, func: &IntrospectedFunction) -> Result<Function, String> {
+        let context = |e: String| format!("In function `{}`: {}", func.name, e);
+        let params = func.params.iter()
+            .map(|param| self.parameter(param))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(context)?;
+        let language = match func.language.as_deref() {
+            Some("builtin") => Language::Builtin,
+            Some("edgeql") | None => Language::EdgeQL,
+            Some(other) => return Err(context(format!("Unknown function language `{}`.", other)))
+        };
+        let volatility = match func.volatility.as_deref() {
+            Some("Immutable") => Volatility::Immutable,
+            Some("Stable") => Volatility::Stable,
+            Some("Volatile") | None => Volatility::Volatile,
+            Some(other) => return Err(context(format!("Unknown volatility `{}`.", other)))
+        };
+        Ok(Function {
+            ident: self.name(&func.name),
+            params,
+            return_type: func.return_type.as_ref().map(|ty| self.name(&ty.name))
+                .ok_or_else(|| context("Missing return type.".to_string()))?,
+            return_typemod: typemod(func.return_typemod.as_deref()).map_err(context)?,
+            volatility,
+            language,
+            body: func.body.as_deref().map(Expression::new),
+            annotations: boxed(annotations(&func.annotations))
+        })
+    }
+
+    fn parameter(&self, param: &IntrospectedParameter) -> Result<Parameter, String> {
+        let kind = match param.kind.as_deref() {
+            Some("PositionalParam") | None => ParameterKind::Positional,
+            Some("NamedOnlyParam") => ParameterKind::NamedOnly,
+            Some("VariadicParam") => ParameterKind::Variadic,
+            Some(other) => return Err(format!("Unknown parameter kind `{}`.", other))
+        };
+        Ok(Parameter {
+            ident: param.name.clone(),
+            ty: param.ty.as_ref().map(|ty| self.name(&ty.name))
+                .ok_or_else(|| format!("Parameter `{}` has no type.", param.name))?,
+            kind,
+            typemod: typemod(param.typemod.as_deref())?,
+            default: param.default.as_deref().map(Expression::new)
+        })
+    }
+}
+
+fn multi(pointer: &IntrospectedPointer) -> Result<bool, String> {
+    match pointer.cardinality.as_deref() {
+        Some("One") | None => Ok(false),
+        Some("Many") => Ok(true),
+        Some(other) => Err(format!("`{}` has unknown cardinality `{}`.", pointer.name, other))
+    }
+}
+
+fn typemod(typemod: Option<&str>) -> Result<TypeModifier, String> {
+    match typemod {
+        Some("SingletonType") | None => Ok(TypeModifier::Singleton),
+        Some("OptionalType") => Ok(TypeModifier::Optional),
+        Some("SetOfType") => Ok(TypeModifier::SetOf),
+        Some(other) => Err(format!("Unknown type modifier `{}`.", other))
+    }
+}
+
+fn annotations(annotations: &[NamedValue]) -> Vec<Annotation> {
+    annotations.iter()
+        .map(|anno| Annotation {
+            ident: anno.name.clone(),
+            value: anno.value.clone().unwrap_or_default()
+        })
+        .collect()
+}
+
+#[allow(clippy::box_collection)]
+fn boxed<T>(items: Vec<T>) -> Option<Box<Vec<T>>> {
+    if items.is_empty() { None } else { Some(Box::new(items)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{Module, Pointer, TypeModifier};
+    use super::Loader;
+
+    const CARDS: &str = include_str!("magic/introspection/cards.json");
+
+    #[test]
+    fn loads_recorded_introspection() {
+        let module = Module::from_introspection("default", CARDS).unwrap();
+
+        let card = module.get_type("Card").unwrap();
+        assert!(!card.scalar && !card.builtin && !card.is_final);
+        assert_eq!(card.extends.as_deref(), Some(&["Named".to_string()][..]));
+        assert!(card.pointer("name").is_none(), "inherited pointers are not repeated");
+        assert!(matches!(card.pointer("cost"), Some(Pointer::Property(p)) if p.required && p.target.as_deref() == Some("int64")));
+        let awaken = module.get_type("AwakenedCard").unwrap();
+        assert!(awaken.is_final);
+
+        let named = module.get_type("Named").unwrap();
+        assert!(named.abs && named.pointer("id").is_none());
+
+        let user = module.get_type("User").unwrap();
+        let deck = match user.pointer("deck") {
+            Some(Pointer::Link(link)) => link,
+            other => panic!("{:?}", other)
+        };
+        assert!(deck.multi);
+        assert_eq!(deck.link_property("count").unwrap().target.as_deref(), Some("int16"));
+        assert!(user.pointer("deck_cost").unwrap().is_computable());
+        assert_eq!(user.indices.as_ref().unwrap()[0].expr.ident, ".name");
+
+        let element = module.get_type("Element").unwrap();
+        assert!(element.scalar);
+        let constraint = &element.constraints.as_ref().unwrap()[0];
+        assert_eq!(constraint.ident, "one_of");
+        assert_eq!(constraint.args.as_ref().unwrap().len(), 2);
+
+        let func = module.get_functions("deck_total").next().unwrap();
+        assert_eq!(func.return_type, "int64");
+        assert_eq!(func.params[0].typemod, TypeModifier::SetOf);
+        assert!(module.validate().is_empty(), "{}", module.validate());
+    }
+
+    #[test]
+    fn strips_only_whole_module_prefixes() {
+        let loader = Loader { module: "cards" };
+        assert_eq!(loader.name("cards::Card"), "Card");
+        assert_eq!(loader.name("mycards::Card"), "mycards::Card");
+        assert_eq!(loader.name("std::str"), "str");
+        assert_eq!(loader.name("cal::local_date"), "cal::local_date");
+        assert_eq!(
+            loader.name("tuple<cards::Card, array<std::int64>, notstd::X>"),
+            "tuple<Card, array<int64>, notstd::X>"
+        );
+    }
+
+    #[test]
+    fn loads_chunked_data() {
+        let whole = Module::from_introspection("default", CARDS).unwrap();
+        let chunks = [format!("[{}]", CARDS).into_bytes()];
+        let chunked = Module::from_introspection_data("default", &chunks).unwrap();
+        assert_eq!(whole.fingerprint(), chunked.fingerprint());
+        assert!(Module::from_introspection("default", "{\"object_types\": 1}").is_err());
+    }
+}
@@ -1,4 +1,25 @@
 pub mod datastructures;
+pub mod edgeql;
+pub mod inference;
+pub mod constraints;
+pub mod inheritance;
+pub mod indexes;
+pub mod annotations;
+pub mod aliases;
+pub mod functions;
+pub mod visit;
+pub mod validate;
+pub mod serialize;
+pub mod fingerprint;
+pub mod introspection;
+pub mod diff;
+pub mod apply;
+pub mod ddl;
+pub mod rename;
+pub mod merge;
+pub mod compat;
+#[cfg(test)]
+mod fixtures;
 use datastructures::{TypeBuilder, Type};
 
 pub fn new_scalar_type(ident: &str) -> Type {
@@ -28,4 +49,52 @@ mod tests {
         dbg!(&scalar);
         assert_eq!(scalar.scalar, true);
     }
+
+    #[test]
+    fn link_properties() {
+        use datastructures::{LinkBuilder, PropertyBuilder};
+
+        let count = PropertyBuilder::default()
+            .ident("count")
+            .target("int64")
+            .build()
+            .unwrap();
+        let deck = LinkBuilder::default()
+            .ident("deck")
+            .multi(true)
+            .target("Card")
+            .properties(Box::new(vec![count]))
+            .build()
+            .unwrap();
+        let user = TypeBuilder::default()
+            .ident("User")
+            .links(Box::new(vec![deck.clone()]))
+            .build()
+            .unwrap();
+
+        let module = fixtures::module(vec![_new_object_type("Card"), user]);
+        assert!(module.validate_link_properties().is_ok());
+        assert_eq!(deck.link_properties().next().unwrap().0, "@count");
+        assert!(deck.link_property("@count").is_some());
+
+        let owner = PropertyBuilder::default()
+            .ident("owner")
+            .target("User")
+            .build()
+            .unwrap();
+        let mut nested = deck;
+        nested.properties = Some(Box::new(vec![owner]));
+        assert!(nested.validate_properties(&module).is_err());
+    }
+
+    #[test]
+    fn qualified_lookups() {
+        use datastructures::Module;
+
+        let mut module = Module::new("other");
+        module.types = vec![_new_object_type("User")];
+        assert!(module.get_type("User").is_some());
+        assert!(module.get_type("other::User").is_some());
+        assert!(module.get_type("default::User").is_none());
+    }
 }
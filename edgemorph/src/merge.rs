@@ -0,0 +1,225 @@
+//! Three-way merge of two schemas that were changed independently
+//! from a common base, e.g. by migrations written on two branches.
+//!
+//! Items are matched by name, like `diff` does. An item changed on one
+//! side only takes that side's version; an item changed on both sides
+//! in different ways is a conflict, except for object types, whose
+//! pointers, constraints, indices and annotations are merged one by
+//! one. Two branches that alter the same property therefore conflict,
+//! while two branches that add different properties to a type do not.
+use std::fmt;
+use serde::Serialize;
+use crate::datastructures::{
+    list, AbstractAnnotation, Alias, Annotation, Constraint, Index, Link, Module, Property, Type
+};
+use crate::diff::{constraint_key, overload_key, same_json};
+use crate::fingerprint::canonical_expression;
+
+/// An item that both sides changed in ways that cannot be combined.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    /// The item, e.g. `default::User.email`.
+    pub item: String,
+    pub kind: ConflictKind
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConflictKind {
+    /// Both sides added an item with this name, differently.
+    BothAdded,
+    /// Both sides changed the item, differently.
+    BothChanged,
+    /// One side changed the item and the other removed it.
+    ChangedAndRemoved
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` was ", self.item)?;
+        f.write_str(match self.kind {
+            ConflictKind::BothAdded => "added differently on both sides",
+            ConflictKind::BothChanged => "changed differently on both sides",
+            ConflictKind::ChangedAndRemoved => "changed on one side and removed on the other"
+        })
+    }
+}
+
+/// `base` with the changes of both `ours` and `theirs`, or every
+/// conflict between them. Items keep the order of `ours`, followed by
+/// the ones that only `theirs` added.
+pub fn merge(base: &Module, ours: &Module, theirs: &Module) -> Result<Module, Vec<Conflict>> {
+    let mut conflicts = Vec::new();
+    let mut merged = Module::new(&ours.ident);
+    let module = &format!("{}::", ours.ident);
+    merged.types = Merge::new(module, |ty: &Type| ty.ident.clone())
+        .nested(merge_type)
+        .run(&base.types, &ours.types, &theirs.types, &mut conflicts);
+    merged.constraints = Merge::new(module, |c: &Constraint| c.ident.clone())
+        .run(&base.constraints, &ours.constraints, &theirs.constraints, &mut conflicts);
+    merged.properties = Merge::new(module, |p: &Property| p.ident.clone())
+        .run(&base.properties, &ours.properties, &theirs.properties, &mut conflicts);
+    merged.links = Merge::new(module, |l: &Link| l.ident.clone())
+        .run(&base.links, &ours.links, &theirs.links, &mut conflicts);
+    merged.annotations = Merge::new(module, |a: &AbstractAnnotation| a.ident.clone())
+        .run(&base.annotations, &ours.annotations, &theirs.annotations, &mut conflicts);
+    merged.aliases = Merge::new(module, |a: &Alias| a.ident.clone())
+        .run(&base.aliases, &ours.aliases, &theirs.aliases, &mut conflicts);
+    merged.functions = Merge::new(module, overload_key)
+        .run(&base.functions, &ours.functions, &theirs.functions, &mut conflicts);
+    if conflicts.is_empty() {
+        Ok(merged)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Merges an item that both sides changed, given its name.
+type Nested<T> = fn(&str, &T, &T, &T, &mut Vec<Conflict>) -> T;
+
+/// A merge of one list of named items.
+struct Merge<'a, T, K> {
+    /// Name of the item that holds the list, e.g. `default::User`.
+    owner:  &'a str,
+    key:    K,
+    nested: Option<Nested<T>>
+}
+
+impl<'a, T: Clone + Serialize, K: Fn(&T) -> String> Merge<'a, T, K> {
+    fn new(owner: &'a str, key: K) -> Merge<'a, T, K> {
+        Merge { owner, key, nested: None }
+    }
+
+    fn nested(self, nested: Nested<T>) -> Merge<'a, T, K> {
+        Merge { nested: Some(nested), ..self }
+    }
+
+    fn find<'b>(&self, items: &'b [T], key: &str) -> Option<&'b T> {
+        items.iter().find(|item| (self.key)(item) == key)
+    }
+
+    fn run(&self, base: &[T], ours: &[T], theirs: &[T], conflicts: &mut Vec<Conflict>) -> Vec<T> {
+        let mut keys: Vec<String> = ours.iter().map(&self.key).collect();
+        keys.extend(theirs.iter().map(&self.key).filter(|key| self.find(ours, key).is_none()));
+        let mut merged = Vec::new();
+        for key in keys {
+            let item = format!("{}{}", self.owner, key);
+            let conflict = |kind: ConflictKind| Conflict { item: item.clone(), kind };
+            let kept = match (self.find(base, &key), self.find(ours, &key), self.find(theirs, &key)) {
+                (_, Some(ours), Some(theirs)) if same_json(ours, theirs) => Some(ours.clone()),
+                (None, Some(_), Some(_)) => {
+                    conflicts.push(conflict(ConflictKind::BothAdded));
+                    None
+                },
+                (Some(base), Some(ours), Some(theirs)) => {
+                    if same_json(base, ours) {
+                        Some(theirs.clone())
+                    } else if same_json(base, theirs) {
+                        Some(ours.clone())
+                    } else if let Some(nested) = self.nested {
+                        Some(nested(&item, base, ours, theirs, conflicts))
+                    } else {
+                        conflicts.push(conflict(ConflictKind::BothChanged));
+                        None
+                    }
+                },
+                (Some(base), Some(kept), None) | (Some(base), None, Some(kept)) => {
+                    if !same_json(base, kept) {
+                        conflicts.push(conflict(ConflictKind::ChangedAndRemoved));
+                    }
+                    None
+                },
+                (None, Some(added), None) | (None, None, Some(added)) => Some(added.clone()),
+                (_, None, None) => unreachable!()
+            };
+            merged.extend(kept);
+        }
+        merged
+    }
+}
+
+/// Merge a type that both sides changed: its flags and bases as a
+/// whole, its pointers, constraints, indices and annotations one by one.
+fn merge_type(item: &str, base: &Type, ours: &Type, theirs: &Type, conflicts: &mut Vec<Conflict>) -> Type {
+    let mut merged = ours.clone();
+    merged.abs = pick(item, "abstract", &base.abs, &ours.abs, &theirs.abs, conflicts);
+    merged.scalar = pick(item, "scalar", &base.scalar, &ours.scalar, &theirs.scalar, conflicts);
+    merged.is_final = pick(item, "final", &base.is_final, &ours.is_final, &theirs.is_final, conflicts);
+    merged.extends = pick(item, "extending", &base.extends, &ours.extends, &theirs.extends, conflicts);
+    let pointers = format!("{}.", item);
+    merged.properties = boxed(Merge::new(&pointers, |p: &Property| p.ident.clone())
+        .run(list(&base.properties), list(&ours.properties), list(&theirs.properties), conflicts), &ours.properties);
+    merged.links = boxed(Merge::new(&pointers, |l: &Link| l.ident.clone())
+        .run(list(&base.links), list(&ours.links), list(&theirs.links), conflicts), &ours.links);
+    let constraints = format!("{} constraint ", item);
+    merged.constraints = boxed(Merge::new(&constraints, constraint_key)
+        .run(list(&base.constraints), list(&ours.constraints), list(&theirs.constraints), conflicts),
+        &ours.constraints);
+    let indices = format!("{} index on ", item);
+    merged.indices = boxed(Merge::new(&indices, |index: &Index| canonical_expression(&index.expr))
+        .run(list(&base.indices), list(&ours.indices), list(&theirs.indices), conflicts), &ours.indices);
+    let annotations = format!("{} annotation ", item);
+    merged.annotations = boxed(Merge::new(&annotations, |a: &Annotation| a.ident.clone())
+        .run(list(&base.annotations), list(&ours.annotations), list(&theirs.annotations), conflicts),
+        &ours.annotations);
+    merged
+}
+
+/// The side of a value that changed, or a conflict if both did.
+fn pick<V: Clone + PartialEq>(item: &str, field: &str, base: &V, ours: &V, theirs: &V,
+                              conflicts: &mut Vec<Conflict>) -> V {
+    if ours == theirs || base == theirs {
+        ours.clone()
+    } else if base == ours {
+        theirs.clone()
+    } else {
+        conflicts.push(Conflict { item: format!("{} ({})", item, field), kind: ConflictKind::BothChanged });
+        ours.clone()
+    }
+}
+
+/// `items` as `ours` kept them: no list at all when it had none and
+/// nothing was added.
+#[allow(clippy::box_collection)]
+fn boxed<T>(items: Vec<T>, ours: &Option<Box<Vec<T>>>) -> Option<Box<Vec<T>>> {
+    if items.is_empty() && ours.is_none() {
+        None
+    } else {
+        Some(Box::new(items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::Property;
+    use crate::fixtures::{self, users};
+    use super::{merge, Conflict, ConflictKind};
+
+    fn prop(ident: &str, required: bool) -> Property {
+        Property { required, ..fixtures::prop(ident, "str") }
+    }
+
+    #[test]
+    fn combines_changes_to_different_pointers() {
+        let base = users(vec![prop("name", false)]);
+        let ours = users(vec![prop("name", true)]);
+        let theirs = users(vec![prop("name", false), prop("email", false)]);
+        let merged = merge(&base, &ours, &theirs).unwrap();
+        let props: Vec<(String, bool)> = merged.types[0].properties.as_ref().unwrap().iter()
+            .map(|prop| (prop.ident.clone(), prop.required))
+            .collect();
+        assert_eq!(props, vec![("name".to_string(), true), ("email".to_string(), false)]);
+    }
+
+    #[test]
+    fn reports_pointers_changed_on_both_sides() {
+        let base = users(vec![prop("name", false), prop("email", false)]);
+        let ours = users(vec![prop("name", true), prop("email", true)]);
+        let mut email = prop("email", false);
+        email.target = Some("int64".to_string());
+        let theirs = users(vec![email]);
+        assert_eq!(merge(&base, &ours, &theirs).unwrap_err(), vec![
+            Conflict { item: "default::User.name".to_string(), kind: ConflictKind::ChangedAndRemoved },
+            Conflict { item: "default::User.email".to_string(), kind: ConflictKind::BothChanged }
+        ]);
+    }
+}
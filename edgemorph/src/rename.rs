@@ -0,0 +1,400 @@
+//! Telling renamed items apart from removed and re-added ones.
+//!
+//! A plain `diff` reports a renamed type or pointer as removed and
+//! added again, and migrating that drops its data. `propose` pairs
+//! removed types, properties and links with added ones that look
+//! alike, and `diff_with_renames` diffs two schemas once the user has
+//! confirmed which of those are renames.
+//!
+//! An item can also say what it was called before with a
+//! `renamed_from` annotation, which is proposed whatever else changed:
+//!
+//! ```text
+//! property email_address -> str {
+//!     annotation renamed_from := 'email';
+//! }
+//! ```
+use std::collections::BTreeSet;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::datastructures::{list, Annotation, Expression, Function, Link, Module, Property, Type};
+use crate::diff::{constraint_key, diff, Change, Location};
+use crate::fingerprint::canonical_expression;
+use crate::visit::{walk_function_mut, walk_link_mut, walk_property_mut, walk_type_mut, VisitorMut};
+
+/// Annotation that names an item's previous name.
+pub const RENAMED_FROM: &str = "renamed_from";
+
+/// Similarity below which a pair is not proposed.
+const THRESHOLD: f64 = 0.6;
+
+/// A type, property or link that was renamed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Rename {
+    Type { from: String, to: String },
+    /// `at` is the pointer under its old name, on its type's new name.
+    Pointer { at: Location, to: String }
+}
+
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rename::Type { from, to } => write!(f, "type {} -> {}", from, to),
+            Rename::Pointer { at, to } => {
+                write!(f, "{} {} -> {}", if at.is_link { "link" } else { "property" }, at, to)
+            }
+        }
+    }
+}
+
+/// A possible rename, for the user to confirm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub rename:     Rename,
+    /// From 0 to 1, how alike the two items are.
+    pub similarity: f64,
+    /// Whether the new item has a `renamed_from` annotation naming the old one.
+    pub hinted:     bool
+}
+
+/// Renames that might explain the types and pointers removed between
+/// `old` and `new`. Pointer renames assume that every proposed type
+/// rename is accepted.
+pub fn propose(old: &Module, new: &Module) -> Vec<Proposal> {
+    let removed: Vec<&Type> = old.types.iter()
+        .filter(|ty| !ty.alias && new.get_type(&ty.ident).is_none())
+        .collect();
+    let added: Vec<&Type> = new.types.iter()
+        .filter(|ty| !ty.alias && old.get_type(&ty.ident).is_none())
+        .collect();
+    let mut candidates = Vec::new();
+    for old_ty in &removed {
+        for new_ty in added.iter().filter(|new_ty| new_ty.scalar == old_ty.scalar) {
+            let hinted = hinted(list(&new_ty.annotations), &old_ty.ident);
+            let similarity = (jaccard(&type_features(old_ty), &type_features(new_ty))
+                + jaccard(&type_shape(old_ty), &type_shape(new_ty))) / 2.0;
+            candidates.push(Proposal {
+                rename: Rename::Type { from: old_ty.ident.clone(), to: new_ty.ident.clone() },
+                similarity,
+                hinted
+            });
+        }
+    }
+    let mut proposals = best(candidates);
+
+    let renames: Vec<Rename> = proposals.iter().map(|proposal| proposal.rename.clone()).collect();
+    let renamed = apply(old, &renames).expect("proposed renames apply to the schema they came from");
+    for new_ty in new.types.iter().filter(|ty| !ty.alias && !ty.scalar) {
+        let old_ty = match renamed.types.iter().find(|ty| ty.ident == new_ty.ident) {
+            Some(ty) => ty,
+            None => continue
+        };
+        let at = Location::of(new_ty);
+        proposals.extend(best(pointer_candidates(&at, &properties(old_ty), &properties(new_ty))));
+        proposals.extend(best(pointer_candidates(&at.link(""), &links(old_ty), &links(new_ty))));
+    }
+    proposals
+}
+
+/// `diff`, with `renames` reported as renames rather than as removed
+/// and added items. Fails if a rename does not match `old`.
+pub fn diff_with_renames(old: &Module, new: &Module, renames: &[Rename]) -> Result<Vec<Change>, String> {
+    let renamed = apply(old, renames)?;
+    let mut changes: Vec<Change> = Vec::new();
+    for rename in renames {
+        if let Rename::Type { from, to } = rename {
+            let scalar = old.get_type(from).map(|ty| ty.scalar) == Some(true);
+            changes.push(Change::TypeRenamed { from: from.clone(), to: to.clone(), scalar });
+        }
+    }
+    for rename in renames {
+        if let Rename::Pointer { at, to } = rename {
+            changes.push(Change::PointerRenamed { at: at.clone(), to: to.clone() });
+        }
+    }
+    changes.extend(diff(&renamed, new));
+    Ok(changes)
+}
+
+/// `old` with `renames` applied: type renames first, then pointer
+/// renames on the renamed types.
+pub(crate) fn apply(old: &Module, renames: &[Rename]) -> Result<Module, String> {
+    let mut renamed = old.clone();
+    for rename in renames {
+        if let Rename::Type { from, to } = rename {
+            if renamed.get_type(from).is_none() {
+                return Err(format!("Cannot rename type `{}`: it is not in `{}`.", from, old.ident));
+            }
+            RenameType { from, to }.visit_module_mut(&mut renamed);
+        }
+    }
+    for rename in renames {
+        if let Rename::Pointer { at, to } = rename {
+            let pointer = at.pointer.as_deref().unwrap_or_default();
+            let ty = renamed.types.iter_mut().find(|ty| ty.ident == at.ty);
+            let ident = ty.and_then(|ty| if at.is_link {
+                ty.links.iter_mut().flat_map(|links| links.iter_mut()).find(|link| link.ident == pointer).map(|link| &mut link.ident)
+            } else {
+                ty.properties.iter_mut().flat_map(|props| props.iter_mut()).find(|prop| prop.ident == pointer).map(|prop| &mut prop.ident)
+            });
+            match ident {
+                Some(ident) => *ident = to.clone(),
+                None => return Err(format!("Cannot rename `{}`: it is not in `{}`.", at, old.ident))
+            }
+        }
+    }
+    Ok(renamed)
+}
+
+/// Points every reference to type `from` at `to`.
+struct RenameType<'a> {
+    from: &'a str,
+    to:   &'a str
+}
+
+impl<'a> RenameType<'a> {
+    fn rename(&self, ident: &mut String) {
+        if ident == self.from {
+            *ident = self.to.to_string();
+        }
+    }
+}
+
+impl<'a> VisitorMut for RenameType<'a> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        self.rename(&mut ty.ident);
+        ty.extends.iter_mut().flatten().for_each(|base| self.rename(base));
+        walk_type_mut(self, ty);
+    }
+
+    fn visit_property_mut(&mut self, prop: &mut Property) {
+        prop.target.iter_mut().for_each(|target| self.rename(target));
+        walk_property_mut(self, prop);
+    }
+
+    fn visit_link_mut(&mut self, link: &mut Link) {
+        link.target.iter_mut().for_each(|target| self.rename(target));
+        walk_link_mut(self, link);
+    }
+
+    fn visit_function_mut(&mut self, func: &mut Function) {
+        func.params.iter_mut().for_each(|param| self.rename(&mut param.ty));
+        self.rename(&mut func.return_type);
+        walk_function_mut(self, func);
+    }
+}
+
+/// What two pointers are compared on.
+struct Pointer<'a> {
+    ident:       &'a str,
+    target:      Option<&'a String>,
+    multi:       bool,
+    annotations: &'a [Annotation],
+    features:    BTreeSet<String>
+}
+
+impl<'a> From<&'a Property> for Pointer<'a> {
+    fn from(prop: &'a Property) -> Pointer<'a> {
+        let mut features = flags(prop.required, prop.readonly, &prop.expr, &prop.default);
+        features.extend(list(&prop.constraints).iter().map(constraint_key));
+        Pointer { ident: &prop.ident, target: prop.target.as_ref(), multi: prop.multi,
+                  annotations: list(&prop.annotations), features }
+    }
+}
+
+impl<'a> From<&'a Link> for Pointer<'a> {
+    fn from(link: &'a Link) -> Pointer<'a> {
+        let mut features = flags(link.required, link.readonly, &link.expr, &link.default);
+        features.extend(list(&link.constraints).iter().map(constraint_key));
+        features.extend(list(&link.properties).iter().map(|prop| format!("@{}: {:?}", prop.ident, prop.target)));
+        Pointer { ident: &link.ident, target: link.target.as_ref(), multi: link.multi,
+                  annotations: list(&link.annotations), features }
+    }
+}
+
+fn properties(ty: &Type) -> Vec<Pointer<'_>> {
+    list(&ty.properties).iter().map(Pointer::from).collect()
+}
+
+fn links(ty: &Type) -> Vec<Pointer<'_>> {
+    list(&ty.links).iter().map(Pointer::from).collect()
+}
+
+fn flags(
+    required: bool,
+    readonly: bool,
+    expr: &Option<Expression>,
+    default: &Option<Expression>) -> BTreeSet<String>
+{
+    let mut features = BTreeSet::new();
+    if required {
+        features.insert("required".to_string());
+    }
+    if readonly {
+        features.insert("readonly".to_string());
+    }
+    if let Some(expr) = expr {
+        features.insert(format!("expr {}", canonical_expression(expr)));
+    }
+    if let Some(default) = default {
+        features.insert(format!("default {}", canonical_expression(default)));
+    }
+    features
+}
+
+/// Pairs of a removed and an added pointer of the type at `at`. Unless
+/// hinted, both must have the same target and cardinality.
+fn pointer_candidates(at: &Location, old: &[Pointer], new: &[Pointer]) -> Vec<Proposal> {
+    let removed = old.iter().filter(|p| !new.iter().any(|other| other.ident == p.ident));
+    let mut candidates = Vec::new();
+    for old_p in removed {
+        for new_p in new.iter().filter(|p| !old.iter().any(|other| other.ident == p.ident)) {
+            let hinted = hinted(new_p.annotations, old_p.ident);
+            if !hinted && (old_p.target != new_p.target || old_p.multi != new_p.multi) {
+                continue;
+            }
+            let similarity =
+                (jaccard(&old_p.features, &new_p.features) + name_similarity(old_p.ident, new_p.ident)) / 2.0;
+            let at = Location { pointer: Some(old_p.ident.to_string()), ..at.clone() };
+            let rename = Rename::Pointer { at, to: new_p.ident.to_string() };
+            candidates.push(Proposal { rename, similarity, hinted });
+        }
+    }
+    candidates
+}
+
+/// Whether `annotations` say the item used to be called `ident`.
+fn hinted(annotations: &[Annotation], ident: &str) -> bool {
+    let unqualified = |name: &str| name.rsplit("::").next().unwrap_or_default().to_string();
+    annotations.iter().any(|anno| {
+        unqualified(&anno.ident) == RENAMED_FROM && unqualified(&anno.value) == unqualified(ident)
+    })
+}
+
+/// Keep the hinted and most similar candidates, using each old and
+/// each new item at most once.
+fn best(mut candidates: Vec<Proposal>) -> Vec<Proposal> {
+    candidates.retain(|candidate| candidate.hinted || candidate.similarity >= THRESHOLD);
+    candidates.sort_by(|a, b| {
+        b.hinted.cmp(&a.hinted).then(b.similarity.partial_cmp(&a.similarity).unwrap_or(std::cmp::Ordering::Equal))
+    });
+    let mut chosen: Vec<Proposal> = Vec::new();
+    for candidate in candidates {
+        let taken = chosen.iter().any(|other| {
+            let (a_from, a_to) = ends(&other.rename);
+            let (b_from, b_to) = ends(&candidate.rename);
+            a_from == b_from || a_to == b_to
+        });
+        if !taken {
+            chosen.push(candidate);
+        }
+    }
+    chosen
+}
+
+fn ends(rename: &Rename) -> (&str, &str) {
+    match rename {
+        Rename::Type { from, to } => (from, to),
+        Rename::Pointer { at, to } => (at.pointer.as_deref().unwrap_or_default(), to)
+    }
+}
+
+fn type_features(ty: &Type) -> BTreeSet<String> {
+    let mut features: BTreeSet<String> = ty.extends.iter().flatten().map(|base| format!("extending {}", base)).collect();
+    features.extend(list(&ty.properties).iter().map(|prop| format!("property {}: {:?}", prop.ident, prop.target)));
+    features.extend(list(&ty.links).iter().map(|link| format!("link {}: {:?}", link.ident, link.target)));
+    features.extend(list(&ty.constraints).iter().map(constraint_key));
+    features
+}
+
+/// Pointers by kind and target alone, so that renaming a pointer
+/// along with its type still leaves the two alike.
+fn type_shape(ty: &Type) -> BTreeSet<String> {
+    let mut pointers: Vec<String> = list(&ty.properties).iter()
+        .map(|prop| format!("property {:?}", prop.target))
+        .chain(list(&ty.links).iter().map(|link| format!("link {:?}", link.target)))
+        .collect();
+    pointers.sort();
+    // Number repeated pointers, which a set would merge.
+    let mut shape = BTreeSet::new();
+    for pointer in pointers {
+        let n = (0..).find(|n| !shape.contains(&format!("{} #{}", pointer, n))).unwrap_or_default();
+        shape.insert(format!("{} #{}", pointer, n));
+    }
+    shape
+}
+
+/// Shared over total features; two empty sets are alike.
+fn jaccard(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// Dice coefficient of the names' character bigrams.
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| -> Vec<(char, char)> { s.chars().zip(s.chars().skip(1)).collect() };
+    let (a, b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let mut rest = b.clone();
+    let shared = a.iter()
+        .filter(|bigram| match rest.iter().position(|other| other == *bigram) {
+            Some(i) => { rest.remove(i); true },
+            None => false
+        })
+        .count();
+    2.0 * shared as f64 / (a.len() + b.len()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{AnnotationBuilder, Module, Property};
+    use crate::ddl::generate;
+    use crate::fixtures::{prop, users};
+    use super::{diff_with_renames, propose, Rename};
+
+    fn user(ty: &str, email: &str, hint: Option<&str>) -> Module {
+        let mut email = Property { required: true, ..prop(email, "str") };
+        email.annotations = hint.map(|old| {
+            Box::new(vec![AnnotationBuilder::default().ident("renamed_from").value(old).build().unwrap()])
+        });
+        let mut module = users(vec![prop("name", "str"), email]);
+        module.types[0].ident = ty.to_string();
+        module
+    }
+
+    #[test]
+    fn proposes_similar_items() {
+        let proposals: Vec<String> = propose(&user("User", "email", None), &user("Account", "email_address", None))
+            .iter()
+            .map(|proposal| proposal.rename.to_string())
+            .collect();
+        assert_eq!(proposals, vec!["type User -> Account", "property Account.email -> email_address"]);
+
+        // Unlike names need a hint.
+        assert!(propose(&user("User", "email", None), &user("User", "contact", None)).is_empty());
+        let hinted = propose(&user("User", "email", None), &user("User", "contact", Some("email")));
+        assert!(hinted[0].hinted);
+    }
+
+    #[test]
+    fn renames_instead_of_dropping() {
+        let old = user("User", "email", None);
+        let new = user("Account", "email_address", None);
+        let renames: Vec<Rename> = propose(&old, &new).into_iter().map(|proposal| proposal.rename).collect();
+        let ddl: Vec<String> = generate(&diff_with_renames(&old, &new, &renames).unwrap())
+            .into_iter()
+            .map(|step| step.ddl)
+            .collect();
+        assert_eq!(ddl, vec![
+            "ALTER TYPE User RENAME TO Account;",
+            "ALTER TYPE Account { ALTER PROPERTY email { RENAME TO email_address; }; };"
+        ]);
+        let stale = vec![Rename::Type { from: "Person".to_string(), to: "Account".to_string() }];
+        assert!(diff_with_renames(&old, &new, &stale).is_err());
+    }
+}
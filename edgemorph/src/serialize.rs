@@ -0,0 +1,100 @@
+//! Saving and loading a resolved schema, as JSON for other tools and
+//! as compact binary for caches.
+use crate::datastructures::Module;
+
+/// Bumped whenever the model changes in a way that makes older
+/// binary encodings unreadable.
+pub const FORMAT_VERSION: u32 = 1;
+
+impl Module {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Cannot serialize `{}`: {}", self.ident, e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Module, String> {
+        serde_json::from_str(json).map_err(|e| format!("Cannot deserialize schema: {}", e))
+    }
+
+    /// Encode the module with `bincode`, prefixed by `FORMAT_VERSION`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(&(FORMAT_VERSION, self))
+            .map_err(|e| format!("Cannot serialize `{}`: {}", self.ident, e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, String> {
+        let version: u32 = bincode::deserialize(bytes)
+            .map_err(|e| format!("Cannot deserialize schema: {}", e))?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Schema was encoded with format version {}, but this build reads version {}.",
+                version, FORMAT_VERSION));
+        }
+        let (_, module): (u32, Module) = bincode::deserialize(bytes)
+            .map_err(|e| format!("Cannot deserialize schema: {}", e))?;
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{
+        ConstraintBuilder, Expression, FunctionBuilder, LinkBuilder, Module, ParameterBuilder, PropertyBuilder,
+        TypeBuilder
+    };
+    use crate::fixtures;
+
+    fn library() -> Module {
+        let book = TypeBuilder::default()
+            .ident("Book")
+            .is_final(true)
+            .properties(Box::new(vec![
+                PropertyBuilder::default()
+                    .ident("title")
+                    .target("str")
+                    .required(true)
+                    .constraints(Box::new(vec![
+                        ConstraintBuilder::default()
+                            .ident("max_len_value")
+                            .args(Box::new(vec![Expression::new("100")]))
+                            .build()
+                            .unwrap()
+                    ]))
+                    .build()
+                    .unwrap(),
+                PropertyBuilder::default().ident("shout").computable("str_upper(.title)").build().unwrap()
+            ]))
+            .links(Box::new(vec![LinkBuilder::default().ident("sequel").target("Book").build().unwrap()]))
+            .build()
+            .unwrap();
+        let shout = FunctionBuilder::default()
+            .ident("shout")
+            .params(vec![ParameterBuilder::default().ident("s").ty("str").build().unwrap()])
+            .return_type("str")
+            .body("str_upper(s)")
+            .build()
+            .unwrap();
+        let mut module = fixtures::module(vec![book]);
+        module.functions = vec![shout];
+        module
+    }
+
+    #[test]
+    fn round_trips_through_json_and_bytes() {
+        let module = library();
+        let json = module.to_json().unwrap();
+        let from_json = Module::from_json(&json).unwrap();
+        assert_eq!(from_json.to_json().unwrap(), json);
+
+        let from_bytes = Module::from_bytes(&module.to_bytes().unwrap()).unwrap();
+        assert_eq!(from_bytes.to_json().unwrap(), json);
+        assert!(from_bytes.get_type("Book").unwrap().is_final);
+        assert_eq!(from_bytes.functions, module.functions);
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let mut bytes = library().to_bytes().unwrap();
+        bytes[0] = bytes[0].wrapping_add(1);
+        assert!(Module::from_bytes(&bytes).unwrap_err().contains("format version"));
+    }
+}
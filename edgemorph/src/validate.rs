@@ -0,0 +1,390 @@
+//! Schema-wide semantic checks that report every problem found,
+//! rather than stopping at the first one.
+use std::fmt;
+use crate::datastructures::{Module, Pointer, Type};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+/// A single problem found in a schema. `subject` names the item that
+/// the problem is about, e.g. `User` or `User.name`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub subject:  String,
+    pub message:  String
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error   => "error"
+        };
+        write!(f, "{}: {}: {}", severity, self.subject, self.message)
+    }
+}
+
+/// Everything that `Module::validate` found, in the order it was found.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Warning)
+    }
+
+    fn error<S: Into<String>>(&mut self, subject: S, message: String) {
+        self.diagnostics.push(Diagnostic { severity: Severity::Error, subject: subject.into(), message });
+    }
+
+    fn warning<S: Into<String>>(&mut self, subject: S, message: String) {
+        self.diagnostics.push(Diagnostic { severity: Severity::Warning, subject: subject.into(), message });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in self.diagnostics.iter() {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names that object types may extend without declaring them.
+const STD_OBJECTS: &[&str] = &["BaseObject", "Object"];
+
+impl Module {
+    /// Run every semantic check on the module and collect the results.
+    ///
+    /// Checks that walk the type hierarchy (constraints, indexes, link
+    /// properties and annotations) are skipped when inheritance is
+    /// cyclic, as there is no hierarchy to walk.
+    pub fn validate(&self) -> Report {
+        let mut report = Report::default();
+        self.check_type_names(&mut report);
+        let cyclic = self.check_hierarchy(&mut report);
+        for ty in self.types.iter() {
+            self.check_pointers(ty, &mut report);
+            self.check_redefinitions(ty, &mut report);
+        }
+        if cyclic {
+            return report;
+        }
+        let issues = self.abstract_constraint_issues().into_iter()
+            .chain(self.constraint_issues())
+            .chain(self.link_property_issues())
+            .chain(self.index_issues())
+            .chain(self.annotation_issues());
+        for (subject, issue) in issues {
+            report.error(subject, issue);
+        }
+        report
+    }
+
+    fn check_type_names(&self, report: &mut Report) {
+        for (i, ty) in self.types.iter().enumerate() {
+            if self.types[..i].iter().any(|other| other.ident == ty.ident) {
+                report.error(ty.ident.as_str(), "Type is declared more than once.".to_string());
+            }
+        }
+        for alias in self.aliases.iter() {
+            if self.types.iter().any(|ty| ty.ident == alias.ident && !ty.alias) {
+                report.error(alias.ident.as_str(), "Alias has the same name as a type.".to_string());
+            }
+        }
+    }
+
+    /// Returns whether inheritance is cyclic.
+    fn check_hierarchy(&self, report: &mut Report) -> bool {
+        let mut cyclic = false;
+        for ty in self.types.iter() {
+            for base in ty.extends.iter().flat_map(|bases| bases.iter()) {
+                let std_object = STD_OBJECTS.contains(&base.trim_start_matches("std::"));
+                match self.get_type(base) {
+                    Some(base_ty) if base_ty.is_final => report.error(
+                        ty.ident.as_str(), format!("Cannot extend `{}`, which is final.", base)),
+                    Some(base_ty) if base_ty.is_internal && !ty.builtin => report.error(
+                        ty.ident.as_str(), format!("Cannot extend `{}`, which is internal to EdgeDB.", base)),
+                    Some(base_ty) if base_ty.scalar != ty.scalar => report.error(
+                        ty.ident.as_str(), format!("Cannot extend `{}`: scalar and object types do not mix.", base)),
+                    Some(_) => {},
+                    None if ty.scalar && self.is_scalar(base) => {},
+                    None if !ty.scalar && std_object => {},
+                    None => report.error(ty.ident.as_str(), format!("Extends unknown type `{}`.", base))
+                }
+            }
+            if let Some(cycle) = self.inheritance_cycle(ty) {
+                report.error(ty.ident.as_str(), format!("Inheritance is cyclic: {}.", cycle.join(" -> ")));
+                cyclic = true;
+            }
+        }
+        cyclic
+    }
+
+    /// The path from `ty` back to itself through `extends`, if any.
+    fn inheritance_cycle(&self, ty: &Type) -> Option<Vec<String>> {
+        // Depth-first, keeping the path to the type being visited on
+        // `stack` together with the index of the next base to try.
+        let mut stack: Vec<(&Type, usize)> = vec![(ty, 0)];
+        let mut done: Vec<&str> = Vec::new();
+        while let Some((current, next)) = stack.pop() {
+            let bases = current.extends.as_deref().unwrap_or(&[]);
+            let base = match bases.get(next).and_then(|base| self.get_type(base)) {
+                Some(base) => base,
+                None if next < bases.len() => {
+                    stack.push((current, next + 1));
+                    continue;
+                },
+                None => {
+                    done.push(&current.ident);
+                    continue;
+                }
+            };
+            stack.push((current, next + 1));
+            if base.ident == ty.ident {
+                let mut cycle: Vec<String> = stack.iter().map(|(t, _)| t.ident.clone()).collect();
+                cycle.push(ty.ident.clone());
+                return Some(cycle);
+            }
+            let on_path = stack.iter().any(|(t, _)| t.ident == base.ident);
+            if !on_path && !done.contains(&base.ident.as_str()) {
+                stack.push((base, 0));
+            }
+        }
+        None
+    }
+
+    fn check_pointers(&self, ty: &Type, report: &mut Report) {
+        let pointers: Vec<Pointer> = ty.properties.iter().flat_map(|props| props.iter()).map(Pointer::Property)
+            .chain(ty.links.iter().flat_map(|links| links.iter()).map(Pointer::Link))
+            .collect();
+        for (i, pointer) in pointers.iter().enumerate() {
+            let subject = format!("{}.{}", ty.ident, pointer.ident());
+            if pointers[..i].iter().any(|other| other.ident() == pointer.ident()) {
+                report.error(subject.as_str(), "Pointer is declared more than once.".to_string());
+            }
+            // Alias pseudo-types mark the computables they infer as
+            // required; only declared ones are at fault.
+            if pointer.is_computable() && pointer.required() && !ty.alias {
+                report.error(subject.as_str(), "Computables cannot be `required`.".to_string());
+            }
+            match (pointer, pointer.target()) {
+                (Pointer::Link(_), Some(target)) if self.is_scalar(target) => report.error(
+                    subject.as_str(), format!("Link targets scalar type `{}`; use a property instead.", target)),
+                (Pointer::Link(_), Some(target)) if !self.is_object(target) && !is_std_object(target) => report.error(
+                    subject.as_str(), format!("Link targets unknown type `{}`.", target)),
+                (Pointer::Property(_), Some(target)) if self.is_object(target) || is_std_object(target) => report.error(
+                    subject.as_str(), format!("Property targets object type `{}`; use a link instead.", target)),
+                (Pointer::Property(_), Some(target)) if !self.is_scalar(target) => report.error(
+                    subject.as_str(), format!("Property targets unknown type `{}`.", target)),
+                _ => {}
+            }
+            if let Pointer::Link(link) = pointer {
+                let props: Vec<String> = link.link_properties().map(|(ident, _)| ident).collect();
+                for (j, ident) in props.iter().enumerate() {
+                    if props[..j].contains(ident) {
+                        report.error(
+                            format!("{}{}", subject, ident), "Link property is declared more than once.".to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compare the pointers of `ty` with the ones it inherits.
+    fn check_redefinitions(&self, ty: &Type, report: &mut Report) {
+        let ancestors = self.ancestors(ty);
+        let pointers = ty.properties.iter().flat_map(|props| props.iter()).map(Pointer::Property)
+            .chain(ty.links.iter().flat_map(|links| links.iter()).map(Pointer::Link));
+        for pointer in pointers {
+            let inherited = ancestors.iter().find_map(|base| base.pointer(pointer.ident()).map(|p| (base, p)));
+            let (base, inherited) = match inherited {
+                Some(found) => found,
+                None => continue
+            };
+            let subject = format!("{}.{}", ty.ident, pointer.ident());
+            if pointer.is_link() != inherited.is_link() {
+                report.error(subject.as_str(), format!(
+                    "Redefines {} `{}.{}` as a {}.",
+                    kind(inherited), base.ident, inherited.ident(), kind(pointer)));
+                continue;
+            }
+            if pointer.multi() != inherited.multi() {
+                report.error(subject.as_str(), format!(
+                    "Redefines `{}.{}` as {}, but it is {} there.",
+                    base.ident, inherited.ident(), multiplicity(pointer), multiplicity(inherited)));
+            }
+            let overloaded = match pointer {
+                Pointer::Property(prop) => prop.overloaded,
+                Pointer::Link(link) => link.overloaded
+            };
+            if !overloaded && !ty.alias {
+                report.warning(subject.as_str(), format!(
+                    "Redefines `{}.{}` without `overloaded`.", base.ident, inherited.ident()));
+            }
+        }
+    }
+}
+
+fn is_std_object(ident: &str) -> bool {
+    STD_OBJECTS.contains(&ident.trim_start_matches("std::"))
+}
+
+fn kind(pointer: Pointer) -> &'static str {
+    if pointer.is_link() { "link" } else { "property" }
+}
+
+fn multiplicity(pointer: Pointer) -> &'static str {
+    if pointer.multi() { "multi" } else { "single" }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastructures::{
+        AliasBuilder, AliasExprBuilder, AnnotationBuilder, ConstraintBuilder, IndexBuilder, LinkBuilder, Module,
+        PropertyBuilder, TypeBuilder
+    };
+    use crate::fixtures::shop;
+    use super::Severity;
+
+    /// `shop`, with a final `Book` that extends `Item`.
+    fn library() -> Module {
+        let book = TypeBuilder::default()
+            .ident("Book")
+            .extends(vec!["Item".to_string()])
+            .is_final(true)
+            .links(Box::new(vec![LinkBuilder::default().ident("related").target("Item").multi(true).build().unwrap()]))
+            .build()
+            .unwrap();
+        let mut library = shop();
+        library.types.push(book);
+        library
+    }
+
+    #[test]
+    fn accepts_a_sound_schema() {
+        let report = library().validate();
+        assert!(report.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut module = library();
+        let broken = TypeBuilder::default()
+            .ident("Ebook")
+            .extends(vec!["Book".to_string()])
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("tags").target("str").overloaded(true).build().unwrap(),
+                PropertyBuilder::default().ident("name").computable(".title").required(true).build().unwrap(),
+                PropertyBuilder::default().ident("publisher").target("Item").build().unwrap()
+            ]))
+            .links(Box::new(vec![
+                LinkBuilder::default().ident("format").target("str").build().unwrap(),
+                LinkBuilder::default().ident("publisher").target("Item").build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        module.types.push(broken);
+        module.types[0].extends = Some(vec!["Ebook".to_string()]);
+
+        let report = module.validate();
+        let messages: Vec<String> = report.diagnostics.iter().map(|d| d.to_string()).collect();
+        let expect = [
+            "Cannot extend `Book`, which is final.",
+            "Inheritance is cyclic: Item -> Ebook -> Book -> Item.",
+            "error: Ebook.tags: Redefines `Item.tags` as single, but it is multi there.",
+            "error: Ebook.name: Computables cannot be `required`.",
+            "warning: Ebook.name: Redefines `Item.name` without `overloaded`.",
+            "Property targets object type `Item`",
+            "Link targets scalar type `str`",
+            "error: Ebook.publisher: Pointer is declared more than once."
+        ];
+        for expected in expect.iter() {
+            assert!(messages.iter().any(|m| m.contains(expected)), "missing {:?} in\n{}", expected, report);
+        }
+        assert!(report.has_errors());
+        assert_eq!(report.warnings().next().map(|d| d.severity), Some(Severity::Warning));
+    }
+
+    #[test]
+    fn reports_every_issue_past_pointer_errors() {
+        let mut module = library();
+        let summary = AnnotationBuilder::default().ident("summary").value("An item").build().unwrap();
+        let item = &mut module.types[0];
+        item.properties.as_mut().unwrap().push(PropertyBuilder::default().ident("name").target("str").build().unwrap());
+        item.annotations = Some(Box::new(vec![summary.clone(), summary]));
+        item.indices = Some(Box::new(vec![
+            IndexBuilder::default().expr(".tags").build().unwrap(),
+            IndexBuilder::default().expr(".price").build().unwrap()
+        ]));
+
+        let report = module.validate();
+        assert_eq!(report.errors().count(), 5, "{}", report);
+        let expect = [
+            "error: Item.name: Pointer is declared more than once.",
+            "error: Item: In index on (.tags) of `Item`: `Item.tags` is a multi pointer and cannot be indexed.",
+            "error: Item: In index on (.price) of `Item`: `Item` has no property called `price`.",
+            "error: Item: Annotation `summary` on `Item` is not declared.",
+            "error: Item: Annotation `summary` is set more than once on `Item`."
+        ];
+        for expected in expect.iter() {
+            assert!(report.errors().any(|d| d.to_string().contains(expected)), "missing {:?} in\n{}", expected, report);
+        }
+    }
+
+    #[test]
+    fn accepts_computables_that_aliases_infer_as_required() {
+        let mut module = library();
+        let sdl = "alias Shouting := Item { up := str_upper(.name) };";
+        module.aliases.push(AliasBuilder::default()
+            .ident("Shouting")
+            .alias_expr(AliasExprBuilder::default().sdl(sdl).build().unwrap())
+            .build()
+            .unwrap());
+        module.resolve_aliases().unwrap();
+        let report = module.validate();
+        assert!(!report.has_errors(), "{}", report);
+    }
+
+    #[test]
+    fn rejects_extending_internal_types() {
+        let mut module = library();
+        module.types[0].is_internal = true;
+        assert_eq!(module.codegen_types().map(|ty| ty.ident.as_str()).collect::<Vec<_>>(), vec!["Book"]);
+        let report = module.validate();
+        assert!(report.errors().any(|d| d.subject == "Book" && d.message.contains("internal")), "{}", report);
+
+        module.types[1].builtin = true;
+        assert!(module.validate().is_empty());
+        assert_eq!(module.codegen_types().count(), 0);
+    }
+
+    #[test]
+    fn reports_constraints_without_arguments() {
+        let mut module = library();
+        let max_len = ConstraintBuilder::default().ident("max_len_value").build().unwrap();
+        module.types[0].properties.as_mut().unwrap()[0].constraints = Some(Box::new(vec![max_len]));
+        let report = module.validate();
+        assert!(report.errors().any(|d| d.subject == "Item.name" && d.message.contains("used without arguments")),
+                "{}", report);
+    }
+}
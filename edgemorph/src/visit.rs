@@ -0,0 +1,660 @@
+//! Traversal of schemas and of EdgeQL expressions.
+//!
+//! `Visitor` and `VisitorMut` walk a tree by shared or mutable
+//! reference, and `Fold` rebuilds it by value. Every method has a
+//! default that walks the children of the node, so an implementation
+//! only overrides the nodes it is interested in, and calls the matching
+//! `walk_*` (or `fold_*`) function to keep descending.
+use crate::datastructures::{
+    Alias, AliasExpr, Annotation, Constraint, Expression, Function, Index, Link, Module, Parameter, Property,
+    Statement, Subcommand, Type
+};
+use crate::edgeql::{Expr, OrderBy, Path, PathSource, ShapeElement};
+
+pub trait Visitor {
+    fn visit_module(&mut self, module: &Module) { walk_module(self, module) }
+    fn visit_type(&mut self, ty: &Type) { walk_type(self, ty) }
+    fn visit_property(&mut self, prop: &Property) { walk_property(self, prop) }
+    fn visit_link(&mut self, link: &Link) { walk_link(self, link) }
+    fn visit_constraint(&mut self, constraint: &Constraint) { walk_constraint(self, constraint) }
+    fn visit_subcommand(&mut self, subcommand: &Subcommand) { walk_subcommand(self, subcommand) }
+    fn visit_index(&mut self, index: &Index) { walk_index(self, index) }
+    fn visit_alias(&mut self, alias: &Alias) { walk_alias(self, alias) }
+    fn visit_function(&mut self, func: &Function) { walk_function(self, func) }
+    fn visit_annotation(&mut self, _annotation: &Annotation) {}
+    /// Expressions are parsed and their syntax tree visited; ones
+    /// that do not parse are skipped, as validation reports them.
+    fn visit_expression(&mut self, expression: &Expression) { walk_expression(self, expression) }
+    fn visit_statement(&mut self, stmt: &Statement) { walk_statement(self, stmt) }
+    fn visit_expr(&mut self, expr: &Expr) { walk_expr(self, expr) }
+    fn visit_path(&mut self, path: &Path) { walk_path(self, path) }
+    fn visit_shape_element(&mut self, element: &ShapeElement) { walk_shape_element(self, element) }
+}
+
+pub fn walk_module<V: Visitor + ?Sized>(v: &mut V, module: &Module) {
+    module.types.iter().for_each(|ty| v.visit_type(ty));
+    module.constraints.iter().for_each(|constraint| v.visit_constraint(constraint));
+    module.properties.iter().for_each(|prop| v.visit_property(prop));
+    module.links.iter().for_each(|link| v.visit_link(link));
+    module.aliases.iter().for_each(|alias| v.visit_alias(alias));
+    module.functions.iter().for_each(|func| v.visit_function(func));
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(v: &mut V, ty: &Type) {
+    ty.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+    ty.properties.iter().flat_map(|props| props.iter()).for_each(|prop| v.visit_property(prop));
+    ty.links.iter().flat_map(|links| links.iter()).for_each(|link| v.visit_link(link));
+    ty.constraints.iter().flat_map(|cs| cs.iter()).for_each(|constraint| v.visit_constraint(constraint));
+    ty.indices.iter().flat_map(|indices| indices.iter()).for_each(|index| v.visit_index(index));
+}
+
+pub fn walk_property<V: Visitor + ?Sized>(v: &mut V, prop: &Property) {
+    prop.expr.iter().chain(prop.default.iter()).for_each(|expr| v.visit_expression(expr));
+    prop.constraints.iter().flat_map(|cs| cs.iter()).for_each(|constraint| v.visit_constraint(constraint));
+    prop.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_link<V: Visitor + ?Sized>(v: &mut V, link: &Link) {
+    link.expr.iter().chain(link.default.iter()).for_each(|expr| v.visit_expression(expr));
+    link.properties.iter().flat_map(|props| props.iter()).for_each(|prop| v.visit_property(prop));
+    link.constraints.iter().flat_map(|cs| cs.iter()).for_each(|constraint| v.visit_constraint(constraint));
+    link.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_constraint<V: Visitor + ?Sized>(v: &mut V, constraint: &Constraint) {
+    constraint.args.iter().flat_map(|args| args.iter()).for_each(|arg| v.visit_expression(arg));
+    constraint.on.iter().for_each(|on| v.visit_expression(on));
+    constraint.subcommands.iter()
+        .flat_map(|subcommands| subcommands.iter())
+        .for_each(|subcommand| v.visit_subcommand(subcommand));
+}
+
+pub fn walk_subcommand<V: Visitor + ?Sized>(v: &mut V, subcommand: &Subcommand) {
+    subcommand.using.iter().for_each(|using| v.visit_expression(using));
+    subcommand.annotation.iter().for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_index<V: Visitor + ?Sized>(v: &mut V, index: &Index) {
+    v.visit_expression(&index.expr);
+    index.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_alias<V: Visitor + ?Sized>(v: &mut V, alias: &Alias) {
+    if let Some(expr) = alias.alias_expr.expression() {
+        v.visit_expression(&expr);
+    }
+    alias.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(v: &mut V, func: &Function) {
+    func.params.iter().filter_map(|param| param.default.as_ref()).for_each(|default| v.visit_expression(default));
+    func.body.iter().for_each(|body| v.visit_expression(body));
+    func.annotations.iter().flat_map(|annos| annos.iter()).for_each(|anno| v.visit_annotation(anno));
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(v: &mut V, expression: &Expression) {
+    if let Ok(expr) = expression.parse() {
+        v.visit_expr(&expr);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(v: &mut V, stmt: &Statement) {
+    match stmt {
+        Statement::Using(expr) | Statement::Expression(expr) => v.visit_expression(expr),
+        Statement::Alias(alias) => {
+            if let Some(expr) = alias.expression() {
+                v.visit_expression(&expr);
+            }
+        },
+        Statement::Subcommand(subcommand) => v.visit_subcommand(subcommand),
+        Statement::Constraint(constraint) => v.visit_constraint(constraint),
+        Statement::Query(expr) => v.visit_expr(expr)
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Literal(_) => {},
+        Expr::Path(path) => v.visit_path(path),
+        Expr::Call { args, .. } => args.iter().for_each(|arg| v.visit_expr(arg)),
+        Expr::Cast { expr, .. } => v.visit_expr(expr),
+        Expr::UnaryOp { operand, .. } => v.visit_expr(operand),
+        Expr::BinaryOp { left, right, .. } => {
+            v.visit_expr(left);
+            v.visit_expr(right);
+        },
+        Expr::IfElse { then, condition, otherwise } => {
+            v.visit_expr(then);
+            v.visit_expr(condition);
+            v.visit_expr(otherwise);
+        },
+        Expr::Index { expr, index } => {
+            v.visit_expr(expr);
+            v.visit_expr(index);
+        },
+        Expr::Tuple(items) | Expr::Array(items) | Expr::Set(items) => items.iter().for_each(|item| v.visit_expr(item)),
+        Expr::Shape { expr, elements } => {
+            v.visit_expr(expr);
+            elements.iter().for_each(|element| v.visit_shape_element(element));
+        },
+        Expr::Select { expr, filter, order_by, offset, limit } => {
+            v.visit_expr(expr);
+            filter.iter().for_each(|filter| v.visit_expr(filter));
+            order_by.iter().for_each(|order| v.visit_expr(&order.expr));
+            offset.iter().chain(limit.iter()).for_each(|clause| v.visit_expr(clause));
+        },
+        Expr::With { bindings, body } => {
+            bindings.iter().for_each(|(_, binding)| v.visit_expr(binding));
+            v.visit_expr(body);
+        },
+        Expr::For { iter, body, .. } => {
+            v.visit_expr(iter);
+            v.visit_expr(body);
+        },
+        Expr::Insert { elements, .. } => elements.iter().for_each(|element| v.visit_shape_element(element)),
+        Expr::Update { expr, filter, elements } => {
+            v.visit_expr(expr);
+            filter.iter().for_each(|filter| v.visit_expr(filter));
+            elements.iter().for_each(|element| v.visit_shape_element(element));
+        },
+        Expr::Delete { expr, filter } => {
+            v.visit_expr(expr);
+            filter.iter().for_each(|filter| v.visit_expr(filter));
+        }
+    }
+}
+
+pub fn walk_path<V: Visitor + ?Sized>(v: &mut V, path: &Path) {
+    if let PathSource::Expr(expr) = &path.source {
+        v.visit_expr(expr);
+    }
+}
+
+pub fn walk_shape_element<V: Visitor + ?Sized>(v: &mut V, element: &ShapeElement) {
+    element.expr.iter().for_each(|expr| v.visit_expr(expr));
+    element.shape.iter().for_each(|nested| v.visit_shape_element(nested));
+}
+
+/// Like `Visitor`, but with mutable access to every node. Expressions
+/// are kept as EdgeQL source in the schema, so `visit_expression_mut`
+/// does not descend into their syntax tree.
+pub trait VisitorMut {
+    fn visit_module_mut(&mut self, module: &mut Module) { walk_module_mut(self, module) }
+    fn visit_type_mut(&mut self, ty: &mut Type) { walk_type_mut(self, ty) }
+    fn visit_property_mut(&mut self, prop: &mut Property) { walk_property_mut(self, prop) }
+    fn visit_link_mut(&mut self, link: &mut Link) { walk_link_mut(self, link) }
+    fn visit_constraint_mut(&mut self, constraint: &mut Constraint) { walk_constraint_mut(self, constraint) }
+    fn visit_subcommand_mut(&mut self, subcommand: &mut Subcommand) { walk_subcommand_mut(self, subcommand) }
+    fn visit_index_mut(&mut self, index: &mut Index) { walk_index_mut(self, index) }
+    fn visit_alias_mut(&mut self, alias: &mut Alias) { walk_alias_mut(self, alias) }
+    fn visit_function_mut(&mut self, func: &mut Function) { walk_function_mut(self, func) }
+    fn visit_annotation_mut(&mut self, _annotation: &mut Annotation) {}
+    fn visit_expression_mut(&mut self, _expression: &mut Expression) {}
+    fn visit_statement_mut(&mut self, stmt: &mut Statement) { walk_statement_mut(self, stmt) }
+    fn visit_expr_mut(&mut self, expr: &mut Expr) { walk_expr_mut(self, expr) }
+    fn visit_path_mut(&mut self, path: &mut Path) { walk_path_mut(self, path) }
+    fn visit_shape_element_mut(&mut self, element: &mut ShapeElement) { walk_shape_element_mut(self, element) }
+}
+
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(v: &mut V, module: &mut Module) {
+    module.types.iter_mut().for_each(|ty| v.visit_type_mut(ty));
+    module.constraints.iter_mut().for_each(|constraint| v.visit_constraint_mut(constraint));
+    module.properties.iter_mut().for_each(|prop| v.visit_property_mut(prop));
+    module.links.iter_mut().for_each(|link| v.visit_link_mut(link));
+    module.aliases.iter_mut().for_each(|alias| v.visit_alias_mut(alias));
+    module.functions.iter_mut().for_each(|func| v.visit_function_mut(func));
+}
+
+pub fn walk_type_mut<V: VisitorMut + ?Sized>(v: &mut V, ty: &mut Type) {
+    ty.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+    ty.properties.iter_mut().flat_map(|props| props.iter_mut()).for_each(|prop| v.visit_property_mut(prop));
+    ty.links.iter_mut().flat_map(|links| links.iter_mut()).for_each(|link| v.visit_link_mut(link));
+    ty.constraints.iter_mut().flat_map(|cs| cs.iter_mut()).for_each(|constraint| v.visit_constraint_mut(constraint));
+    ty.indices.iter_mut().flat_map(|indices| indices.iter_mut()).for_each(|index| v.visit_index_mut(index));
+}
+
+pub fn walk_property_mut<V: VisitorMut + ?Sized>(v: &mut V, prop: &mut Property) {
+    prop.expr.iter_mut().chain(prop.default.iter_mut()).for_each(|expr| v.visit_expression_mut(expr));
+    prop.constraints.iter_mut().flat_map(|cs| cs.iter_mut()).for_each(|constraint| v.visit_constraint_mut(constraint));
+    prop.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_link_mut<V: VisitorMut + ?Sized>(v: &mut V, link: &mut Link) {
+    link.expr.iter_mut().chain(link.default.iter_mut()).for_each(|expr| v.visit_expression_mut(expr));
+    link.properties.iter_mut().flat_map(|props| props.iter_mut()).for_each(|prop| v.visit_property_mut(prop));
+    link.constraints.iter_mut().flat_map(|cs| cs.iter_mut()).for_each(|constraint| v.visit_constraint_mut(constraint));
+    link.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_constraint_mut<V: VisitorMut + ?Sized>(v: &mut V, constraint: &mut Constraint) {
+    constraint.args.iter_mut().flat_map(|args| args.iter_mut()).for_each(|arg| v.visit_expression_mut(arg));
+    constraint.on.iter_mut().for_each(|on| v.visit_expression_mut(on));
+    constraint.subcommands.iter_mut()
+        .flat_map(|subcommands| subcommands.iter_mut())
+        .for_each(|subcommand| v.visit_subcommand_mut(subcommand));
+}
+
+pub fn walk_subcommand_mut<V: VisitorMut + ?Sized>(v: &mut V, subcommand: &mut Subcommand) {
+    subcommand.using.iter_mut().for_each(|using| v.visit_expression_mut(using));
+    subcommand.annotation.iter_mut().for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_index_mut<V: VisitorMut + ?Sized>(v: &mut V, index: &mut Index) {
+    v.visit_expression_mut(&mut index.expr);
+    index.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_alias_mut<V: VisitorMut + ?Sized>(v: &mut V, alias: &mut Alias) {
+    alias.alias_expr.expr.iter_mut().for_each(|expr| v.visit_expression_mut(expr));
+    alias.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(v: &mut V, func: &mut Function) {
+    func.params.iter_mut()
+        .filter_map(|param| param.default.as_mut())
+        .for_each(|default| v.visit_expression_mut(default));
+    func.body.iter_mut().for_each(|body| v.visit_expression_mut(body));
+    func.annotations.iter_mut().flat_map(|annos| annos.iter_mut()).for_each(|anno| v.visit_annotation_mut(anno));
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(v: &mut V, stmt: &mut Statement) {
+    match stmt {
+        Statement::Using(expr) | Statement::Expression(expr) => v.visit_expression_mut(expr),
+        Statement::Alias(alias) => alias.expr.iter_mut().for_each(|expr| v.visit_expression_mut(expr)),
+        Statement::Subcommand(subcommand) => v.visit_subcommand_mut(subcommand),
+        Statement::Constraint(constraint) => v.visit_constraint_mut(constraint),
+        Statement::Query(expr) => v.visit_expr_mut(expr)
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Literal(_) => {},
+        Expr::Path(path) => v.visit_path_mut(path),
+        Expr::Call { args, .. } => args.iter_mut().for_each(|arg| v.visit_expr_mut(arg)),
+        Expr::Cast { expr, .. } => v.visit_expr_mut(expr),
+        Expr::UnaryOp { operand, .. } => v.visit_expr_mut(operand),
+        Expr::BinaryOp { left, right, .. } => {
+            v.visit_expr_mut(left);
+            v.visit_expr_mut(right);
+        },
+        Expr::IfElse { then, condition, otherwise } => {
+            v.visit_expr_mut(then);
+            v.visit_expr_mut(condition);
+            v.visit_expr_mut(otherwise);
+        },
+        Expr::Index { expr, index } => {
+            v.visit_expr_mut(expr);
+            v.visit_expr_mut(index);
+        },
+        Expr::Tuple(items) | Expr::Array(items) | Expr::Set(items) => {
+            items.iter_mut().for_each(|item| v.visit_expr_mut(item))
+        },
+        Expr::Shape { expr, elements } => {
+            v.visit_expr_mut(expr);
+            elements.iter_mut().for_each(|element| v.visit_shape_element_mut(element));
+        },
+        Expr::Select { expr, filter, order_by, offset, limit } => {
+            v.visit_expr_mut(expr);
+            filter.iter_mut().for_each(|filter| v.visit_expr_mut(filter));
+            order_by.iter_mut().for_each(|order| v.visit_expr_mut(&mut order.expr));
+            offset.iter_mut().chain(limit.iter_mut()).for_each(|clause| v.visit_expr_mut(clause));
+        },
+        Expr::With { bindings, body } => {
+            bindings.iter_mut().for_each(|(_, binding)| v.visit_expr_mut(binding));
+            v.visit_expr_mut(body);
+        },
+        Expr::For { iter, body, .. } => {
+            v.visit_expr_mut(iter);
+            v.visit_expr_mut(body);
+        },
+        Expr::Insert { elements, .. } => elements.iter_mut().for_each(|element| v.visit_shape_element_mut(element)),
+        Expr::Update { expr, filter, elements } => {
+            v.visit_expr_mut(expr);
+            filter.iter_mut().for_each(|filter| v.visit_expr_mut(filter));
+            elements.iter_mut().for_each(|element| v.visit_shape_element_mut(element));
+        },
+        Expr::Delete { expr, filter } => {
+            v.visit_expr_mut(expr);
+            filter.iter_mut().for_each(|filter| v.visit_expr_mut(filter));
+        }
+    }
+}
+
+pub fn walk_path_mut<V: VisitorMut + ?Sized>(v: &mut V, path: &mut Path) {
+    if let PathSource::Expr(expr) = &mut path.source {
+        v.visit_expr_mut(expr);
+    }
+}
+
+pub fn walk_shape_element_mut<V: VisitorMut + ?Sized>(v: &mut V, element: &mut ShapeElement) {
+    element.expr.iter_mut().for_each(|expr| v.visit_expr_mut(expr));
+    element.shape.iter_mut().for_each(|nested| v.visit_shape_element_mut(nested));
+}
+
+/// Rebuilds a tree node by node, e.g. to rewrite every path.
+pub trait Fold {
+    fn fold_module(&mut self, module: Module) -> Module { fold_module(self, module) }
+    fn fold_type(&mut self, ty: Type) -> Type { fold_type(self, ty) }
+    fn fold_property(&mut self, prop: Property) -> Property { fold_property(self, prop) }
+    fn fold_link(&mut self, link: Link) -> Link { fold_link(self, link) }
+    fn fold_constraint(&mut self, constraint: Constraint) -> Constraint { fold_constraint(self, constraint) }
+    fn fold_subcommand(&mut self, subcommand: Subcommand) -> Subcommand { fold_subcommand(self, subcommand) }
+    fn fold_index(&mut self, index: Index) -> Index { fold_index(self, index) }
+    fn fold_alias(&mut self, alias: Alias) -> Alias { fold_alias(self, alias) }
+    fn fold_function(&mut self, func: Function) -> Function { fold_function(self, func) }
+    fn fold_annotation(&mut self, annotation: Annotation) -> Annotation { annotation }
+    /// Expressions are parsed and their syntax tree folded; the result
+    /// is written back as EdgeQL only if the fold changed it. Ones that
+    /// do not parse are kept, as validation reports them.
+    fn fold_expression(&mut self, expression: Expression) -> Expression { fold_expression(self, expression) }
+    fn fold_statement(&mut self, stmt: Statement) -> Statement { fold_statement(self, stmt) }
+    fn fold_expr(&mut self, expr: Expr) -> Expr { fold_expr(self, expr) }
+    fn fold_path(&mut self, path: Path) -> Path { fold_path(self, path) }
+    fn fold_shape_element(&mut self, element: ShapeElement) -> ShapeElement { fold_shape_element(self, element) }
+}
+
+#[allow(clippy::box_collection)]
+fn fold_vec<T, F: FnMut(T) -> T>(items: Option<Box<Vec<T>>>, f: F) -> Option<Box<Vec<T>>> {
+    items.map(|items| Box::new(items.into_iter().map(f).collect()))
+}
+
+pub fn fold_module<F: Fold + ?Sized>(f: &mut F, module: Module) -> Module {
+    Module {
+        types: module.types.into_iter().map(|ty| f.fold_type(ty)).collect(),
+        constraints: module.constraints.into_iter().map(|constraint| f.fold_constraint(constraint)).collect(),
+        properties: module.properties.into_iter().map(|prop| f.fold_property(prop)).collect(),
+        links: module.links.into_iter().map(|link| f.fold_link(link)).collect(),
+        aliases: module.aliases.into_iter().map(|alias| f.fold_alias(alias)).collect(),
+        functions: module.functions.into_iter().map(|func| f.fold_function(func)).collect(),
+        ..module
+    }
+}
+
+pub fn fold_type<F: Fold + ?Sized>(f: &mut F, ty: Type) -> Type {
+    Type {
+        annotations: fold_vec(ty.annotations, |anno| f.fold_annotation(anno)),
+        properties: fold_vec(ty.properties, |prop| f.fold_property(prop)),
+        links: fold_vec(ty.links, |link| f.fold_link(link)),
+        constraints: fold_vec(ty.constraints, |constraint| f.fold_constraint(constraint)),
+        indices: fold_vec(ty.indices, |index| f.fold_index(index)),
+        ..ty
+    }
+}
+
+pub fn fold_property<F: Fold + ?Sized>(f: &mut F, prop: Property) -> Property {
+    Property {
+        expr: prop.expr.map(|expr| f.fold_expression(expr)),
+        default: prop.default.map(|default| f.fold_expression(default)),
+        constraints: fold_vec(prop.constraints, |constraint| f.fold_constraint(constraint)),
+        annotations: fold_vec(prop.annotations, |anno| f.fold_annotation(anno)),
+        ..prop
+    }
+}
+
+pub fn fold_link<F: Fold + ?Sized>(f: &mut F, link: Link) -> Link {
+    Link {
+        expr: link.expr.map(|expr| f.fold_expression(expr)),
+        default: link.default.map(|default| f.fold_expression(default)),
+        properties: fold_vec(link.properties, |prop| f.fold_property(prop)),
+        constraints: fold_vec(link.constraints, |constraint| f.fold_constraint(constraint)),
+        annotations: fold_vec(link.annotations, |anno| f.fold_annotation(anno)),
+        ..link
+    }
+}
+
+pub fn fold_constraint<F: Fold + ?Sized>(f: &mut F, constraint: Constraint) -> Constraint {
+    Constraint {
+        args: fold_vec(constraint.args, |arg| f.fold_expression(arg)),
+        on: constraint.on.map(|on| f.fold_expression(on)),
+        subcommands: fold_vec(constraint.subcommands, |subcommand| f.fold_subcommand(subcommand)),
+        ..constraint
+    }
+}
+
+pub fn fold_subcommand<F: Fold + ?Sized>(f: &mut F, subcommand: Subcommand) -> Subcommand {
+    Subcommand {
+        using: subcommand.using.map(|using| Box::new(f.fold_expression(*using))),
+        annotation: subcommand.annotation.map(|anno| Box::new(f.fold_annotation(*anno))),
+        ..subcommand
+    }
+}
+
+pub fn fold_index<F: Fold + ?Sized>(f: &mut F, index: Index) -> Index {
+    Index {
+        expr: f.fold_expression(index.expr),
+        annotations: fold_vec(index.annotations, |anno| f.fold_annotation(anno))
+    }
+}
+
+pub fn fold_alias<F: Fold + ?Sized>(f: &mut F, alias: Alias) -> Alias {
+    Alias {
+        alias_expr: fold_alias_expr(f, alias.alias_expr),
+        annotations: fold_vec(alias.annotations, |anno| f.fold_annotation(anno)),
+        ..alias
+    }
+}
+
+/// The aliased expression, folded. Once it changes, the SDL or DDL it
+/// was read from no longer matches and is dropped.
+fn fold_alias_expr<F: Fold + ?Sized>(f: &mut F, alias_expr: AliasExpr) -> AliasExpr {
+    let expr = alias_expr.expression();
+    let folded = expr.clone().map(|expr| f.fold_expression(expr));
+    if folded == expr {
+        alias_expr
+    } else {
+        AliasExpr { ddl: None, sdl: None, expr: folded }
+    }
+}
+
+pub fn fold_function<F: Fold + ?Sized>(f: &mut F, func: Function) -> Function {
+    Function {
+        params: func.params.into_iter()
+            .map(|param| Parameter { default: param.default.map(|default| f.fold_expression(default)), ..param })
+            .collect(),
+        body: func.body.map(|body| f.fold_expression(body)),
+        annotations: fold_vec(func.annotations, |anno| f.fold_annotation(anno)),
+        ..func
+    }
+}
+
+pub fn fold_expression<F: Fold + ?Sized>(f: &mut F, expression: Expression) -> Expression {
+    match expression.parse() {
+        Ok(expr) => {
+            let folded = f.fold_expr(expr.clone());
+            if folded == expr {
+                expression
+            } else {
+                Expression::new(&folded.to_string())
+            }
+        },
+        Err(_) => expression
+    }
+}
+
+pub fn fold_statement<F: Fold + ?Sized>(f: &mut F, stmt: Statement) -> Statement {
+    match stmt {
+        Statement::Using(expr) => Statement::Using(f.fold_expression(expr)),
+        Statement::Expression(expr) => Statement::Expression(f.fold_expression(expr)),
+        Statement::Alias(alias) => Statement::Alias(fold_alias_expr(f, alias)),
+        Statement::Subcommand(subcommand) => Statement::Subcommand(f.fold_subcommand(subcommand)),
+        Statement::Constraint(constraint) => Statement::Constraint(f.fold_constraint(constraint)),
+        Statement::Query(expr) => Statement::Query(f.fold_expr(expr))
+    }
+}
+
+pub fn fold_expr<F: Fold + ?Sized>(f: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Literal(lit) => Expr::Literal(lit),
+        Expr::Path(path) => Expr::Path(f.fold_path(path)),
+        Expr::Call { func, args } => Expr::Call { func, args: args.into_iter().map(|arg| f.fold_expr(arg)).collect() },
+        Expr::Cast { ty, expr } => Expr::Cast { ty, expr: Box::new(f.fold_expr(*expr)) },
+        Expr::UnaryOp { op, operand } => Expr::UnaryOp { op, operand: Box::new(f.fold_expr(*operand)) },
+        Expr::BinaryOp { left, op, right } => Expr::BinaryOp {
+            left: Box::new(f.fold_expr(*left)),
+            op,
+            right: Box::new(f.fold_expr(*right))
+        },
+        Expr::IfElse { then, condition, otherwise } => Expr::IfElse {
+            then: Box::new(f.fold_expr(*then)),
+            condition: Box::new(f.fold_expr(*condition)),
+            otherwise: Box::new(f.fold_expr(*otherwise))
+        },
+        Expr::Index { expr, index } => Expr::Index { expr: Box::new(f.fold_expr(*expr)), index: Box::new(f.fold_expr(*index)) },
+        Expr::Tuple(items) => Expr::Tuple(items.into_iter().map(|item| f.fold_expr(item)).collect()),
+        Expr::Array(items) => Expr::Array(items.into_iter().map(|item| f.fold_expr(item)).collect()),
+        Expr::Set(items) => Expr::Set(items.into_iter().map(|item| f.fold_expr(item)).collect()),
+        Expr::Shape { expr, elements } => Expr::Shape {
+            expr: Box::new(f.fold_expr(*expr)),
+            elements: elements.into_iter().map(|element| f.fold_shape_element(element)).collect()
+        },
+        Expr::Select { expr, filter, order_by, offset, limit } => Expr::Select {
+            expr: Box::new(f.fold_expr(*expr)),
+            filter: filter.map(|filter| Box::new(f.fold_expr(*filter))),
+            order_by: order_by.into_iter()
+                .map(|order| OrderBy { expr: f.fold_expr(order.expr), ..order })
+                .collect(),
+            offset: offset.map(|offset| Box::new(f.fold_expr(*offset))),
+            limit: limit.map(|limit| Box::new(f.fold_expr(*limit)))
+        },
+        Expr::With { bindings, body } => Expr::With {
+            bindings: bindings.into_iter().map(|(name, binding)| (name, f.fold_expr(binding))).collect(),
+            body: Box::new(f.fold_expr(*body))
+        },
+        Expr::For { var, iter, body } => Expr::For {
+            var,
+            iter: Box::new(f.fold_expr(*iter)),
+            body: Box::new(f.fold_expr(*body))
+        },
+        Expr::Insert { ty, elements } => Expr::Insert {
+            ty,
+            elements: elements.into_iter().map(|element| f.fold_shape_element(element)).collect()
+        },
+        Expr::Update { expr, filter, elements } => Expr::Update {
+            expr: Box::new(f.fold_expr(*expr)),
+            filter: filter.map(|filter| Box::new(f.fold_expr(*filter))),
+            elements: elements.into_iter().map(|element| f.fold_shape_element(element)).collect()
+        },
+        Expr::Delete { expr, filter } => Expr::Delete {
+            expr: Box::new(f.fold_expr(*expr)),
+            filter: filter.map(|filter| Box::new(f.fold_expr(*filter)))
+        }
+    }
+}
+
+pub fn fold_path<F: Fold + ?Sized>(f: &mut F, path: Path) -> Path {
+    let source = match path.source {
+        PathSource::Expr(expr) => PathSource::Expr(Box::new(f.fold_expr(*expr))),
+        other => other
+    };
+    Path { source, ..path }
+}
+
+pub fn fold_shape_element<F: Fold + ?Sized>(f: &mut F, element: ShapeElement) -> ShapeElement {
+    ShapeElement {
+        expr: element.expr.map(|expr| f.fold_expr(expr)),
+        shape: element.shape.into_iter().map(|nested| f.fold_shape_element(nested)).collect(),
+        ..element
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{
+        AliasBuilder, AliasExprBuilder, ConstraintBuilder, IndexBuilder, PropertyBuilder, SubcommandBuilder, TypeBuilder
+    };
+    use crate::edgeql::{self, PathStep};
+    use crate::fixtures;
+    use crate::functions::parse_signature;
+
+    #[derive(Default)]
+    struct Pointers(Vec<String>);
+
+    impl Visitor for Pointers {
+        fn visit_path(&mut self, path: &Path) {
+            for step in path.steps.iter() {
+                if let PathStep::Forward(ident) = step {
+                    self.0.push(ident.clone());
+                }
+            }
+            walk_path(self, path);
+        }
+    }
+
+    struct Rename;
+
+    impl Fold for Rename {
+        fn fold_path(&mut self, path: Path) -> Path {
+            let steps = path.steps.into_iter()
+                .map(|step| match step {
+                    PathStep::Forward(ident) if ident == "cost" => PathStep::Forward("price".to_string()),
+                    other => other
+                })
+                .collect();
+            fold_path(self, Path { steps, ..path })
+        }
+    }
+
+    #[test]
+    fn visits_expressions_in_a_schema() {
+        let card = TypeBuilder::default()
+            .ident("Card")
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("cost").target("int64").build().unwrap(),
+                PropertyBuilder::default().ident("label").computable("<str>.cost ++ .element").build().unwrap()
+            ]))
+            .build()
+            .unwrap();
+        let module = fixtures::module(vec![card]);
+        let mut pointers = Pointers::default();
+        pointers.visit_module(&module);
+        assert_eq!(pointers.0, vec!["cost", "element"]);
+
+        let stmt = Statement::Query(edgeql::parse("SELECT Card { total := sum(.cost) } FILTER .cost > 1").unwrap());
+        let renamed = Rename.fold_statement(stmt);
+        let mut pointers = Pointers::default();
+        pointers.visit_statement(&renamed);
+        assert_eq!(pointers.0, vec!["price", "price"]);
+    }
+
+    #[test]
+    fn folds_expressions_in_a_schema() {
+        let positive = SubcommandBuilder::default().using(Box::new(".cost > 0".into())).build().unwrap();
+        let exclusive = ConstraintBuilder::default()
+            .ident("exclusive")
+            .subcommands(Box::new(vec![positive]))
+            .build()
+            .unwrap();
+        let card = TypeBuilder::default()
+            .ident("Card")
+            .properties(Box::new(vec![
+                PropertyBuilder::default().ident("label").computable("<str>.cost  ++ .element").build().unwrap()
+            ]))
+            .constraints(Box::new(vec![exclusive]))
+            .indices(Box::new(vec![IndexBuilder::default().expr(".cost").build().unwrap()]))
+            .build()
+            .unwrap();
+        let sdl = "alias Cheap := Card { double := .cost * 2 };";
+        let cheap = AliasBuilder::default()
+            .ident("Cheap")
+            .alias_expr(AliasExprBuilder::default().sdl(sdl).build().unwrap())
+            .build()
+            .unwrap();
+        let mut total = parse_signature("total(c: Card) -> int64").unwrap();
+        total.body = Some("c.cost * 2".into());
+        let mut module = fixtures::module(vec![card]);
+        module.aliases.push(cheap);
+        module.functions.push(total);
+
+        let module = Rename.fold_module(module);
+        let card = &module.types[0];
+        let label = &card.properties.as_ref().unwrap()[0];
+        assert_eq!(label.expr.as_ref().unwrap().ident, "<str>.price ++ .element");
+        let using = card.constraints.as_ref().unwrap()[0].subcommands.as_ref().unwrap()[0].using.as_ref().unwrap();
+        assert_eq!(using.ident, ".price > 0");
+        assert_eq!(card.indices.as_ref().unwrap()[0].expr.ident, ".price");
+        assert_eq!(module.aliases[0].alias_expr.expression().unwrap().ident, "Card { double := .price * 2 }");
+        assert_eq!(module.functions[0].body.as_ref().unwrap().ident, "c.price * 2");
+    }
+}
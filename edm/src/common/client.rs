@@ -0,0 +1,39 @@
+//! Running EdgeQL scripts on the databases in `edgemorph.toml`.
+//!
+//! Scripts go through the `edgedb` command-line client, the way
+//! `ServerGuard::database_cmd` in `common.rs` talks to a test server.
+//! Fed on standard input, the client runs one statement after the
+//! other and exits with an error at the first one that fails; a
+//! transaction the script started is then rolled back as the
+//! connection closes.
+use std::io::Write;
+use std::process::{Command, Stdio};
+use crate::install::Database;
+
+/// The `edgedb` command-line client, looked up on `PATH`.
+pub const CLIENT: &str = "edgedb";
+
+/// Run `script` on `database`, named `ident` in `edgemorph.toml`.
+pub fn execute(ident: &str, database: &Database, script: &str) -> Result<(), String> {
+    let mut cmd = Command::new(CLIENT);
+    cmd.arg("--no-version-check");
+    if !database.dsn.is_empty() {
+        cmd.arg("--dsn").arg(&database.dsn);
+    }
+    if !database.name.is_empty() {
+        cmd.arg("--database").arg(&database.name);
+    }
+    cmd.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().map_err(|e| format!("Cannot run `{}`: {}", CLIENT, e))?;
+    // Dropping stdin closes it, which ends the script.
+    child.stdin.take().expect("stdin is piped").write_all(script.as_bytes())
+        .map_err(|e| format!("Cannot send the script to `{}`: {}", CLIENT, e))?;
+    let output = child.wait_with_output().map_err(|e| format!("Cannot run `{}`: {}", CLIENT, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "The script failed on database `{}` and was rolled back:\n{}",
+            ident, String::from_utf8_lossy(&output.stderr).trim_end()))
+    }
+}
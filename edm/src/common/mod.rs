@@ -1,4 +1,6 @@
 mod serialize;
 mod deserialize;
+mod client;
 pub use self::serialize::*;
 pub use self::deserialize::*;
+pub use self::client::*;
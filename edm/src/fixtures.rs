@@ -0,0 +1,23 @@
+//! Schemas that the tests of several modules migrate.
+use edgemorph::datastructures::{Module, PropertyBuilder, Type, TypeBuilder};
+
+/// A type with properties given as `(ident, target)`.
+pub fn ty(ident: &str, props: &[(&str, &str)]) -> Type {
+    let props = props.iter()
+        .map(|(ident, target)| PropertyBuilder::default().ident(*ident).target(*target).build().unwrap())
+        .collect();
+    TypeBuilder::default().ident(ident).properties(Box::new(props)).build().unwrap()
+}
+
+/// A schema of one module, declaring `types`.
+pub fn schema(module: &str, types: Vec<Type>) -> Vec<Module> {
+    let mut schema = Module::new(module);
+    schema.types = types;
+    vec![schema]
+}
+
+/// A schema of one type, `Card`, with `str` properties `props`.
+pub fn card(props: &[&str]) -> Vec<Module> {
+    let props: Vec<(&str, &str)> = props.iter().map(|ident| (*ident, "str")).collect();
+    schema("default", vec![ty("Card", &props)])
+}
@@ -0,0 +1,125 @@
+//! `edm make install`: migrate a database to the schema in the
+//! working tree.
+use edgemorph::ddl::Risk;
+use crate::migration::{Block, History, Migration};
+use super::plan::split_risk;
+
+/// The migrations that `edm make install` applies to a database whose
+/// schema has fingerprint `installed`, `None` for an empty database.
+pub fn pending<'a>(history: &'a History, installed: Option<&str>) -> Result<&'a [Migration], String> {
+    history.check("install")?;
+    let start = match installed {
+        None => 0,
+        Some(fingerprint) => match history.migrations.iter().rposition(|m| m.fingerprint == fingerprint) {
+            Some(i) => i + 1,
+            None => return Err(format!(
+                "The installed schema ({}) is not produced by any migration in `{}`.",
+                fingerprint, history.dir.display()))
+        }
+    };
+    Ok(&history.migrations[start..])
+}
+
+/// The script that `edm make install` runs on a database whose schema
+/// has fingerprint `installed`: every pending migration, with its
+/// hooks, in one transaction. Destructive steps are refused unless
+/// `allow_destructive`.
+pub fn install_script(history: &History, installed: Option<&str>, allow_destructive: bool) -> Result<String, String> {
+    let pending = pending(history, installed)?;
+    let mut destructive: Vec<String> = Vec::new();
+    for migration in pending {
+        for block in migration.run_order()? {
+            if let Block::Step { number, reason, .. } = block {
+                if let (reason, Some(Risk::Destructive)) = split_risk(&reason) {
+                    let file = Migration::file_name(migration.number, &migration.name);
+                    destructive.push(format!("    {} step {}: {}", file, number, reason));
+                }
+            }
+        }
+    }
+    if !allow_destructive && !destructive.is_empty() {
+        return Err(format!(
+            "Refusing to install, {} step(s) would drop data:\n{}\n\
+             Run `edm make install --allow-destructive` to apply them anyway.",
+            destructive.len(), destructive.join("\n")));
+    }
+    let mut script = vec!["START TRANSACTION;".to_string()];
+    for migration in pending {
+        script.push(format!("# migration {}\n{}", Migration::file_name(migration.number, &migration.name),
+                            migration.script()?));
+    }
+    script.push("COMMIT;".to_string());
+    Ok(script.join("\n\n") + "\n")
+}
+
+/// The script that reverts a database whose schema has fingerprint
+/// `installed` to the schema of migration `to`, `0` for an empty
+/// database: the down steps of every later migration, last one first,
+/// in one transaction.
+pub fn revert_script(history: &History, installed: &str, to: u32) -> Result<String, String> {
+    history.check("revert")?;
+    let current = match history.migrations.iter().rposition(|m| m.fingerprint == installed) {
+        Some(i) => i,
+        None => return Err(format!(
+            "The installed schema ({}) is not produced by any migration in `{}`.", installed, history.dir.display()))
+    };
+    if to >= history.migrations[current].number {
+        return Err(format!(
+            "Cannot revert to {:04}: the database is at {:04}.", to, history.migrations[current].number));
+    }
+    let mut script = vec!["START TRANSACTION;".to_string()];
+    for migration in history.migrations[..=current].iter().rev().take_while(|m| m.number > to) {
+        script.push(format!("# revert migration {}\n{}", Migration::file_name(migration.number, &migration.name),
+                            migration.down_script()?));
+    }
+    script.push("COMMIT;".to_string());
+    Ok(script.join("\n\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::TempDir;
+    use crate::fixtures::card;
+    use crate::install::{plan, Config, Database};
+    use crate::migration::{History, INSTALLED};
+    use super::{install_script, revert_script};
+
+    #[test]
+    fn refuses_destructive_steps() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path()).unwrap();
+        history.create("card", &card(&["name", "nick"]), |_, _| Ok(false)).unwrap();
+        history.create("drop nick", &card(&["name"]), |_, _| Ok(false)).unwrap();
+        let installed = history.migrations[0].fingerprint.clone();
+
+        let e = install_script(&history, Some(&installed), false).unwrap_err();
+        assert!(e.contains("\n    0002_drop_nick.edgeql step 1: remove property `Card.nick`\n"), "{}", e);
+        let script = install_script(&history, Some(&installed), true).unwrap();
+        assert!(script.contains("ALTER TYPE Card { DROP PROPERTY nick; };"), "{}", script);
+
+        let e = revert_script(&history, &history.migrations[1].fingerprint, 1).unwrap_err();
+        assert!(e.contains("cannot be reverted"), "{}", e);
+    }
+
+    #[test]
+    fn plans_refused_steps_without_failing() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path()).unwrap();
+        history.create("card", &card(&["name", "nick"]), |_, _| Ok(false)).unwrap();
+        history.create("drop nick", &card(&["name"]), |_, _| Ok(false)).unwrap();
+        let installed = format!("primary = \"{}\"\n", history.migrations[0].fingerprint);
+        fs::write(dir.path().join(INSTALLED), installed).unwrap();
+        let mut config = Config::default();
+        let database = Database { name: "cards".to_string(), ..Database::default() };
+        config.edgedb.databases.insert("primary".to_string(), database);
+
+        let plans = plan(&config, &history, false).unwrap();
+        assert_eq!(plans[0].refused.len(), 1);
+        assert_eq!(plans[0].refused[0].reason.as_deref(), Some("remove property `Card.nick`"));
+        assert!(plans[0].script.as_ref().unwrap().contains("DROP PROPERTY nick;"));
+        let text = plans[0].to_string();
+        assert!(text.contains("\n#     0002_drop_nick.edgeql step 1: remove property `Card.nick`\n"), "{}", text);
+        assert_eq!(plan(&config, &history, true).unwrap()[0].refused, vec![]);
+    }
+}
@@ -1,2 +1,4 @@
 mod install;
+mod plan;
 pub use self::install::*;
+pub use self::plan::*;
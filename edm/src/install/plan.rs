@@ -0,0 +1,205 @@
+//! `edm make install --dry-run`: what installing would do to each
+//! database in `edgemorph.toml`, worked out from the migration history
+//! and `installed.toml` alone, without connecting to any database.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use edgemorph::ddl::Risk;
+use crate::migration::{Block, History, Migration, When};
+use super::{install_script, pending};
+
+/// The parts of `edgemorph.toml` that installing reads.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub edgedb: EdgeDb
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EdgeDb {
+    /// Databases by their identifier, e.g. `primary`.
+    #[serde(default)]
+    pub databases: BTreeMap<String, Database>
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Database {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub dsn: String,
+    /// Module files by module name.
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Cannot deserialize `{}`: {}", path.display(), e))
+    }
+}
+
+/// One statement of an install script.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Statement {
+    /// File name of the migration the statement comes from.
+    pub migration: String,
+    pub step:      usize,
+    /// When the statement is a hook, whether it runs before or after
+    /// its step.
+    pub hook:      Option<When>,
+    /// The change a step makes; `None` for hooks.
+    pub reason:    Option<String>,
+    /// What a step may do to existing data; `None` for hooks, which
+    /// are written by hand.
+    pub risk:      Option<Risk>,
+    pub ddl:       String
+}
+
+impl Statement {
+    /// The statement that `block` runs, if it runs when installing.
+    fn new(migration: &Migration, block: Block) -> Option<Statement> {
+        let migration = Migration::file_name(migration.number, &migration.name);
+        let statement = match block {
+            Block::Step { number, reason, ddl } => {
+                let (reason, risk) = split_risk(&reason);
+                Statement { migration, step: number, hook: None, reason: Some(reason), risk, ddl }
+            },
+            Block::Hook { when, step, query } => {
+                Statement { migration, step, hook: Some(when), reason: None, risk: None, ddl: query }
+            },
+            Block::Down { .. } => return None
+        };
+        Some(statement)
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.hook, &self.reason, &self.risk) {
+            (Some(when), _, _) => writeln!(f, "# {} {} step {} (hook)", self.migration, when, self.step)?,
+            (None, Some(reason), Some(risk)) => {
+                writeln!(f, "# {} step {}: {} ({})", self.migration, self.step, reason, risk)?
+            },
+            (None, Some(reason), None) => writeln!(f, "# {} step {}: {}", self.migration, self.step, reason)?,
+            (None, None, _) => writeln!(f, "# {} step {}", self.migration, self.step)?
+        }
+        writeln!(f, "{}", self.ddl)
+    }
+}
+
+/// A step reason as written in a migration file, without the risk
+/// that `render_steps` appends to it.
+pub(crate) fn split_risk(reason: &str) -> (String, Option<Risk>) {
+    for risk in &[Risk::Safe, Risk::NeedsBackfill, Risk::Destructive] {
+        if let Some(reason) = reason.strip_suffix(&format!(" ({})", risk)) {
+            return (reason.to_string(), Some(*risk));
+        }
+    }
+    (reason.to_string(), None)
+}
+
+/// What `edm make install` would do to one database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatabasePlan {
+    /// Identifier of the database in `edgemorph.toml`.
+    pub database:   String,
+    pub name:       String,
+    /// Fingerprint of the installed schema; `None` for an empty database.
+    pub current:    Option<String>,
+    /// Fingerprint of the schema after installing.
+    pub target:     Option<String>,
+    pub migrations: Vec<String>,
+    pub statements: Vec<Statement>,
+    /// The highest risk of any step, `None` when there are no steps.
+    pub risk:       Option<Risk>,
+    /// Destructive steps that installing refuses to run without
+    /// `--allow-destructive`; while there are any, nothing is installed.
+    pub refused:    Vec<Statement>,
+    /// The script as it would run with every step allowed, or `None`
+    /// when the database is up to date.
+    pub script:     Option<String>
+}
+
+impl DatabasePlan {
+    pub fn is_up_to_date(&self) -> bool {
+        self.migrations.is_empty()
+    }
+}
+
+impl fmt::Display for DatabasePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fingerprint = |fingerprint: &Option<String>| match fingerprint {
+            Some(fingerprint) => fingerprint.clone(),
+            None => "none (empty database)".to_string()
+        };
+        writeln!(f, "# database `{}` ({})", self.database, self.name)?;
+        writeln!(f, "# current: {}", fingerprint(&self.current))?;
+        writeln!(f, "# target:  {}", fingerprint(&self.target))?;
+        if self.is_up_to_date() {
+            return writeln!(f, "# up to date");
+        }
+        match self.risk {
+            Some(risk) => writeln!(f, "# risk:    {}", risk)?,
+            None => writeln!(f, "# risk:    none")?
+        }
+        if !self.refused.is_empty() {
+            let count = self.refused.len();
+            writeln!(f, "# refused: {} step(s) would drop data; `--allow-destructive` applies them:", count)?;
+            for statement in &self.refused {
+                writeln!(f, "#     {} step {}: {}", statement.migration, statement.step,
+                         statement.reason.as_deref().unwrap_or_default())?;
+            }
+        }
+        writeln!(f, "START TRANSACTION;")?;
+        for statement in &self.statements {
+            writeln!(f)?;
+            write!(f, "{}", statement)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "COMMIT;")
+    }
+}
+
+/// What `edm make install` would do to each database in `config`,
+/// given the fingerprints recorded in `history`'s `installed.toml`.
+/// Destructive steps are listed as refused unless `allow_destructive`,
+/// but are part of the script either way.
+pub fn plan(config: &Config, history: &History, allow_destructive: bool) -> Result<Vec<DatabasePlan>, String> {
+    let installed = history.installed()?;
+    let target = history.head().map(|head| head.fingerprint.clone());
+    let mut plans = Vec::new();
+    for (database, settings) in &config.edgedb.databases {
+        let current = installed.get(database).cloned();
+        let migrations = pending(history, current.as_deref())?;
+        let mut statements = Vec::new();
+        for migration in migrations {
+            statements.extend(migration.run_order()?.into_iter().filter_map(|block| Statement::new(migration, block)));
+        }
+        let script = if migrations.is_empty() {
+            None
+        } else {
+            Some(install_script(history, current.as_deref(), true)?)
+        };
+        let refused = statements.iter()
+            .filter(|statement| !allow_destructive && statement.risk == Some(Risk::Destructive))
+            .cloned()
+            .collect();
+        plans.push(DatabasePlan {
+            database: database.clone(),
+            name: settings.name.clone(),
+            target: target.clone(),
+            current,
+            migrations: migrations.iter().map(|m| Migration::file_name(m.number, &m.name)).collect(),
+            risk: statements.iter().filter_map(|statement| statement.risk).max(),
+            refused,
+            statements,
+            script
+        });
+    }
+    Ok(plans)
+}
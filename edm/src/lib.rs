@@ -2,9 +2,12 @@ pub mod init;
 pub mod add;
 pub mod make;
 pub mod install;
+pub mod migration;
 pub mod compile;
 pub mod test;
 pub mod common;
+#[cfg(test)]
+mod fixtures;
 
 #[cfg(test)]
 extern crate test_case;
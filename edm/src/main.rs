@@ -1,5 +1,331 @@
-//use clap::{App, Arg};
-// use std::env;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use edgemorph::datastructures::Module;
+use edgemorph::rename::Proposal;
+use edm::common::execute;
+use edm::install::{install_script, plan, revert_script, Config};
+use edm::migration::{acknowledge, lint, History, ACKNOWLEDGED, ANSWERS};
 // #[macro_use]
 // mod macros;
-fn main() {}
+
+fn main() {
+    let dir = Arg::with_name("dir")
+        .long("dir")
+        .value_name("MIGRATIONS_DIR")
+        .default_value("migrations")
+        .help("Directory that holds the migration files");
+    let no_input = Arg::with_name("no-input")
+        .long("no-input")
+        .help("Fail on rename proposals that are not answered in renames.toml, e.g. in CI");
+    let schema = Arg::with_name("schema")
+        .long("schema")
+        .value_name("MODULE_JSON")
+        .multiple(true)
+        .required(true)
+        .help("Compiled schema modules, as JSON");
+    let config = Arg::with_name("config")
+        .long("config")
+        .value_name("EDGEMORPH_TOML")
+        .default_value("edgemorph.toml")
+        .help("Project configuration that lists the databases");
+    let matches = App::new("edm")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("make")
+            .about("Build and install the schema")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("install")
+                .about("Migrate each database in edgemorph.toml to the last migration")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Print the DDL, fingerprints and risks for each database without connecting to it"))
+                .arg(Arg::with_name("allow-destructive")
+                    .long("allow-destructive")
+                    .help("Apply steps that drop data instead of refusing to install"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .possible_values(&["text", "json"])
+                    .default_value("text")
+                    .help("Output format of the dry run"))
+                .arg(config.clone())
+                .arg(dir.clone())))
+        .subcommand(SubCommand::with_name("migration")
+            .about("Manage the on-disk migration history")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("check")
+                .about("Check the migration history for damage and for branches that need merging")
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("create")
+                .about("Write a migration from the last migration to the current schema")
+                .arg(Arg::with_name("name").required(true).help("Short description, used in the file name"))
+                .arg(no_input.clone())
+                .arg(dir.clone())
+                .arg(schema.clone()))
+            .subcommand(SubCommand::with_name("revert")
+                .about("Undo the last migrations installed on a database")
+                .arg(Arg::with_name("database").required(true).help("Identifier of the database in edgemorph.toml"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .value_name("NUMBER")
+                    .help("Number of the migration to revert to, 0 for an empty database [default: the previous one]"))
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Print the down steps without connecting to the database"))
+                .arg(config)
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("squash")
+                .about("Replace a range of migrations with one equivalent migration")
+                .arg(Arg::with_name("from").required(true).help("Number of the first migration to squash"))
+                .arg(Arg::with_name("to").required(true).help("Number of the last migration to squash"))
+                .arg(Arg::with_name("name").long("name").takes_value(true).help("Name of the squashed migration"))
+                .arg(no_input.clone())
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("lint")
+                .about("Fail on changes that break generated clients unless they are acknowledged")
+                .arg(Arg::with_name("since")
+                    .long("since")
+                    .value_name("NUMBER")
+                    .default_value("1")
+                    .help("Number of the first migration to lint"))
+                .arg(Arg::with_name("acknowledge")
+                    .long("acknowledge")
+                    .help("Record the breaking changes found as acknowledged in breaking.toml"))
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("merge")
+                .about("Rebase one branch of a forked history onto the other")
+                .arg(Arg::with_name("rebase")
+                    .long("rebase")
+                    .value_name("MIGRATION_FILE")
+                    .help("A migration of the branch to rebase [default: the branch whose first file sorts last]"))
+                .arg(no_input)
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("rehash")
+                .about("Record the hash of the last migration after adding data-migration hooks to it")
+                .arg(dir.clone()))
+            .subcommand(SubCommand::with_name("status")
+                .about("Check the migration history for edited or missing files")
+                .arg(dir)
+                .arg(schema)))
+        .get_matches();
+    let result = match matches.subcommand() {
+        ("make", Some(make)) => match make.subcommand() {
+            ("install", Some(args)) => make_install(args),
+            _ => unreachable!()
+        },
+        ("migration", Some(migration)) => match migration.subcommand() {
+            ("check", Some(args)) => migration_check(args),
+            ("create", Some(args)) => migration_create(args),
+            ("lint", Some(args)) => migration_lint(args),
+            ("merge", Some(args)) => migration_merge(args),
+            ("rehash", Some(args)) => migration_rehash(args),
+            ("revert", Some(args)) => migration_revert(args),
+            ("squash", Some(args)) => migration_squash(args),
+            ("status", Some(args)) => migration_status(args),
+            _ => unreachable!()
+        },
+        _ => unreachable!()
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn make_install(args: &ArgMatches) -> Result<(), String> {
+    let config = Config::load(args.value_of("config").unwrap())?;
+    let history = History::load(args.value_of("dir").unwrap())?;
+    let allow_destructive = args.is_present("allow-destructive");
+    let plans = plan(&config, &history, allow_destructive)?;
+    if args.is_present("dry-run") {
+        if args.value_of("format") == Some("json") {
+            println!("{}", serde_json::to_string_pretty(&plans).map_err(|e| e.to_string())?);
+        } else {
+            let plans: Vec<String> = plans.iter().map(ToString::to_string).collect();
+            print!("{}", plans.join("\n"));
+        }
+        return Ok(());
+    }
+    // Every script is built, and refused, before any database is touched.
+    let mut scripts = Vec::new();
+    for plan in plans.iter().filter(|plan| !plan.is_up_to_date()) {
+        scripts.push((plan, install_script(&history, plan.current.as_deref(), allow_destructive)?));
+    }
+    for (plan, script) in scripts {
+        execute(&plan.database, &config.edgedb.databases[&plan.database], &script)?;
+        history.record_installed(&plan.database, plan.target.as_deref())?;
+        println!("Installed {} migration(s) on `{}`.", plan.migrations.len(), plan.database);
+    }
+    for plan in plans.iter().filter(|plan| plan.is_up_to_date()) {
+        println!("`{}` is up to date.", plan.database);
+    }
+    Ok(())
+}
+
+fn load_schema(args: &ArgMatches) -> Result<Vec<Module>, String> {
+    args.values_of("schema").unwrap()
+        .map(|path| {
+            let json = fs::read_to_string(path).map_err(|e| format!("Cannot read `{}`: {}", path, e))?;
+            Module::from_json(&json)
+        })
+        .collect()
+}
+
+fn migration_check(args: &ArgMatches) -> Result<(), String> {
+    let history = History::load(args.value_of("dir").unwrap())?;
+    let problems = history.problems();
+    if problems.is_empty() {
+        match history.head() {
+            Some(head) => {
+                println!("{} migration(s), head {:04} ({})", history.migrations.len(), head.number, head.hash)
+            },
+            None => println!("No migrations.")
+        }
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    Err(format!("The migration history in `{}` is damaged.", args.value_of("dir").unwrap()))
+}
+
+fn migration_create(args: &ArgMatches) -> Result<(), String> {
+    let schema = load_schema(args)?;
+    let mut history = History::load(args.value_of("dir").unwrap())?;
+    let migration = history.create(args.value_of("name").unwrap(), &schema, rename_answers(args))?;
+    println!("Created `{}`. Run `edm make install` to apply it.", migration.path.display());
+    Ok(())
+}
+
+fn migration_lint(args: &ArgMatches) -> Result<(), String> {
+    let since = args.value_of("since").unwrap();
+    let since = since.parse().map_err(|_| format!("`{}` is not a migration number.", since))?;
+    let history = History::load(args.value_of("dir").unwrap())?;
+    let findings = lint(&history, since)?;
+    let mut migration = None;
+    for finding in &findings {
+        if migration != Some(&finding.migration) {
+            println!("{}", finding.migration);
+            migration = Some(&finding.migration);
+        }
+        println!("    {}", finding);
+    }
+    if args.is_present("acknowledge") {
+        acknowledge(&history, &findings)?;
+        println!("Recorded the breaking changes in `{}`.", history.dir.join(ACKNOWLEDGED).display());
+        return Ok(());
+    }
+    let failing = findings.iter().filter(|finding| finding.fails()).count();
+    if failing == 0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} breaking change(s) are not acknowledged. Once clients are ready for them, \
+             run `edm migration lint --acknowledge` and commit `{}`.",
+            failing, ACKNOWLEDGED))
+    }
+}
+
+fn migration_merge(args: &ArgMatches) -> Result<(), String> {
+    let mut history = History::load(args.value_of("dir").unwrap())?;
+    let rebased = history.merge(args.value_of("rebase"), rename_answers(args))?;
+    for path in &rebased {
+        println!("Rebased into `{}`.", path.display());
+    }
+    if rebased.is_empty() {
+        println!("The other branch already makes every change of the rebased one.");
+    }
+    Ok(())
+}
+
+fn migration_squash(args: &ArgMatches) -> Result<(), String> {
+    let number = |arg: &str| {
+        let value = args.value_of(arg).unwrap();
+        value.parse().map_err(|_| format!("`{}` is not a migration number.", value))
+    };
+    let mut history = History::load(args.value_of("dir").unwrap())?;
+    let migration = history.squash(number("from")?, number("to")?, args.value_of("name"), rename_answers(args))?;
+    println!("Squashed into `{}`.", migration.path.display());
+    Ok(())
+}
+
+/// Answers rename proposals on the terminal, or refuses to with `--no-input`.
+fn rename_answers(args: &ArgMatches) -> impl FnMut(&str, &Proposal) -> Result<bool, String> {
+    let interactive = !args.is_present("no-input");
+    move |key, proposal| {
+        if interactive {
+            confirm_rename(key, proposal)
+        } else {
+            Err(format!("Rename proposal `{}` is not answered in `{}`.", key, ANSWERS))
+        }
+    }
+}
+
+fn confirm_rename(key: &str, proposal: &Proposal) -> Result<bool, String> {
+    let why = if proposal.hinted {
+        "renamed_from annotation".to_string()
+    } else {
+        format!("{:.0}% similar", proposal.similarity * 100.0)
+    };
+    print!("Was this a rename? {} ({}) [y/n] ", key, why);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).map_err(|e| e.to_string())?;
+    match answer.trim() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(format!("No answer for rename proposal `{}`.", key))
+    }
+}
+
+fn migration_rehash(args: &ArgMatches) -> Result<(), String> {
+    let mut history = History::load(args.value_of("dir").unwrap())?;
+    let migration = history.rehash()?;
+    println!("Rehashed `{}`.", migration.path.display());
+    Ok(())
+}
+
+fn migration_revert(args: &ArgMatches) -> Result<(), String> {
+    let database = args.value_of("database").unwrap();
+    let history = History::load(args.value_of("dir").unwrap())?;
+    let installed = history.installed()?;
+    let current = match installed.get(database) {
+        Some(fingerprint) => fingerprint,
+        None => return Err(format!("`{}` is empty, there is nothing to revert.", database))
+    };
+    let to = match args.value_of("to") {
+        Some(to) => to.parse().map_err(|_| format!("`{}` is not a migration number.", to))?,
+        None => match history.migrations.iter().rposition(|m| &m.fingerprint == current) {
+            Some(i) => history.migrations[i].number - 1,
+            None => return Err(format!("The schema installed on `{}` is not produced by any migration.", database))
+        }
+    };
+    let script = revert_script(&history, current, to)?;
+    if args.is_present("dry-run") {
+        print!("{}", script);
+        return Ok(());
+    }
+    let config_path = args.value_of("config").unwrap();
+    let config = Config::load(config_path)?;
+    let settings = match config.edgedb.databases.get(database) {
+        Some(settings) => settings,
+        None => return Err(format!("`{}` is not a database in `{}`.", database, config_path))
+    };
+    execute(database, settings, &script)?;
+    let reverted = history.migrations.iter().find(|m| m.number == to).map(|m| m.fingerprint.as_str());
+    history.record_installed(database, reverted)?;
+    println!("Reverted `{}` to {:04}.", database, to);
+    Ok(())
+}
+
+fn migration_status(args: &ArgMatches) -> Result<(), String> {
+    let schema = load_schema(args)?;
+    let status = History::load(args.value_of("dir").unwrap())?.status(&schema);
+    print!("{}", status);
+    if status.problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("The migration history in `{}` is damaged.", args.value_of("dir").unwrap()))
+    }
+}
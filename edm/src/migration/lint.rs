@@ -0,0 +1,145 @@
+//! `edm migration lint`: changes that break clients generated from an
+//! older schema.
+//!
+//! Every change a migration makes is classified as compatible or
+//! breaking for generated Rust and Python clients. Breaking changes
+//! fail the lint until they are acknowledged in `breaking.toml`, next
+//! to the migrations, which reviewers see change in the same commit:
+//!
+//! ```toml
+//! "9a41…/default: remove property `User.nick`" = true
+//! ```
+//!
+//! Changes are keyed by the fingerprint of the schema that their
+//! migration produces, which stays the same when later migrations are
+//! renumbered, e.g. by squashing earlier ones.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use edgemorph::compat::{classify, Compatibility};
+use super::migration::{changes, History, Migration};
+
+/// File that keeps the acknowledged breaking changes.
+pub const ACKNOWLEDGED: &str = "breaking.toml";
+
+/// One change made by a migration, classified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// File name of the migration that makes the change.
+    pub migration:     String,
+    /// Key of the change in `breaking.toml`:
+    /// `<fingerprint of the migration>/<module>: <change>`.
+    pub key:           String,
+    pub change:        String,
+    pub compatibility: Compatibility,
+    pub acknowledged:  bool
+}
+
+impl Finding {
+    /// Breaking and not acknowledged.
+    pub fn fails(&self) -> bool {
+        self.compatibility != Compatibility::Compatible && !self.acknowledged
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.compatibility {
+            Compatibility::Compatible => write!(f, "compatible  {}", self.change),
+            Compatibility::Breaking(reason) => {
+                write!(f, "breaking    {}: {}", self.change, reason)?;
+                if self.acknowledged {
+                    write!(f, " (acknowledged)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Acknowledged breaking changes, by key.
+pub fn acknowledged(history: &History) -> Result<BTreeMap<String, bool>, String> {
+    let path = history.dir.join(ACKNOWLEDGED);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Cannot deserialize `{}`: {}", path.display(), e))
+}
+
+/// Classify every change of the migrations numbered `since` or later,
+/// diffing the schemas kept for them. Renames are taken from
+/// `renames.toml`; unanswered proposals are not renames.
+pub fn lint(history: &History, since: u32) -> Result<Vec<Finding>, String> {
+    history.check("lint")?;
+    let answers = history.answers()?;
+    let acknowledged = acknowledged(history)?;
+    let mut findings = Vec::new();
+    for (i, migration) in history.migrations.iter().enumerate().filter(|(_, m)| m.number >= since) {
+        let old = if i == 0 { Vec::new() } else { history.migrations[i - 1].schema()? };
+        let renamed = |key: &str, _: &_| Ok(answers.get(key) == Some(&true));
+        let file = Migration::file_name(migration.number, &migration.name);
+        for (module, changes) in changes(&old, &migration.schema()?, renamed)? {
+            for change in changes {
+                let key = format!("{}/{}: {}", migration.fingerprint, module, change);
+                findings.push(Finding {
+                    migration: file.clone(),
+                    acknowledged: acknowledged.get(&key) == Some(&true),
+                    change: change.to_string(),
+                    compatibility: classify(&change),
+                    key
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+/// Add the breaking changes among `findings` to `breaking.toml`.
+pub fn acknowledge(history: &History, findings: &[Finding]) -> Result<(), String> {
+    let mut acknowledged = acknowledged(history)?;
+    for finding in findings.iter().filter(|finding| finding.compatibility != Compatibility::Compatible) {
+        acknowledged.insert(finding.key.clone(), true);
+    }
+    let path = history.dir.join(ACKNOWLEDGED);
+    let text = toml::to_string(&acknowledged).map_err(|e| format!("Cannot serialize `{}`: {}", ACKNOWLEDGED, e))?;
+    fs::write(&path, text).map_err(|e| format!("Cannot write `{}`: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::fixtures::card;
+    use crate::migration::History;
+    use super::{acknowledge, lint};
+
+    #[test]
+    fn acknowledgements_survive_renumbering() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path()).unwrap();
+        let migrations = [
+            ("card", &["name", "nick", "rank"][..]),
+            ("title", &["name", "nick", "rank", "title"]),
+            ("cleanup", &["name", "rank", "title"])
+        ];
+        for (name, props) in &migrations {
+            history.create(name, &card(props), |_, _| Ok(false)).unwrap();
+        }
+        let findings = lint(&history, 3).unwrap();
+        acknowledge(&history, &findings).unwrap();
+        let fingerprint = history.migrations[2].fingerprint.clone();
+        assert_eq!(findings[0].key, format!("{}/default: remove property `Card.nick`", fingerprint));
+
+        history.squash(1, 2, None, |_, _| Ok(false)).unwrap();
+        assert_eq!(history.migrations[1].number, 2);
+        assert!(lint(&history, 1).unwrap().iter().all(|finding| !finding.fails()));
+
+        history.create("unrank", &card(&["name", "title"]), |_, _| Ok(false)).unwrap();
+        let failing: Vec<String> = lint(&history, 1).unwrap().into_iter()
+            .filter(|finding| finding.fails())
+            .map(|finding| finding.key)
+            .collect();
+        let fingerprint = &history.migrations[2].fingerprint;
+        assert_eq!(failing, vec![format!("{}/default: remove property `Card.rank`", fingerprint)]);
+    }
+}
@@ -0,0 +1,1377 @@
+//! `edm migration`: the on-disk history of schema migrations.
+//!
+//! A migrations directory holds numbered files, `0001_init.edgeql`,
+//! `0002_add_cards.edgeql`, ..., each starting with a header like
+//!
+//! ```text
+//! # edgemorph migration 0002
+//! # parent: 5f0c…
+//! # fingerprint: 9a41…
+//! # hash: 03be…
+//! ```
+//!
+//! followed by a blank line and the body. `parent` is the hash of the
+//! previous migration (`none` for the first), `fingerprint` is the
+//! schema fingerprint the migration produces, and `hash` covers the
+//! file's number, the parent, the fingerprint and the body, so that
+//! editing, removing or renumbering a file breaks the chain.
+//!
+//! The body is a list of numbered DDL steps; those that change a module
+//! other than `default` run between `SET MODULE <module>;` and `RESET
+//! MODULE;`. Data-migration hooks, EdgeQL written by hand that runs
+//! right before or after a step, go between them:
+//!
+//! ```text
+//! # before step 2
+//! UPDATE User FILTER NOT EXISTS .email SET { email := '' };
+//!
+//! # step 2: make `User.email` required (needs backfill)
+//! ALTER TYPE User { ALTER PROPERTY email { SET REQUIRED; }; };
+//! ```
+//!
+//! After adding hooks to the last migration, `edm migration rehash`
+//! records its new hash.
+//!
+//! Each step is followed by the DDL that undoes it, which `edm
+//! migration revert` runs in reverse order, or is marked irreversible
+//! when undoing it would need data that it dropped:
+//!
+//! ```text
+//! # down step 2
+//! ALTER TYPE User { ALTER PROPERTY email { SET OPTIONAL; }; };
+//!
+//! # step 3: remove property `User.nick` (destructive)
+//! ALTER TYPE User { DROP PROPERTY nick; };
+//! # down step 3: irreversible
+//! ```
+//!
+//! Next to each file, a `.json` file of the same name keeps the schema
+//! that the migration produces, which later migrations are diffed
+//! against and which squashing re-diffs. `renames.toml` keeps the
+//! answers given to rename proposals, so that creating a migration
+//! again, e.g. in CI, makes the same choices without asking.
+//! `installed.toml` keeps the fingerprint of the schema installed on
+//! each database, which `edm make install` plans from.
+//!
+//! Two branches that each create a migration on top of the same one
+//! fork the chain. `edm migration merge` rebases one branch onto the
+//! other by re-diffing its schemas.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::slice;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use edgemorph::datastructures::{Module, STD_SCALARS};
+use edgemorph::edgeql::type_references;
+use edgemorph::apply::apply;
+use edgemorph::ddl::{generate, Step};
+use edgemorph::diff::{diff, Change};
+use edgemorph::fingerprint::schema_fingerprint;
+use edgemorph::merge::{merge, Conflict};
+use edgemorph::rename::{diff_with_renames, propose, Proposal, Rename};
+
+/// File that keeps the answers to rename proposals.
+pub const ANSWERS: &str = "renames.toml";
+
+/// File that keeps the fingerprint of the schema installed on each
+/// database, keyed by its name in `edgemorph.toml`.
+pub const INSTALLED: &str = "installed.toml";
+
+const MAGIC: &str = "edgemorph migration";
+
+/// Module that names which are not qualified resolve against.
+const DEFAULT_MODULE: &str = "default";
+
+/// Modules whose types are always there.
+const STD_MODULES: &[&str] = &["std", "schema", "sys", "cfg", "math", "cal"];
+const STD_OBJECTS: &[&str] = &["BaseObject", "Object"];
+
+/// One migration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub number: u32,
+    pub name: String,
+    /// Hash of the previous migration, `None` for the first one.
+    pub parent: Option<String>,
+    /// Fingerprint of the schema after this migration.
+    pub fingerprint: String,
+    /// Hash recorded in the header.
+    pub hash: String,
+    pub body: String,
+    pub path: PathBuf
+}
+
+impl Migration {
+    pub fn file_name(number: u32, name: &str) -> String {
+        format!("{:04}_{}.edgeql", number, name)
+    }
+
+    /// File that keeps the schema this migration produces.
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.path.with_extension("json")
+    }
+
+    /// The schema this migration produces.
+    pub fn schema(&self) -> Result<Vec<Module>, String> {
+        let path = self.snapshot_path();
+        let json = fs::read_to_string(&path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Cannot deserialize `{}`: {}", path.display(), e))
+    }
+
+    /// Whether the recorded hash still matches the file's contents.
+    pub fn is_intact(&self) -> bool {
+        chain_hash(self.number, self.parent.as_deref(), &self.fingerprint, &self.body) == self.hash
+    }
+
+    /// The steps and hooks of the body, in file order.
+    pub fn blocks(&self) -> Result<Vec<Block>, String> {
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut text: Vec<&str> = Vec::new();
+        for line in self.body.lines() {
+            match Block::parse_header(line) {
+                Some(block) => {
+                    self.fill(blocks.last_mut(), &text)?;
+                    text.clear();
+                    blocks.push(block);
+                },
+                None => text.push(line)
+            }
+        }
+        self.fill(blocks.last_mut(), &text)?;
+        let steps = blocks.iter().filter(|block| matches!(block, Block::Step { .. })).count();
+        for (i, block) in blocks.iter().filter(|block| matches!(block, Block::Step { .. })).enumerate() {
+            if let Block::Step { number, .. } = block {
+                if *number != i + 1 {
+                    return Err(format!("`{}` has step {} where step {} belongs.", self.path.display(), number, i + 1));
+                }
+            }
+        }
+        for block in &blocks {
+            let (what, step) = match block {
+                Block::Hook { when, step, .. } => (format!("a hook {}", when), step),
+                Block::Down { step, .. } => ("a down".to_string(), step),
+                Block::Step { .. } => continue
+            };
+            if *step == 0 || *step > steps {
+                return Err(format!("`{}` has {} step {}, which does not exist.", self.path.display(), what, step));
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Give `block` the lines that follow its header.
+    fn fill(&self, block: Option<&mut Block>, text: &[&str]) -> Result<(), String> {
+        let content = text.join("\n").trim().to_string();
+        match block {
+            Some(Block::Step { ddl, .. }) => *ddl = content,
+            Some(Block::Hook { query, .. }) => *query = content,
+            Some(Block::Down { ddl: Some(ddl), .. }) => *ddl = content,
+            Some(Block::Down { step, ddl: None }) if !content.is_empty() => {
+                return Err(format!("`{}` has DDL under irreversible step {}.", self.path.display(), step));
+            },
+            Some(Block::Down { ddl: None, .. }) => (),
+            None if !content.is_empty() => {
+                return Err(format!("`{}` has text before its first step or hook.", self.path.display()));
+            },
+            None => ()
+        }
+        Ok(())
+    }
+
+    /// The steps and hooks in the order they run: each step with its
+    /// hooks around it.
+    pub fn run_order(&self) -> Result<Vec<Block>, String> {
+        let blocks = self.blocks()?;
+        let hooks = |at: usize, position: When| blocks.iter().filter(move |block| match block {
+            Block::Hook { when, step, .. } => *step == at && *when == position,
+            _ => false
+        });
+        let mut ordered: Vec<Block> = Vec::new();
+        for block in &blocks {
+            if let Block::Step { number, .. } = block {
+                ordered.extend(hooks(*number, When::Before).cloned());
+                ordered.push(block.clone());
+                ordered.extend(hooks(*number, When::After).cloned());
+            }
+        }
+        Ok(ordered)
+    }
+
+    /// The body as it runs. Hooks are written by hand, so the last
+    /// statement of one may lack its `;`.
+    pub fn script(&self) -> Result<String, String> {
+        let statements: Vec<String> = self.run_order()?
+            .into_iter()
+            .filter_map(|block| match block {
+                Block::Step { ddl, .. } => Some(ddl),
+                Block::Hook { query, .. } if query.ends_with(';') => Some(query),
+                Block::Hook { query, .. } => Some(format!("{};", query)),
+                Block::Down { .. } => None
+            })
+            .collect();
+        Ok(statements.join("\n"))
+    }
+
+    /// The DDL that undoes the migration: the down step of every step,
+    /// last step first. Hooks are not undone.
+    pub fn down_script(&self) -> Result<String, String> {
+        let blocks = self.blocks()?;
+        let mut script: Vec<String> = Vec::new();
+        let mut irreversible: Vec<String> = Vec::new();
+        for block in blocks.iter().rev() {
+            if let Block::Step { number, reason, .. } = block {
+                let down = blocks.iter().find_map(|block| match block {
+                    Block::Down { step, ddl } if step == number => Some(ddl.as_ref()),
+                    _ => None
+                });
+                match down.flatten() {
+                    Some(ddl) => script.push(ddl.clone()),
+                    None => irreversible.push(format!("    step {}: {}", number, reason))
+                }
+            }
+        }
+        if !irreversible.is_empty() {
+            irreversible.reverse();
+            return Err(format!(
+                "`{}` cannot be reverted; these steps have no down step:\n{}",
+                self.path.display(), irreversible.join("\n")));
+        }
+        Ok(script.join("\n"))
+    }
+
+    pub fn render(&self) -> String {
+        format!(
+            "# {} {:04}\n# parent: {}\n# fingerprint: {}\n# hash: {}\n\n{}",
+            MAGIC, self.number, self.parent.as_deref().unwrap_or("none"), self.fingerprint, self.hash, self.body)
+    }
+
+    pub fn parse(path: &Path, text: &str) -> Result<Migration, String> {
+        let invalid = |what: &str| format!("`{}` is not a valid migration: {}.", path.display(), what);
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let (number, name) = parse_file_name(file_name)
+            .ok_or_else(|| invalid("expected a name like `0001_init.edgeql`"))?;
+        let (header, body) = match text.find("\n\n") {
+            Some(end) => (&text[..end], &text[end + 2..]),
+            None => return Err(invalid("the header is not followed by a blank line"))
+        };
+        let mut lines = header.lines();
+        // The number in the first line is informational: the file name's
+        // number is the one that is hashed, so renaming a file shows up
+        // as an edit.
+        match lines.next() {
+            Some(line) if line.starts_with(&format!("# {} ", MAGIC)) => (),
+            _ => return Err(invalid(&format!("expected `# {} {:04}` as the first line", MAGIC, number)))
+        }
+        let mut field = |key: &str| {
+            lines.next()
+                .and_then(|line| line.strip_prefix("# "))
+                .and_then(|line| line.strip_prefix(key))
+                .and_then(|line| line.strip_prefix(": "))
+                .map(str::to_string)
+                .ok_or_else(|| invalid(&format!("missing `{}`", key)))
+        };
+        let parent = field("parent")?;
+        let fingerprint = field("fingerprint")?;
+        let hash = field("hash")?;
+        Ok(Migration {
+            number,
+            name: name.to_string(),
+            parent: if parent == "none" { None } else { Some(parent) },
+            fingerprint,
+            hash,
+            body: body.to_string(),
+            path: path.to_path_buf()
+        })
+    }
+}
+
+/// Body of a new migration: `steps`, numbered from 1.
+pub fn render_steps(steps: &[Step]) -> String {
+    render_body(steps, &[])
+}
+
+/// `steps`, numbered from 1, with `hooks` on the steps they are attached to.
+fn render_body(steps: &[Step], hooks: &[Hook]) -> String {
+    let mut sections: Vec<String> = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let number = i + 1;
+        let hooks = |position: When| hooks.iter()
+            .filter(move |hook| hook.step == number && hook.when == position)
+            .map(|hook| format!("# {} step {}\n{}\n", hook.when, number, hook.query));
+        sections.extend(hooks(When::Before));
+        sections.push(format!("# step {}: {} ({})\n{}\n", number, step.reason, step.risk, step.ddl));
+        sections.extend(hooks(When::After));
+        sections.push(match &step.down {
+            Some(ddl) => format!("# down step {}\n{}\n", number, ddl),
+            None => format!("# down step {}: irreversible\n", number)
+        });
+    }
+    sections.join("\n")
+}
+
+/// A hook, detached from the migration it was written in.
+#[derive(Debug, Clone, PartialEq)]
+struct Hook {
+    when:  When,
+    step:  usize,
+    query: String
+}
+
+/// When a hook runs, relative to its step.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum When {
+    Before,
+    After
+}
+
+impl fmt::Display for When {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            When::Before => "before",
+            When::After  => "after"
+        })
+    }
+}
+
+/// A section of a migration's body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// Generated DDL, numbered from 1.
+    Step { number: usize, reason: String, ddl: String },
+    /// EdgeQL written by hand that runs before or after a step.
+    Hook { when: When, step: usize, query: String },
+    /// DDL that undoes a step, `None` when the step is irreversible.
+    Down { step: usize, ddl: Option<String> }
+}
+
+impl Block {
+    /// The empty block that a `# step N: ...`, `# before step N`,
+    /// `# after step N` or `# down step N` line starts.
+    fn parse_header(line: &str) -> Option<Block> {
+        let line = line.strip_prefix("# ")?;
+        if let Some(step) = line.strip_prefix("down step ") {
+            return match step.strip_suffix(": irreversible") {
+                Some(step) => Some(Block::Down { step: step.parse().ok()?, ddl: None }),
+                None => Some(Block::Down { step: step.trim().parse().ok()?, ddl: Some(String::new()) })
+            };
+        }
+        if let Some(rest) = line.strip_prefix("step ") {
+            let (number, reason) = rest.split_at(rest.find(": ")?);
+            let number = number.parse().ok()?;
+            return Some(Block::Step { number, reason: reason[2..].to_string(), ddl: String::new() });
+        }
+        let (when, step) = match line.strip_prefix("before step ") {
+            Some(step) => (When::Before, step),
+            None => (When::After, line.strip_prefix("after step ")?)
+        };
+        Some(Block::Hook { when, step: step.trim().parse().ok()?, query: String::new() })
+    }
+}
+
+/// Hash that chains migration `number` to its parent.
+pub fn chain_hash(number: u32, parent: Option<&str>, fingerprint: &str, body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n{}\n", number, parent.unwrap_or("none"), fingerprint));
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn parse_file_name(file_name: &str) -> Option<(u32, &str)> {
+    let stem = file_name.strip_suffix(".edgeql")?;
+    let (number, name) = stem.split_at(stem.find('_')?);
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((number.parse().ok()?, &name[1..]))
+}
+
+/// Something wrong with the history on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The file's contents no longer match its recorded hash.
+    Edited(PathBuf),
+    /// There is no file with this number, but later ones exist.
+    Missing(u32),
+    /// More than one file has this number.
+    Duplicate(u32),
+    /// The file's parent is not the hash of the migration before it.
+    Unchained(PathBuf),
+    /// The schema kept next to a migration is missing or does not
+    /// match its fingerprint.
+    StaleSnapshot(PathBuf),
+    /// The body does not parse, or a hook refers to a type that does
+    /// not exist when it runs.
+    InvalidHook { path: PathBuf, message: String },
+    /// More than one migration follows migration `base`, `None` for
+    /// the start of the history, e.g. after merging two branches that
+    /// each created a migration. `heads` are the last migrations of
+    /// each branch.
+    Forked { base: Option<u32>, heads: Vec<PathBuf> }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Edited(path) => write!(f, "`{}` was edited after it was created", path.display()),
+            Problem::Missing(number) => write!(f, "migration {:04} is missing", number),
+            Problem::Duplicate(number) => write!(f, "more than one migration is numbered {:04}", number),
+            Problem::Unchained(path) => write!(f, "`{}` does not follow the migration before it", path.display()),
+            Problem::StaleSnapshot(path) => {
+                write!(f, "`{}` is missing or does not match its migration", path.display())
+            },
+            Problem::InvalidHook { path, message } => write!(f, "`{}`: {}", path.display(), message),
+            Problem::Forked { base, heads } => {
+                let heads: Vec<String> = heads.iter().map(|head| format!("`{}`", head.display())).collect();
+                match base {
+                    Some(base) => write!(f, "the history forks after migration {:04}", base)?,
+                    None => write!(f, "the history forks at its start")?
+                }
+                write!(f, " into branches ending in {}; run `edm migration merge`", heads.join(" and "))
+            }
+        }
+    }
+}
+
+/// Result of `edm migration status`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub migrations: usize,
+    /// Number and hash of the last migration.
+    pub head: Option<(u32, String)>,
+    pub problems: Vec<Problem>,
+    /// Whether the schema differs from what the last migration produces.
+    pub pending: bool
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.head {
+            Some((number, hash)) => writeln!(f, "{} migration(s), head {:04} ({})", self.migrations, number, hash)?,
+            None => writeln!(f, "No migrations.")?
+        }
+        for problem in &self.problems {
+            writeln!(f, "error: {}", problem)?;
+        }
+        if self.pending {
+            writeln!(f, "The schema has changes that no migration covers; run `edm migration create`.")?;
+        }
+        Ok(())
+    }
+}
+
+/// Branches of a history that forks.
+#[derive(Debug, Clone)]
+pub struct Fork<'a> {
+    /// The last migration before the fork, `None` at the start.
+    pub base: Option<&'a Migration>,
+    /// The migrations of each branch, in order.
+    pub branches: Vec<Vec<&'a Migration>>
+}
+
+/// The migrations found in a directory, ordered by number.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub dir: PathBuf,
+    pub migrations: Vec<Migration>
+}
+
+impl History {
+    /// Read every migration in `dir`. A directory that does not exist
+    /// yet is an empty history.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<History, String> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut migrations = Vec::new();
+        if dir.exists() {
+            let entries = fs::read_dir(&dir).map_err(|e| format!("Cannot read `{}`: {}", dir.display(), e))?;
+            for entry in entries {
+                let path = entry.map_err(|e| format!("Cannot read `{}`: {}", dir.display(), e))?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("edgeql") {
+                    continue;
+                }
+                let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+                migrations.push(Migration::parse(&path, &text)?);
+            }
+        }
+        migrations.sort_by(|a, b| (a.number, &a.path).cmp(&(b.number, &b.path)));
+        Ok(History { dir, migrations })
+    }
+
+    pub fn head(&self) -> Option<&Migration> {
+        self.migrations.last()
+    }
+
+    /// The schema produced by the last migration.
+    pub fn snapshot(&self) -> Result<Vec<Module>, String> {
+        self.head().map_or(Ok(Vec::new()), Migration::schema)
+    }
+
+    /// Answers to rename proposals, keyed by `<module>::<rename>`.
+    pub fn answers(&self) -> Result<BTreeMap<String, bool>, String> {
+        let path = self.dir.join(ANSWERS);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Cannot deserialize `{}`: {}", path.display(), e))
+    }
+
+    /// Fingerprints of the schemas installed on each database. A
+    /// database that is not listed is empty.
+    pub fn installed(&self) -> Result<BTreeMap<String, String>, String> {
+        let path = self.dir.join(INSTALLED);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read `{}`: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Cannot deserialize `{}`: {}", path.display(), e))
+    }
+
+    /// Note that `database` now has the schema with `fingerprint`,
+    /// `None` once it is empty again.
+    pub fn record_installed(&self, database: &str, fingerprint: Option<&str>) -> Result<(), String> {
+        let mut installed = self.installed()?;
+        match fingerprint {
+            Some(fingerprint) => installed.insert(database.to_string(), fingerprint.to_string()),
+            None => installed.remove(database)
+        };
+        let path = self.dir.join(INSTALLED);
+        let text = toml::to_string(&installed).map_err(|e| format!("Cannot serialize `{}`: {}", path.display(), e))?;
+        fs::write(&path, text).map_err(|e| format!("Cannot write `{}`: {}", path.display(), e))
+    }
+
+    /// The first place where more than one migration follows the same
+    /// parent, with the branches that start there.
+    pub fn fork(&self) -> Option<Fork<'_>> {
+        let children = |parent: Option<&str>| -> Vec<&Migration> {
+            self.migrations.iter().filter(|migration| migration.parent.as_deref() == parent).collect()
+        };
+        let base = std::iter::once(None)
+            .chain(self.migrations.iter().map(Some))
+            .find(|base| children(base.map(|base| base.hash.as_str())).len() > 1)?;
+        let branches = children(base.map(|base| base.hash.as_str()))
+            .into_iter()
+            .map(|first| {
+                let mut branch = vec![first];
+                while let Some(next) = children(Some(&branch[branch.len() - 1].hash)).first() {
+                    branch.push(next);
+                }
+                branch
+            })
+            .collect();
+        Some(Fork { base, branches })
+    }
+
+    /// Edited, missing, duplicated and out-of-order migrations, and
+    /// forks.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = self.migrations.iter()
+            .filter(|migration| !migration.is_intact())
+            .map(|migration| Problem::Edited(migration.path.clone()))
+            .collect();
+        // The branches of a fork run in no particular order until they
+        // are merged, so the history is not checked as one sequence.
+        if let Some(fork) = self.fork() {
+            problems.push(Problem::Forked {
+                base: fork.base.map(|base| base.number),
+                heads: fork.branches.iter().map(|branch| branch[branch.len() - 1].path.clone()).collect()
+            });
+        } else {
+            problems.extend(self.sequence_problems());
+            problems.extend(self.hook_problems());
+        }
+        for migration in &self.migrations {
+            match migration.schema() {
+                Ok(schema) if fingerprint(&schema).as_deref() == Some(&migration.fingerprint) => (),
+                _ => problems.push(Problem::StaleSnapshot(migration.snapshot_path()))
+            }
+        }
+        problems
+    }
+
+    /// Gaps, duplicates and migrations that do not follow the one
+    /// before them.
+    fn sequence_problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut previous: Option<&Migration> = None;
+        for migration in &self.migrations {
+            let expected = previous.map_or(1, |previous| previous.number + 1);
+            if migration.number < expected {
+                problems.push(Problem::Duplicate(migration.number));
+            }
+            problems.extend((expected..migration.number).map(Problem::Missing));
+            // A gap is already reported as missing files.
+            if migration.number == expected && migration.parent.as_deref() != previous.map(|p| p.hash.as_str()) {
+                problems.push(Problem::Unchained(migration.path.clone()));
+            }
+            previous = Some(migration);
+        }
+        problems.dedup();
+        problems
+    }
+
+    /// Fails with every problem if there are any, as the reason for
+    /// refusing to `action`.
+    pub fn check(&self, action: &str) -> Result<(), String> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let problems: Vec<String> = problems.iter().map(|problem| format!("    {}", problem)).collect();
+        Err(format!(
+            "Refusing to {}, the history in `{}` is damaged:\n{}", action, self.dir.display(), problems.join("\n")))
+    }
+
+    /// Replays the steps of every migration on the set of type names,
+    /// checking each hook against the types that exist when it runs.
+    fn hook_problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut types: BTreeSet<String> = BTreeSet::new();
+        for migration in &self.migrations {
+            let invalid = |message: String| Problem::InvalidHook { path: migration.path.clone(), message };
+            let blocks = match migration.blocks() {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    problems.push(invalid(e));
+                    continue;
+                }
+            };
+            let steps = blocks.iter().filter_map(|block| match block {
+                Block::Step { number, ddl, .. } => Some((*number, ddl)),
+                _ => None
+            });
+            for (number, ddl) in steps {
+                let hooks = |position: When| blocks.iter().filter_map(move |block| match block {
+                    Block::Hook { when, step, query } if *step == number && *when == position => Some(query),
+                    _ => None
+                });
+                let check = |when: When, types: &BTreeSet<String>| hooks(when)
+                    .filter_map(|query| unknown_types(query, types))
+                    .map(|e| invalid(format!("{} step {}: {}", when, number, e)))
+                    .collect::<Vec<_>>();
+                problems.extend(check(When::Before, &types));
+                track_types(&mut types, ddl);
+                problems.extend(check(When::After, &types));
+            }
+        }
+        problems
+    }
+
+    pub fn status(&self, schema: &[Module]) -> Status {
+        Status {
+            migrations: self.migrations.len(),
+            head: self.head().map(|head| (head.number, head.hash.clone())),
+            problems: self.problems(),
+            pending: self.head().map(|head| head.fingerprint.as_str()) != fingerprint(schema).as_deref()
+        }
+    }
+
+    /// Record the new hash of the last migration after hooks were
+    /// added to it. Refuses if that leaves any problem.
+    pub fn rehash(&mut self) -> Result<&Migration, String> {
+        let mut rehashed = self.clone();
+        let head = match rehashed.migrations.last_mut() {
+            Some(head) => head,
+            None => return Err(format!("There are no migrations in `{}`.", self.dir.display()))
+        };
+        head.hash = chain_hash(head.number, head.parent.as_deref(), &head.fingerprint, &head.body);
+        let problems = rehashed.problems();
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems.iter().map(|problem| format!("    {}", problem)).collect();
+            return Err(format!("Refusing to rehash the last migration:\n{}", problems.join("\n")));
+        }
+        let head = rehashed.migrations.pop().unwrap();
+        fs::write(&head.path, head.render()).map_err(|e| format!("Cannot write `{}`: {}", head.path.display(), e))?;
+        self.migrations.pop();
+        self.migrations.push(head);
+        Ok(self.migrations.last().unwrap())
+    }
+
+    /// Write the migration from the last migration's schema to `schema`,
+    /// along with the schema it produces. Refuses to extend a damaged
+    /// history.
+    ///
+    /// Rename proposals are answered from `renames.toml`, or else by
+    /// `ask`, whose answers are added to the file.
+    pub fn create<F>(&mut self, name: &str, schema: &[Module], mut ask: F) -> Result<&Migration, String>
+        where F: FnMut(&str, &Proposal) -> Result<bool, String>
+    {
+        self.check("create a migration")?;
+        let steps = self.steps(&self.snapshot()?, schema, &mut ask)?;
+        if steps.is_empty() {
+            return Err("No schema changes since the last migration.".to_string());
+        }
+        let name = slug(name);
+        let number = self.head().map_or(1, |head| head.number + 1);
+        let parent = self.head().map(|head| head.hash.clone());
+        let fingerprint = schema_fingerprint(schema);
+        let body = render_steps(&steps);
+        let migration = Migration {
+            hash: chain_hash(number, parent.as_deref(), &fingerprint, &body),
+            path: self.dir.join(Migration::file_name(number, &name)),
+            number,
+            name,
+            parent,
+            fingerprint,
+            body
+        };
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Cannot create `{}`: {}", self.dir.display(), e))?;
+        write(&migration, schema)?;
+        self.migrations.push(migration);
+        Ok(self.migrations.last().unwrap())
+    }
+
+    /// Replace migrations `from` to `to` with one migration, re-diffed
+    /// from the schema before `from` to the schema after `to`, and
+    /// renumber and rechain the migrations after them.
+    ///
+    /// Hooks are kept when the step they are attached to is still in
+    /// the squashed migration; otherwise squashing is refused. Databases
+    /// that stopped partway through the range could no longer be
+    /// migrated from the files, so squashing is also refused while
+    /// `installed.toml` records one of them.
+    pub fn squash<F>(&mut self, from: u32, to: u32, name: Option<&str>, mut ask: F) -> Result<&Migration, String>
+        where F: FnMut(&str, &Proposal) -> Result<bool, String>
+    {
+        self.check("squash")?;
+        let index = |number: u32| self.migrations.iter().position(|m| m.number == number)
+            .ok_or_else(|| format!("There is no migration {:04} in `{}`.", number, self.dir.display()));
+        let (first, last) = (index(from)?, index(to)?);
+        if first >= last {
+            return Err(format!("Cannot squash {:04} to {:04}: the range needs at least two migrations.", from, to));
+        }
+        let stranded: Vec<String> = self.installed()?.into_iter()
+            .filter(|(_, fingerprint)| self.migrations[first..last].iter().any(|m| &m.fingerprint == fingerprint))
+            .map(|(database, _)| format!("`{}`", database))
+            .collect();
+        if !stranded.is_empty() {
+            return Err(format!(
+                "Cannot squash {:04} to {:04}: {} stopped partway through the range; install {:04} on it first.",
+                from, to, stranded.join(", "), to));
+        }
+        let before = if first == 0 { Vec::new() } else { self.migrations[first - 1].schema()? };
+        let after = self.migrations[last].schema()?;
+        let changes = self.changes(&before, &after, &mut ask)?;
+        // The squashed migration has to produce the very schema that the
+        // range did.
+        let fingerprint = schema_fingerprint(&produce(&before, &changes)?);
+        if fingerprint != self.migrations[last].fingerprint {
+            return Err(format!(
+                "Cannot squash {:04} to {:04}: its steps would not produce the schema of {:04}.", from, to, to));
+        }
+        let steps = steps(&changes);
+
+        let mut hooks: Vec<Hook> = Vec::new();
+        for migration in &self.migrations[first..=last] {
+            let carried = carry_hooks(migration, &steps, "the squashed migration")
+                .map_err(|e| format!("Cannot squash {:04} to {:04}: {}.", from, to, e))?;
+            hooks.extend(carried);
+        }
+
+        let name = name.map_or_else(|| format!("squash_{:04}_{:04}", from, to), slug);
+        let parent = self.migrations[first].parent.clone();
+        let body = render_body(&steps, &hooks);
+        let squashed = Migration {
+            hash: chain_hash(from, parent.as_deref(), &fingerprint, &body),
+            path: self.dir.join(Migration::file_name(from, &name)),
+            number: from,
+            name,
+            parent,
+            fingerprint,
+            body
+        };
+
+        let mut rewritten: Vec<(Migration, Vec<Module>)> = vec![(squashed, after)];
+        for migration in &self.migrations[last + 1..] {
+            let parent = rewritten.last().map(|(previous, _)| previous.hash.clone());
+            let number = migration.number - (to - from);
+            let mut renumbered = Migration {
+                number,
+                path: self.dir.join(Migration::file_name(number, &migration.name)),
+                parent,
+                ..migration.clone()
+            };
+            renumbered.hash =
+                chain_hash(number, renumbered.parent.as_deref(), &renumbered.fingerprint, &renumbered.body);
+            rewritten.push((renumbered, migration.schema()?));
+        }
+        for migration in &self.migrations[first..] {
+            for path in &[migration.path.clone(), migration.snapshot_path()] {
+                fs::remove_file(path).map_err(|e| format!("Cannot remove `{}`: {}", path.display(), e))?;
+            }
+        }
+        self.migrations.truncate(first);
+        for (migration, schema) in rewritten {
+            write(&migration, &schema)?;
+            self.migrations.push(migration);
+        }
+        Ok(&self.migrations[first])
+    }
+
+    /// Rebase one branch of a forked history onto the other: each of
+    /// its migrations is diffed again, from the last migration written
+    /// so far to the other branch's schema merged with its own, and
+    /// renumbered after the other branch. `rebase` names any migration
+    /// of the branch to rebase, by file name; by default it is the one
+    /// whose first migration sorts last. Refuses, writing nothing, when
+    /// both branches change the same item, or while `installed.toml`
+    /// records a database at a migration of the rebased branch, which
+    /// the rewritten files could no longer migrate.
+    pub fn merge<F>(&mut self, rebase: Option<&str>, mut ask: F) -> Result<Vec<PathBuf>, String>
+        where F: FnMut(&str, &Proposal) -> Result<bool, String>
+    {
+        let fork = match self.fork() {
+            Some(fork) => fork,
+            None => return Err(format!("The history in `{}` does not fork; there is nothing to merge.",
+                                       self.dir.display()))
+        };
+        let problems: Vec<String> = self.problems().iter()
+            .filter(|problem| !matches!(problem, Problem::Forked { .. }))
+            .map(|problem| format!("    {}", problem))
+            .collect();
+        if !problems.is_empty() {
+            return Err(format!(
+                "Refusing to merge, the history in `{}` is damaged:\n{}", self.dir.display(), problems.join("\n")));
+        }
+        if fork.branches.len() != 2 {
+            return Err(format!("The history forks into {} branches; only two can be merged.", fork.branches.len()));
+        }
+        let named = |branch: &Vec<&Migration>, name: &str| branch.iter()
+            .any(|migration| migration.path.file_name().and_then(|file| file.to_str()) == Some(name));
+        let index = match rebase {
+            None => 1,
+            Some(name) => match fork.branches.iter().position(|branch| named(branch, name)) {
+                Some(index) => index,
+                None => return Err(format!("`{}` is not a migration of either branch.", name))
+            }
+        };
+        let kept = fork.branches[1 - index][fork.branches[1 - index].len() - 1];
+        let rebased = &fork.branches[index];
+        let stranded: Vec<String> = self.installed()?.into_iter()
+            .filter(|(_, fingerprint)| rebased.iter().any(|m| &m.fingerprint == fingerprint))
+            .map(|(database, _)| format!("`{}`", database))
+            .collect();
+        if !stranded.is_empty() {
+            return Err(format!(
+                "Cannot rebase `{}`: {} installed migrations of its branch; revert to {:04} first, \
+                 or rebase the other branch.",
+                rebased[0].path.display(), stranded.join(", "), fork.base.map_or(0, |base| base.number)));
+        }
+
+        let base = fork.base.map_or(Ok(Vec::new()), Migration::schema)?;
+        let ours = kept.schema()?;
+        let mut previous = ours.clone();
+        let mut parent = kept.hash.clone();
+        let mut number = kept.number + 1;
+        let mut conflicts: Vec<String> = Vec::new();
+        let mut rewritten: Vec<(Migration, Vec<Module>)> = Vec::new();
+        for migration in rebased {
+            let merged = match merge_schemas(&base, &ours, &migration.schema()?) {
+                Ok(merged) => merged,
+                Err(found) => {
+                    let file = Migration::file_name(migration.number, &migration.name);
+                    conflicts.extend(found.iter().map(|conflict| format!("    {}: {}", file, conflict)));
+                    continue;
+                }
+            };
+            if !conflicts.is_empty() {
+                continue;
+            }
+            let steps = self.steps(&previous, &merged, &mut ask)?;
+            let hooks = carry_hooks(migration, &steps, "the rebased migration")
+                .map_err(|e| format!("Cannot rebase `{}`: {}.", migration.path.display(), e))?;
+            // The other branch made the same changes already.
+            if steps.is_empty() {
+                continue;
+            }
+            let fingerprint = schema_fingerprint(&merged);
+            let body = render_body(&steps, &hooks);
+            let rebased = Migration {
+                hash: chain_hash(number, Some(&parent), &fingerprint, &body),
+                path: self.dir.join(Migration::file_name(number, &migration.name)),
+                number,
+                name: migration.name.clone(),
+                parent: Some(parent.clone()),
+                fingerprint,
+                body
+            };
+            parent = rebased.hash.clone();
+            number += 1;
+            previous = merged.clone();
+            rewritten.push((rebased, merged));
+        }
+        if !conflicts.is_empty() {
+            return Err(format!(
+                "Cannot rebase `{}` onto `{}`, both branches change the same items:\n{}",
+                rebased[0].path.display(), kept.path.display(), conflicts.join("\n")));
+        }
+
+        for migration in rebased {
+            for path in &[migration.path.clone(), migration.snapshot_path()] {
+                fs::remove_file(path).map_err(|e| format!("Cannot remove `{}`: {}", path.display(), e))?;
+            }
+        }
+        for (migration, schema) in &rewritten {
+            write(migration, schema)?;
+        }
+        *self = History::load(&self.dir)?;
+        Ok(rewritten.into_iter().map(|(migration, _)| migration.path).collect())
+    }
+
+    /// The steps from `old` to `new`, with rename proposals answered
+    /// as `self.changes` does.
+    fn steps<F>(&self, old: &[Module], new: &[Module], ask: &mut F) -> Result<Vec<Step>, String>
+        where F: FnMut(&str, &Proposal) -> Result<bool, String>
+    {
+        Ok(steps(&self.changes(old, new, ask)?))
+    }
+
+    /// The changes from `old` to `new`, with rename proposals answered
+    /// from `renames.toml` or else by `ask`, whose answers are added
+    /// to the file.
+    fn changes<F>(&self, old: &[Module], new: &[Module], ask: &mut F) -> Result<Vec<(String, Vec<Change>)>, String>
+        where F: FnMut(&str, &Proposal) -> Result<bool, String>
+    {
+        let mut answers = self.answers()?;
+        let asked = answers.len();
+        let changes = changes(old, new, |key, proposal| match answers.get(key) {
+            Some(answer) => Ok(*answer),
+            None => {
+                let answer = ask(key, proposal)?;
+                answers.insert(key.to_string(), answer);
+                Ok(answer)
+            }
+        })?;
+        if answers.len() != asked {
+            let text = toml::to_string(&answers).map_err(|e| format!("Cannot serialize answers: {}", e))?;
+            fs::create_dir_all(&self.dir).map_err(|e| format!("Cannot create `{}`: {}", self.dir.display(), e))?;
+            fs::write(self.dir.join(ANSWERS), text)
+                .map_err(|e| format!("Cannot write `{}`: {}", self.dir.join(ANSWERS).display(), e))?;
+        }
+        Ok(changes)
+    }
+}
+
+/// The hooks of `migration`, attached to the steps of `steps` that
+/// have the DDL of the steps they were attached to. Fails on the first
+/// hook whose step is not among `steps`, which belong to `target`.
+fn carry_hooks(migration: &Migration, steps: &[Step], target: &str) -> Result<Vec<Hook>, String> {
+    let blocks = migration.blocks()?;
+    let mut hooks = Vec::new();
+    for block in &blocks {
+        if let Block::Hook { when, step, query } = block {
+            let ddl = blocks.iter().find_map(|block| match block {
+                Block::Step { number, ddl, .. } if number == step => Some(ddl),
+                _ => None
+            });
+            match steps.iter().position(|carried| Some(&carried.ddl) == ddl) {
+                Some(i) => hooks.push(Hook { when: *when, step: i + 1, query: query.clone() }),
+                None => return Err(format!(
+                    "the hook {} step {} of `{}` is attached to a step that {} does not have",
+                    when, step, migration.path.display(), target))
+            }
+        }
+    }
+    Ok(hooks)
+}
+
+/// Merge two schemas module by module, pairing modules by name. A
+/// module missing on one side is merged as an empty module, and left
+/// out if nothing remains of it.
+fn merge_schemas(base: &[Module], ours: &[Module], theirs: &[Module]) -> Result<Vec<Module>, Vec<Conflict>> {
+    let find = |modules: &[Module], ident: &str| modules.iter().find(|module| module.ident == ident).cloned();
+    let mut idents: Vec<&str> = ours.iter().map(|module| module.ident.as_str()).collect();
+    idents.extend(theirs.iter().map(|module| module.ident.as_str()).filter(|ident| find(ours, ident).is_none()));
+    let mut merged = Vec::new();
+    let mut conflicts = Vec::new();
+    for ident in idents {
+        let side = |modules: &[Module]| find(modules, ident).unwrap_or_else(|| Module::new(ident));
+        match merge(&side(base), &side(ours), &side(theirs)) {
+            Ok(module) => {
+                let missing = find(ours, ident).is_none() || find(theirs, ident).is_none();
+                let empty = schema_fingerprint(slice::from_ref(&module)) == schema_fingerprint(&[Module::new(ident)]);
+                if !(missing && empty) {
+                    merged.push(module);
+                }
+            },
+            Err(found) => conflicts.extend(found)
+        }
+    }
+    if conflicts.is_empty() { Ok(merged) } else { Err(conflicts) }
+}
+
+/// `name` as it appears in file names.
+fn slug(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+/// Write `migration` and the schema it produces.
+fn write(migration: &Migration, schema: &[Module]) -> Result<(), String> {
+    let snapshot = serde_json::to_string_pretty(schema).map_err(|e| format!("Cannot serialize schema: {}", e))?;
+    fs::write(&migration.path, migration.render())
+        .map_err(|e| format!("Cannot write `{}`: {}", migration.path.display(), e))?;
+    fs::write(migration.snapshot_path(), snapshot)
+        .map_err(|e| format!("Cannot write `{}`: {}", migration.snapshot_path().display(), e))
+}
+
+/// Update the set of existing types for one generated DDL statement:
+/// `CREATE [ABSTRACT] [SCALAR] TYPE X ...`, `DROP [SCALAR] TYPE X` or
+/// `ALTER [SCALAR] TYPE X RENAME TO Y`, possibly run in another module.
+fn track_types(types: &mut BTreeSet<String>, ddl: &str) {
+    let ddl = match ddl.strip_prefix("SET MODULE ").and_then(|rest| rest.find('\n').map(|end| &rest[end + 1..])) {
+        Some(ddl) => ddl,
+        None => ddl
+    };
+    let words: Vec<&str> = ddl.split(|c: char| c.is_whitespace() || c == ';').filter(|w| !w.is_empty()).collect();
+    let rest: Vec<&str> = words.iter().skip(1).skip_while(|w| **w == "ABSTRACT" || **w == "SCALAR").cloned().collect();
+    match (words.first(), &rest[..]) {
+        (Some(&"CREATE"), ["TYPE", name, ..]) | (Some(&"CREATE"), ["ALIAS", name, ..]) => {
+            types.insert(name.to_string());
+        },
+        (Some(&"DROP"), ["TYPE", name, ..]) | (Some(&"DROP"), ["ALIAS", name, ..]) => {
+            types.remove(*name);
+        },
+        (Some(&"ALTER"), ["TYPE", from, "RENAME", "TO", to, ..]) => {
+            types.remove(*from);
+            types.insert(to.to_string());
+        },
+        _ => ()
+    }
+}
+
+/// The types that `query` refers to but that are not in `types`.
+fn unknown_types(query: &str, types: &BTreeSet<String>) -> Option<String> {
+    let references = match type_references(query) {
+        Ok(references) => references,
+        Err(e) => return Some(format!("cannot read the hook: {}", e))
+    };
+    let unknown: Vec<String> = references.into_iter()
+        .filter(|name| {
+            let (module, ident) = match name.rfind("::") {
+                Some(i) => (Some(&name[..i]), &name[i + 2..]),
+                None => (None, name.as_str())
+            };
+            let std = module.map_or(STD_OBJECTS.contains(&ident) || STD_SCALARS.contains(&ident), |module| {
+                STD_MODULES.contains(&module)
+            });
+            !std && !types.contains(ident)
+        })
+        .map(|name| format!("`{}`", name))
+        .collect();
+    if unknown.is_empty() {
+        None
+    } else {
+        Some(format!("the hook refers to {}, which does not exist at that point", unknown.join(", ")))
+    }
+}
+
+/// Fingerprint of `schema`, `None` while nothing has been migrated.
+fn fingerprint(schema: &[Module]) -> Option<String> {
+    if schema.is_empty() { None } else { Some(schema_fingerprint(schema)) }
+}
+
+/// Steps for the changes to every module, as `changes` lists them.
+fn steps(changes: &[(String, Vec<Change>)]) -> Vec<Step> {
+    changes.iter().flat_map(|(module, changes)| in_module(module, generate(changes))).collect()
+}
+
+/// The schema that `changes`, as `changes` lists them, turn `old`
+/// into. A module of `old` is left out if nothing remains of it.
+fn produce(old: &[Module], changes: &[(String, Vec<Change>)]) -> Result<Vec<Module>, String> {
+    let mut produced = Vec::new();
+    for (ident, changes) in changes {
+        let before = old.iter().find(|module| &module.ident == ident);
+        let module = apply(&before.cloned().unwrap_or_else(|| Module::new(ident)), changes)?;
+        let empty = schema_fingerprint(slice::from_ref(&module)) == schema_fingerprint(&[Module::new(ident)]);
+        if !(before.is_some() && empty) {
+            produced.push(module);
+        }
+    }
+    Ok(produced)
+}
+
+/// `steps`, made to run in `module`. The generated DDL uses names that
+/// are not qualified, which resolve against the current module, so the
+/// steps of modules other than `default` set it and reset it again.
+fn in_module(module: &str, steps: Vec<Step>) -> Vec<Step> {
+    if module == DEFAULT_MODULE {
+        return steps;
+    }
+    let wrap = |ddl: &str| format!("SET MODULE {};\n{}\nRESET MODULE;", module, ddl);
+    steps.into_iter()
+        .map(|step| Step { ddl: wrap(&step.ddl), down: step.down.as_deref().map(wrap), ..step })
+        .collect()
+}
+
+/// Changes to every module, by module name, pairing modules by name.
+/// Modules missing on one side are diffed from or to an empty module.
+/// `confirm` decides on each rename proposal, given its key in
+/// `renames.toml`.
+pub(crate) fn changes<F>(old: &[Module], new: &[Module], mut confirm: F)
+    -> Result<Vec<(String, Vec<Change>)>, String>
+    where F: FnMut(&str, &Proposal) -> Result<bool, String>
+{
+    let find = |modules: &[Module], ident: &str| modules.iter().find(|module| module.ident == ident).cloned();
+    let mut changes = Vec::new();
+    for module in new {
+        let before = find(old, &module.ident).unwrap_or_else(|| Module::new(&module.ident));
+        let mut renames: Vec<Rename> = Vec::new();
+        let mut rejected: Vec<String> = Vec::new();
+        for proposal in propose(&before, module) {
+            // Pointer proposals on a type assume that its rename was accepted.
+            if let Rename::Pointer { at, .. } = &proposal.rename {
+                if rejected.contains(&at.ty) {
+                    continue;
+                }
+            }
+            if confirm(&format!("{}::{}", module.ident, proposal.rename), &proposal)? {
+                renames.push(proposal.rename);
+            } else if let Rename::Type { to, .. } = proposal.rename {
+                rejected.push(to);
+            }
+        }
+        changes.push((module.ident.clone(), diff_with_renames(&before, module, &renames)?));
+    }
+    for module in old.iter().filter(|module| find(new, &module.ident).is_none()) {
+        changes.push((module.ident.clone(), diff(module, &Module::new(&module.ident))));
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use edgemorph::datastructures::{Module, Type, TypeBuilder};
+    use edgemorph::rename::Proposal;
+    use tempfile::TempDir;
+    use crate::fixtures::{card, schema, ty};
+    use super::{History, Problem, INSTALLED};
+
+    fn no_input(key: &str, _: &Proposal) -> Result<bool, String> {
+        Err(format!("Rename proposal `{}` is not answered.", key))
+    }
+
+    /// A history of one migration per schema, each named after the
+    /// type it adds.
+    fn history(dir: &Path, schemas: &[Vec<Module>]) -> History {
+        let mut history = History::load(dir).unwrap();
+        for schema in schemas {
+            let name = schema[0].types.last().unwrap().ident.to_lowercase();
+            history.create(&name, schema, no_input).unwrap();
+        }
+        history
+    }
+
+    fn cards() -> Vec<Vec<Module>> {
+        let types = [ty("Card", &[("name", "str")]), ty("Deck", &[]), ty("Player", &[]), ty("Game", &[])];
+        (1..=types.len()).map(|n| schema("default", types[..n].to_vec())).collect()
+    }
+
+    fn file_names(history: &History) -> Vec<String> {
+        history.migrations.iter().map(|m| m.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn creates_migrations_that_status_accepts() {
+        let dir = TempDir::new().unwrap();
+        let schemas = cards();
+        let history = history(dir.path(), &schemas[..2]);
+        assert_eq!(file_names(&history), vec!["0001_card.edgeql", "0002_deck.edgeql"]);
+        assert!(dir.path().join("0002_deck.json").exists());
+
+        let history = History::load(dir.path()).unwrap();
+        let status = history.status(&schemas[1]);
+        assert_eq!(status.migrations, 2);
+        assert_eq!(status.head, Some((2, history.migrations[1].hash.clone())));
+        assert_eq!(status.problems, vec![]);
+        assert!(!status.pending);
+        assert!(history.status(&schemas[2]).pending);
+        assert_eq!(history.migrations[1].parent.as_ref(), Some(&history.migrations[0].hash));
+    }
+
+    #[test]
+    fn runs_steps_in_their_module() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &[schema("cards", vec![ty("Card", &[])])]);
+        assert_eq!(history.migrations[0].script().unwrap(), "SET MODULE cards;\nCREATE TYPE Card;\nRESET MODULE;");
+        assert_eq!(history.problems(), vec![]);
+    }
+
+    #[test]
+    fn checks_hooks_against_the_types_that_exist() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &cards()[..2]);
+        let path = history.migrations[1].path.clone();
+        let text = fs::read_to_string(&path).unwrap();
+        let hook_problems = |hooks: &str| {
+            fs::write(&path, text.replacen("# down step 1", &format!("{}\n\n# down step 1", hooks), 1)).unwrap();
+            History::load(dir.path()).unwrap().hook_problems()
+        };
+        let invalid = |message: &str| Problem::InvalidHook { path: path.clone(), message: message.to_string() };
+        let update = "# after step 1\nWITH D := (SELECT Deck) UPDATE D SET { n := <str>count(Card) };";
+        assert_eq!(hook_problems(update), vec![]);
+        assert_eq!(hook_problems("# before step 1\nFOR d IN {Deck} UNION (DELETE d)"), vec![
+            invalid("before step 1: the hook refers to `Deck`, which does not exist at that point")
+        ]);
+        assert_eq!(hook_problems("# after step 1\nDELETE deck"), vec![
+            invalid("after step 1: the hook refers to `deck`, which does not exist at that point")
+        ]);
+    }
+
+    #[test]
+    fn reports_edited_migrations() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &cards()[..2]);
+        let path = &history.migrations[0].path;
+        let text = fs::read_to_string(path).unwrap();
+        fs::write(path, text.replace("CREATE TYPE Card", "CREATE TYPE Kard")).unwrap();
+        assert_eq!(History::load(dir.path()).unwrap().problems(), vec![Problem::Edited(path.clone())]);
+    }
+
+    #[test]
+    fn reports_missing_migrations() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &cards()[..3]);
+        fs::remove_file(&history.migrations[1].path).unwrap();
+        fs::remove_file(history.migrations[1].snapshot_path()).unwrap();
+        assert_eq!(History::load(dir.path()).unwrap().problems(), vec![Problem::Missing(2)]);
+    }
+
+    /// Two branches that each add a type on top of `0001_card`: `ours`
+    /// in `0002_<type>` and `theirs` in `0002_theirs`.
+    fn fork(dir: &Path, ours: Type, theirs: Type) -> History {
+        let card = ty("Card", &[("name", "str")]);
+        history(dir, &[schema("default", vec![card.clone()]), schema("default", vec![card.clone(), ours])]);
+        let other = TempDir::new().unwrap();
+        let base = History::load(dir).unwrap().migrations[0].clone();
+        for path in &[base.path.clone(), base.snapshot_path()] {
+            fs::copy(path, other.path().join(path.file_name().unwrap())).unwrap();
+        }
+        let mut branch = History::load(other.path()).unwrap();
+        let head = branch.create("theirs", &schema("default", vec![card, theirs]), no_input).unwrap();
+        for path in &[head.path.clone(), head.snapshot_path()] {
+            fs::copy(path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+        History::load(dir).unwrap()
+    }
+
+    #[test]
+    fn reports_forks() {
+        let dir = TempDir::new().unwrap();
+        let history = fork(dir.path(), ty("Deck", &[]), ty("Player", &[]));
+        assert_eq!(history.problems(), vec![Problem::Forked {
+            base: Some(1),
+            heads: vec![dir.path().join("0002_deck.edgeql"), dir.path().join("0002_theirs.edgeql")]
+        }]);
+        assert!(history.check("install").unwrap_err().contains("run `edm migration merge`"));
+    }
+
+    #[test]
+    fn merges_forks() {
+        let dir = TempDir::new().unwrap();
+        let mut history = fork(dir.path(), ty("Deck", &[]), ty("Player", &[]));
+        let rebased = history.merge(None, no_input).unwrap();
+        assert_eq!(rebased, vec![dir.path().join("0003_theirs.edgeql")]);
+        assert_eq!(file_names(&history), vec!["0001_card.edgeql", "0002_deck.edgeql", "0003_theirs.edgeql"]);
+        assert_eq!(history.problems(), vec![]);
+        assert_eq!(history.migrations[2].script().unwrap(), "CREATE TYPE Player;");
+    }
+
+    #[test]
+    fn reports_merge_conflicts() {
+        let dir = TempDir::new().unwrap();
+        let mut history = fork(dir.path(), ty("Deck", &[("size", "int64")]), ty("Deck", &[("size", "str")]));
+        let e = history.merge(None, no_input).unwrap_err();
+        assert!(e.starts_with("Cannot rebase"), "{}", e);
+        assert!(e.contains("`default::Deck` was added differently on both sides"), "{}", e);
+        // Nothing was written.
+        assert_eq!(History::load(dir.path()).unwrap().migrations.len(), 3);
+    }
+
+    #[test]
+    fn refuses_to_rebase_installed_migrations() {
+        let dir = TempDir::new().unwrap();
+        let mut history = fork(dir.path(), ty("Deck", &[]), ty("Player", &[]));
+        let theirs = history.migrations.iter().find(|m| m.name == "theirs").unwrap().clone();
+        fs::write(dir.path().join(INSTALLED), format!("staging = \"{}\"\n", theirs.fingerprint)).unwrap();
+        let e = history.merge(None, no_input).unwrap_err();
+        assert_eq!(e, format!("Cannot rebase `{}`: `staging` installed migrations of its branch; revert to 0001 first, \
+                               or rebase the other branch.", theirs.path.display()));
+        assert_eq!(file_names(&History::load(dir.path()).unwrap()).len(), 3);
+        // Rebasing the other branch leaves `staging` where it is.
+        let rebased = history.merge(Some("0002_deck.edgeql"), no_input).unwrap();
+        assert_eq!(rebased, vec![dir.path().join("0003_deck.edgeql")]);
+    }
+
+    #[test]
+    fn squashes_and_renumbers() {
+        let dir = TempDir::new().unwrap();
+        let mut history = history(dir.path(), &cards());
+        let head = history.migrations[3].fingerprint.clone();
+        history.squash(2, 3, None, no_input).unwrap();
+        assert_eq!(file_names(&history), vec!["0001_card.edgeql", "0002_squash_0002_0003.edgeql", "0003_game.edgeql"]);
+        assert_eq!(history.migrations[1].script().unwrap(), "CREATE TYPE Deck;\nCREATE TYPE Player;");
+
+        let history = History::load(dir.path()).unwrap();
+        assert_eq!(file_names(&history).len(), 3);
+        assert_eq!(history.problems(), vec![]);
+        assert_eq!(history.migrations[2].fingerprint, head);
+    }
+
+    #[test]
+    fn refuses_to_squash_what_its_steps_would_not_produce() {
+        let dir = TempDir::new().unwrap();
+        let mut schemas = cards();
+        // No step brings in a builtin type.
+        schemas[2][0].types.insert(0, TypeBuilder::default().ident("BaseObject").builtin(true).build().unwrap());
+        let mut history = history(dir.path(), &schemas[..3]);
+        let e = history.squash(2, 3, None, no_input).unwrap_err();
+        assert_eq!(e, "Cannot squash 0002 to 0003: its steps would not produce the schema of 0003.");
+        assert_eq!(file_names(&history).len(), 3);
+    }
+
+    #[test]
+    fn refuses_to_squash_past_installed_databases() {
+        let dir = TempDir::new().unwrap();
+        let mut history = history(dir.path(), &cards());
+        let installed = format!("primary = \"{}\"\nstaging = \"{}\"\n",
+                                history.migrations[3].fingerprint, history.migrations[1].fingerprint);
+        fs::write(dir.path().join(INSTALLED), installed).unwrap();
+        let e = history.squash(2, 3, None, no_input).unwrap_err();
+        assert_eq!(e, "Cannot squash 0002 to 0003: `staging` stopped partway through the range; \
+                       install 0003 on it first.");
+        assert_eq!(history.migrations.len(), 4);
+        // A database at the end of the range is migrated the same way
+        // by the squashed migration.
+        history.squash(1, 2, None, no_input).unwrap();
+        assert_eq!(history.migrations.len(), 3);
+    }
+
+    #[test]
+    fn records_installed_schemas() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &cards()[..2]);
+        history.record_installed("primary", Some(&history.migrations[1].fingerprint)).unwrap();
+        history.record_installed("staging", Some(&history.migrations[0].fingerprint)).unwrap();
+        history.record_installed("primary", None).unwrap();
+        let installed = History::load(dir.path()).unwrap().installed().unwrap();
+        assert_eq!(installed.keys().collect::<Vec<_>>(), vec!["staging"]);
+        assert_eq!(installed["staging"], history.migrations[0].fingerprint);
+    }
+
+    #[test]
+    fn refuses_to_revert_irreversible_steps() {
+        let dir = TempDir::new().unwrap();
+        let history = history(dir.path(), &[
+            card(&["name", "nick"]),
+            card(&["name"])
+        ]);
+        assert!(history.migrations[0].down_script().unwrap().ends_with("DROP TYPE Card;"));
+        let e = history.migrations[1].down_script().unwrap_err();
+        assert!(e.ends_with("cannot be reverted; these steps have no down step:\n    \
+                             step 1: remove property `Card.nick` (destructive)"), "{}", e);
+    }
+}
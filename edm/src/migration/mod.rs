@@ -0,0 +1,4 @@
+mod migration;
+mod lint;
+pub use self::migration::*;
+pub use self::lint::*;